@@ -0,0 +1,480 @@
+use crate::server::models::responses::CallbackAttempt;
+use crate::server::server::TaskStatus;
+use crate::task_store::task_store::TaskRecord;
+
+use chrono::prelude::*;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+
+/// Header with HMAC-SHA256 signature of callback body in format "sha256=<hex>"
+pub const SIGNATURE_HEADER: &str = "x-tasksolver-signature";
+
+/// Header with id of task whose status is posted
+pub const TASK_ID_HEADER: &str = "x-tasksolver-task-id";
+
+/// Default maximum amount of attempts to deliver callback
+pub const DEFAULT_CALLBACK_MAX_ATTEMPTS: u32 = 5;
+
+/// Default delay in milliseconds before second attempt to deliver callback
+pub const DEFAULT_CALLBACK_BACKOFF_MS: u64 = 1000;
+
+/// Time in seconds that callback URL has to reply
+const CALLBACK_TIMEOUT_SECONDS: u64 = 10;
+
+/// Settings of callbacks that post final status of tasks
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallbackConfig {
+    /// URL of tasks that don't set callback_url, callbacks are sent only for tasks with URL if not set
+    pub default_url: Option<String>,
+    /// Key of HMAC signature of callbacks, callbacks are not signed if not set
+    pub secret: Option<String>,
+    /// Maximum amount of attempts to deliver callback, including the first one
+    pub max_attempts: u32,
+    /// Delay before second attempt, doubled for every next attempt
+    pub backoff: Duration,
+    /// Allows callback_url of tasks to point at loopback, private and link-local addresses.
+    /// If not set, submitters can't make server post to internal services,
+    /// default_url is set by operator and is never checked
+    pub allow_private_hosts: bool,
+}
+
+impl Default for CallbackConfig {
+    fn default() -> Self {
+        CallbackConfig {
+            default_url: None,
+            secret: None,
+            max_attempts: DEFAULT_CALLBACK_MAX_ATTEMPTS,
+            backoff: Duration::from_millis(DEFAULT_CALLBACK_BACKOFF_MS),
+            allow_private_hosts: false,
+        }
+    }
+}
+
+impl CallbackConfig {
+    /// Returns delay after given failed attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// Returns HMAC-SHA256 signature of body with given secret in format "sha256=<hex>"
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns true if address isn't reachable from public internet:
+/// loopback, private, shared, link-local, unique local, unspecified, broadcast or multicast
+fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // Shared address space 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_address(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local fc00::/7 and link-local fe80::/10
+                    || ip.segments()[0] & 0xfe00 == 0xfc00
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+/// Returns true if host of URL is written as private address, such hosts aren't resolved
+fn has_private_address_host(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.host_str().and_then(|host| {
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse()
+                    .ok()
+            })
+        })
+        .is_some_and(is_private_address)
+}
+
+/// Resolver of callback hosts set by submitters that drops private addresses,
+/// so host names can't lead to internal services either
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| !is_private_address(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(
+                    format!("callback host {} has no public addresses", name.as_str()).into(),
+                );
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Starts tokio thread that posts final status of every finished task to its
+/// callback URL. Every callback is delivered in its own tokio thread,
+/// so slow callback URLs don't delay workers or other callbacks.
+pub fn start_callbacks(config: CallbackConfig, task_status: TaskStatus) -> JoinHandle<()> {
+    let mut finished_tasks = task_status.subscribe_finished_task_records();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(CALLBACK_TIMEOUT_SECONDS))
+        .build()
+        .unwrap();
+    // Redirects are not followed as they could lead to private addresses
+    let public_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(CALLBACK_TIMEOUT_SECONDS))
+        .dns_resolver(Arc::new(PublicAddressResolver))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    task::spawn(async move {
+        while let Some(record) = finished_tasks.recv().await {
+            task::spawn(deliver_callback(
+                record,
+                client.clone(),
+                public_client.clone(),
+                config.clone(),
+                task_status.clone(),
+            ));
+        }
+    })
+}
+
+/// Posts final status of task to its callback URL, retries with exponential backoff
+/// until URL replies with success status code or attempts are over.
+/// Status is taken when task finished, so callback is posted even if task is
+/// evicted meanwhile. Every attempt is recorded in status of task while it is kept.
+/// URLs set by submitters are posted with public_client unless private hosts are allowed.
+async fn deliver_callback(
+    record: TaskRecord,
+    client: reqwest::Client,
+    public_client: reqwest::Client,
+    config: CallbackConfig,
+    task_status: TaskStatus,
+) {
+    let id = record.id;
+    let record_attempt = |callback_attempt| {
+        let id = id.clone();
+        task_status
+            .blocking(move |task_status| task_status.record_callback_attempt(&id, callback_attempt))
+    };
+    let is_submitted_url = record.request.callback_url.is_some();
+    let Some(url) = record.request.callback_url.or(config.default_url.clone()) else {
+        return;
+    };
+    let client = match is_submitted_url && !config.allow_private_hosts {
+        true => public_client,
+        false => client,
+    };
+    if is_submitted_url && !config.allow_private_hosts && has_private_address_host(&url) {
+        record_attempt(CallbackAttempt {
+            attempt: 1,
+            at: Utc::now().to_string(),
+            delivered: false,
+            status_code: None,
+            error: Some("callback url points at private address".to_string()),
+        })
+        .await;
+        return;
+    }
+
+    let mut status = record.status;
+    status.events.clear();
+    status.callback_attempts.clear();
+    let body = serde_json::to_vec(&status).unwrap();
+    let signature = config.secret.as_ref().map(|secret| sign(secret, &body));
+
+    for attempt in 1..=config.max_attempts {
+        let mut request = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TASK_ID_HEADER, &id)
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let callback_attempt = match request.send().await {
+            Ok(response) => CallbackAttempt {
+                attempt,
+                at: Utc::now().to_string(),
+                delivered: response.status().is_success(),
+                status_code: Some(response.status().as_u16()),
+                error: None,
+            },
+            Err(err) => CallbackAttempt {
+                attempt,
+                at: Utc::now().to_string(),
+                delivered: false,
+                status_code: None,
+                error: Some(err.to_string()),
+            },
+        };
+        let delivered = callback_attempt.delivered;
+        record_attempt(callback_attempt).await;
+
+        if delivered {
+            return;
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(config.backoff(attempt)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_callbacks {
+    use crate::callbacks::callbacks::{
+        sign, start_callbacks, CallbackConfig, SIGNATURE_HEADER, TASK_ID_HEADER,
+    };
+    use crate::retention::retention::RetentionPolicy;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::server::server::TaskStatus;
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use warp::http::{HeaderMap, StatusCode};
+    use warp::Filter;
+
+    /// Callback received by stand-in server
+    struct ReceivedCallback {
+        headers: HeaderMap,
+        body: Vec<u8>,
+    }
+
+    /// Starts HTTP server that fails first given amount of requests with 500
+    /// and records requests that it accepted
+    fn start_stand_in(failures: usize) -> (SocketAddr, Arc<Mutex<Vec<ReceivedCallback>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let receiving = received.clone();
+        let route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers: HeaderMap, body: warp::hyper::body::Bytes| {
+                if requests.fetch_add(1, Ordering::SeqCst) < failures {
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                receiving.lock().unwrap().push(ReceivedCallback {
+                    headers,
+                    body: body.to_vec(),
+                });
+                StatusCode::OK
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (address, received)
+    }
+
+    fn finish_task(task_status: &TaskStatus, callback_url: Option<String>) -> String {
+        let mut request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        request.callback_url = callback_url;
+        let id = task_status
+            .add_new_task(&request, GetStatusResponse::new_utc_status())
+            .unwrap();
+
+        let mut finishing_task_status = task_status.clone();
+        finishing_task_status.finish_running_task(
+            &id,
+            "1\n".to_string(),
+            None,
+            TaskStatusEnum::SUCCESS,
+            BTreeMap::new(),
+            None,
+        );
+
+        id
+    }
+
+    async fn wait_for_attempts(task_status: &TaskStatus, id: &str, attempts: usize) {
+        while task_status
+            .get_status_by_id(id)
+            .unwrap()
+            .callback_attempts
+            .len()
+            < attempts
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_callback_is_retried_and_signed() {
+        let (address, received) = start_stand_in(2);
+        let task_status = TaskStatus::new();
+        let config = CallbackConfig {
+            secret: Some("secret".to_string()),
+            backoff: Duration::from_millis(20),
+            allow_private_hosts: true,
+            ..CallbackConfig::default()
+        };
+        start_callbacks(config, task_status.clone());
+
+        let id = finish_task(&task_status, Some(format!("http://{}/done", address)));
+        wait_for_attempts(&task_status, &id, 3).await;
+
+        let attempts = task_status.get_status_by_id(&id).unwrap().callback_attempts;
+        assert_eq!(attempts.len(), 3);
+        assert!(!attempts[0].delivered);
+        assert_eq!(attempts[1].status_code, Some(500));
+        assert!(attempts[2].delivered);
+        assert_eq!(attempts[2].attempt, 3);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let callback = &received[0];
+        assert_eq!(callback.headers[TASK_ID_HEADER], id.as_str());
+        assert_eq!(
+            callback.headers[SIGNATURE_HEADER],
+            sign("secret", &callback.body).as_str()
+        );
+        let status: GetStatusResponse = serde_json::from_slice(&callback.body).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.result.stdout, "1\n");
+    }
+
+    #[tokio::test]
+    async fn test_default_url_and_failed_delivery() {
+        let (address, received) = start_stand_in(0);
+        let task_status = TaskStatus::new();
+        let config = CallbackConfig {
+            default_url: Some(format!("http://{}/default", address)),
+            max_attempts: 2,
+            backoff: Duration::from_millis(20),
+            allow_private_hosts: true,
+            ..CallbackConfig::default()
+        };
+        start_callbacks(config, task_status.clone());
+
+        let default_id = finish_task(&task_status, None);
+        // Nothing listens on port 9 of localhost, so delivery fails
+        let failing_id = finish_task(&task_status, Some("http://127.0.0.1:9/".to_string()));
+        wait_for_attempts(&task_status, &default_id, 1).await;
+        wait_for_attempts(&task_status, &failing_id, 2).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].headers[TASK_ID_HEADER], default_id.as_str());
+        assert!(!received[0].headers.contains_key(SIGNATURE_HEADER));
+
+        let attempts = task_status
+            .get_status_by_id(&failing_id)
+            .unwrap()
+            .callback_attempts;
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts
+            .iter()
+            .all(|attempt| !attempt.delivered && attempt.error.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_private_hosts_are_blocked_by_default() {
+        let (address, received) = start_stand_in(0);
+        let task_status = TaskStatus::new();
+        let config = CallbackConfig {
+            backoff: Duration::from_millis(20),
+            max_attempts: 2,
+            ..CallbackConfig::default()
+        };
+        start_callbacks(config, task_status.clone());
+
+        let literal_id = finish_task(&task_status, Some(format!("http://{}/", address)));
+        let mapped_id = finish_task(
+            &task_status,
+            Some(format!("http://[::ffff:127.0.0.1]:{}/", address.port())),
+        );
+        let name_id = finish_task(
+            &task_status,
+            Some(format!("http://localhost:{}/", address.port())),
+        );
+        wait_for_attempts(&task_status, &literal_id, 1).await;
+        wait_for_attempts(&task_status, &mapped_id, 1).await;
+        wait_for_attempts(&task_status, &name_id, 2).await;
+
+        for id in [&literal_id, &mapped_id] {
+            let attempts = task_status.get_status_by_id(id).unwrap().callback_attempts;
+            assert_eq!(attempts.len(), 1);
+            assert_eq!(
+                attempts[0].error.as_deref(),
+                Some("callback url points at private address")
+            );
+        }
+        let attempts = task_status
+            .get_status_by_id(&name_id)
+            .unwrap()
+            .callback_attempts;
+        assert!(attempts.iter().all(|attempt| !attempt.delivered));
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sign() {
+        // Example from RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_callback_of_evicted_task_is_delivered() {
+        let (address, received) = start_stand_in(0);
+        let task_status = TaskStatus::new();
+        task_status.set_retention_policy(RetentionPolicy {
+            max_finished: Some(0),
+            ..RetentionPolicy::default()
+        });
+        let config = CallbackConfig {
+            allow_private_hosts: true,
+            ..CallbackConfig::default()
+        };
+        start_callbacks(config, task_status.clone());
+
+        let id = finish_task(&task_status, Some(format!("http://{}/done", address)));
+        assert_eq!(
+            task_status.get_status_by_id(&id).unwrap().status,
+            TaskStatusEnum::EVICTED
+        );
+        while received.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].headers[TASK_ID_HEADER], id.as_str());
+        let status: GetStatusResponse = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.result.stdout, "1\n");
+    }
+}
@@ -0,0 +1,669 @@
+use crate::journal::journal::Journal;
+use crate::outputs::outputs::OutputStore;
+use crate::server::models::requests::validate_workspace_path;
+use crate::server::models::responses::{GetStatusResult, OutputRef, TaskStatusEnum};
+use crate::task_store::task_store::{TaskRecord, TaskStore};
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Component, Path};
+use tokio_util::io::ReaderStream;
+use warp::http::header;
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// Name of file with tasks in exported tarball
+pub const TASKS_FILE: &str = "tasks.ndjson";
+
+/// Directory of outputs stored in output directory in exported tarball
+pub const OUTPUTS_DIR: &str = "outputs";
+
+/// Format of exported tasks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Newline-delimited JSON, one task per line
+    #[default]
+    Ndjson,
+    /// Gzip compressed tarball with tasks in tasks.ndjson and outputs from output directory
+    Tar,
+}
+
+impl ExportFormat {
+    /// Returns MIME type of exported file
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Tar => "application/gzip",
+        }
+    }
+
+    /// Returns name of exported file
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "tasks.ndjson",
+            ExportFormat::Tar => "tasks.tar.gz",
+        }
+    }
+}
+
+/// Error of import of exported tasks
+#[derive(Debug)]
+pub enum ImportError {
+    /// Export can't be read or tasks can't be stored
+    Io(io::Error),
+    /// Line of tasks file is not a task
+    InvalidTask { line: usize, message: String },
+    /// Tarball doesn't contain tasks file before outputs
+    MissingTasks,
+    /// Output in tarball has path outside of outputs directory
+    InvalidOutputPath(String),
+    /// Task store or journal already has tasks
+    NotEmpty,
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "{}", err),
+            ImportError::InvalidTask { line, message } => {
+                write!(f, "invalid task on line {}: {}", line, message)
+            }
+            ImportError::MissingTasks => {
+                write!(f, "tarball doesn't contain {} before outputs", TASKS_FILE)
+            }
+            ImportError::InvalidOutputPath(path) => write!(f, "invalid output path {}", path),
+            ImportError::NotEmpty => write!(f, "tasks can be imported only into empty store"),
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+/// Writes tasks in given format. Tarball also contains outputs of tasks that are
+/// stored in files of output store, newline-delimited JSON contains only their previews.
+pub fn export_tasks(
+    records: &[TaskRecord],
+    format: ExportFormat,
+    output_store: Option<&OutputStore>,
+    writer: impl Write,
+) -> io::Result<()> {
+    let mut exporter = TaskExporter::new(format, output_store, writer)?;
+    for record in records {
+        exporter.write(record)?;
+    }
+
+    exporter.finish()
+}
+
+/// Writes tasks in given format one by one, so that they don't have to be loaded at once.
+/// Tasks of tarball are written to temporary file first, as tar needs size of tasks file
+/// before its content, only references to their outputs are kept in memory.
+pub struct TaskExporter<'a, W: Write> {
+    writer: W,
+    output_store: Option<&'a OutputStore>,
+    tasks_file: Option<BufWriter<File>>,
+    output_files: BTreeMap<String, OutputRef>,
+}
+
+impl<'a, W: Write> TaskExporter<'a, W> {
+    pub fn new(
+        format: ExportFormat,
+        output_store: Option<&'a OutputStore>,
+        writer: W,
+    ) -> io::Result<Self> {
+        let tasks_file = match format {
+            ExportFormat::Ndjson => None,
+            ExportFormat::Tar => Some(BufWriter::new(tempfile::tempfile()?)),
+        };
+
+        Ok(TaskExporter {
+            writer,
+            output_store,
+            tasks_file,
+            output_files: BTreeMap::new(),
+        })
+    }
+
+    /// Writes task
+    pub fn write(&mut self, record: &TaskRecord) -> io::Result<()> {
+        let Some(tasks_file) = &mut self.tasks_file else {
+            return write_ndjson(record, &mut self.writer);
+        };
+
+        write_ndjson(record, tasks_file)?;
+        if self.output_store.is_some() {
+            // Result of retried task can refer to same file as its attempt
+            for output_ref in output_refs(record) {
+                self.output_files
+                    .insert(output_ref.file.clone(), output_ref.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes outputs of tarball and flushes writer
+    pub fn finish(mut self) -> io::Result<()> {
+        let Some(tasks_file) = self.tasks_file else {
+            return self.writer.flush();
+        };
+
+        let mut tasks_file = tasks_file.into_inner().map_err(|err| err.into_error())?;
+        tasks_file.rewind()?;
+        let mut builder = tar::Builder::new(GzEncoder::new(self.writer, Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(tasks_file.metadata()?.len());
+        header.set_mode(0o644);
+        builder.append_data(&mut header, TASKS_FILE, tasks_file)?;
+
+        if let Some(output_store) = self.output_store {
+            for (file_name, output_ref) in &self.output_files {
+                let mut file = match File::open(output_store.path(output_ref)) {
+                    Ok(file) => file,
+                    // File is deleted if task was evicted meanwhile
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+                builder.append_file(Path::new(OUTPUTS_DIR).join(file_name), &mut file)?;
+            }
+        }
+
+        builder.into_inner()?.finish()?.flush()
+    }
+}
+
+fn write_ndjson(record: &TaskRecord, mut writer: impl Write) -> io::Result<()> {
+    serde_json::to_writer(&mut writer, record)?;
+    writer.write_all(b"\n")
+}
+
+/// Returns results of task and of its attempts
+fn results(record: &TaskRecord) -> impl Iterator<Item = &GetStatusResult> {
+    std::iter::once(&record.status.result)
+        .chain(record.status.attempts.iter().map(|attempt| &attempt.result))
+}
+
+/// Returns references to outputs of task and of its attempts that are stored in files
+fn output_refs(record: &TaskRecord) -> impl Iterator<Item = &OutputRef> {
+    results(record).flat_map(|result| result.stdout_ref.iter().chain(result.stderr_ref.iter()))
+}
+
+/// Creates reply that streams file with exported tasks
+pub fn export_reply(format: ExportFormat, file: File) -> Response {
+    let file = tokio::fs::File::from_std(file);
+
+    warp::http::Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        )
+        .body(Body::wrap_stream(ReaderStream::new(file)))
+        .unwrap()
+}
+
+/// Reads tasks exported as newline-delimited JSON or tarball, format is detected
+/// by content. Outputs from tarball are written to output store if it is set,
+/// otherwise they are put back into tasks. Only outputs that tasks refer to are
+/// read, so tasks file must precede them. If import fails, written outputs are deleted.
+pub fn import_tasks(
+    reader: impl Read,
+    output_store: Option<&OutputStore>,
+) -> Result<Vec<TaskRecord>, ImportError> {
+    let mut reader = BufReader::new(reader);
+    // Gzip magic bytes
+    if !reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return read_ndjson(reader);
+    }
+
+    let mut written_ids = BTreeSet::new();
+    let result = read_tarball(reader, output_store, &mut written_ids);
+    if let (Err(_), Some(output_store)) = (&result, output_store) {
+        for id in written_ids {
+            output_store.delete(&id);
+        }
+    }
+
+    result
+}
+
+/// Reads tarball, ids of tasks whose outputs were written to output store are
+/// added to written_ids
+fn read_tarball(
+    reader: impl Read,
+    output_store: Option<&OutputStore>,
+    written_ids: &mut BTreeSet<String>,
+) -> Result<Vec<TaskRecord>, ImportError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    let mut records = None;
+    let mut referenced_files = HashSet::new();
+    let mut outputs = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path == Path::new(TASKS_FILE) {
+            let read_records = read_ndjson(BufReader::new(&mut entry))?;
+            referenced_files = read_records
+                .iter()
+                .flat_map(output_refs)
+                .map(|output_ref| output_ref.file.clone())
+                .collect();
+            records = Some(read_records);
+            continue;
+        }
+
+        let Ok(file) = path.strip_prefix(OUTPUTS_DIR) else {
+            continue;
+        };
+        if records.is_none() {
+            return Err(ImportError::MissingTasks);
+        }
+        let is_inside_outputs = file
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        let file = match file.to_str() {
+            Some(file) if is_inside_outputs => file.to_string(),
+            _ => return Err(ImportError::InvalidOutputPath(path.display().to_string())),
+        };
+        // Files that no task refers to are not imported
+        if !referenced_files.contains(&file) {
+            continue;
+        }
+
+        match output_store {
+            Some(output_store) => {
+                // Referenced files are in directory of their task, see validate_record
+                if let Some((id, _)) = file.split_once('/') {
+                    written_ids.insert(id.to_string());
+                }
+                output_store.write_file(&file, &mut entry)?;
+            }
+            None => {
+                let mut output = String::new();
+                entry.read_to_string(&mut output)?;
+                outputs.insert(file, output);
+            }
+        }
+    }
+
+    let mut records = records.ok_or(ImportError::MissingTasks)?;
+    if output_store.is_none() {
+        for record in &mut records {
+            inline_outputs(record, &outputs);
+        }
+    }
+
+    Ok(records)
+}
+
+fn read_ndjson(reader: impl BufRead) -> Result<Vec<TaskRecord>, ImportError> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = serde_json::from_str(&line)
+            .map_err(|err| err.to_string())
+            .and_then(|record| validate_record(&record).map(|_| record))
+            .map_err(|message| ImportError::InvalidTask {
+                line: index + 1,
+                message,
+            })?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Checks that id of imported task and files of its outputs can't point outside
+/// of output directory, as output store joins them to its directory
+fn validate_record(record: &TaskRecord) -> Result<(), String> {
+    let mut components = Path::new(&record.id).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if name == OsStr::new(&record.id) => {}
+        _ => return Err(format!("invalid task id {}", record.id)),
+    }
+
+    let prefix = format!("{}/", record.id);
+    let results = std::iter::once(&record.status.result)
+        .chain(record.status.attempts.iter().map(|attempt| &attempt.result));
+    for output_ref in results.flat_map(|result| [&result.stdout_ref, &result.stderr_ref]) {
+        let Some(output_ref) = output_ref else {
+            continue;
+        };
+        let is_output_of_task = output_ref
+            .file
+            .strip_prefix(&prefix)
+            .is_some_and(|file| validate_workspace_path(file).is_ok());
+        if !is_output_of_task {
+            return Err(format!("invalid output file {}", output_ref.file));
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts outputs that were stored in files back into results of task and of its attempts
+fn inline_outputs(record: &mut TaskRecord, outputs: &HashMap<String, String>) {
+    let inline_output = |output: &mut String, output_ref: &mut Option<OutputRef>| {
+        if let Some(full_output) = output_ref
+            .as_ref()
+            .and_then(|output_ref| outputs.get(&output_ref.file))
+        {
+            *output = full_output.clone();
+            *output_ref = None;
+        }
+    };
+
+    let results = std::iter::once(&mut record.status.result).chain(
+        record
+            .status
+            .attempts
+            .iter_mut()
+            .map(|attempt| &mut attempt.result),
+    );
+    for result in results {
+        inline_output(&mut result.stdout, &mut result.stdout_ref);
+        if let Some(stderr) = &mut result.stderr {
+            inline_output(stderr, &mut result.stderr_ref);
+        }
+    }
+}
+
+/// Returns tasks of journal if it is set, otherwise tasks of task store,
+/// like server restores them on start. Evicted tasks are skipped.
+pub fn stored_tasks(
+    journal: Option<&Journal>,
+    task_store: &dyn TaskStore,
+) -> io::Result<Vec<TaskRecord>> {
+    let records = match journal {
+        Some(journal) => journal.replay()?,
+        None => task_store.list().map_err(io::Error::other)?,
+    };
+
+    Ok(records
+        .into_iter()
+        .filter(|record| record.status.status != TaskStatusEnum::EVICTED)
+        .collect())
+}
+
+/// Returns error if journal (if it is set) or task store already has tasks.
+/// Import checks it before outputs of imported tasks are written.
+pub fn check_empty(
+    journal: Option<&Journal>,
+    task_store: &dyn TaskStore,
+) -> Result<(), ImportError> {
+    let mut is_empty = true;
+    match journal {
+        Some(journal) => is_empty = journal.replay()?.is_empty(),
+        None => task_store
+            .for_each(&mut |_| is_empty = false)
+            .map_err(io::Error::other)?,
+    }
+
+    match is_empty {
+        true => Ok(()),
+        false => Err(ImportError::NotEmpty),
+    }
+}
+
+/// Stores imported tasks with their ids in journal if it is set, otherwise in task store.
+/// Tasks are imported only into empty journal or task store.
+pub fn store_tasks(
+    records: &[TaskRecord],
+    journal: Option<&Journal>,
+    task_store: &dyn TaskStore,
+) -> Result<(), ImportError> {
+    check_empty(journal, task_store)?;
+
+    for record in records {
+        match journal {
+            Some(journal) => {
+                journal.record_submission(&record.id, &record.request, &record.status)?
+            }
+            None => task_store
+                .create(&record.id, &record.request, &record.status)
+                .map_err(io::Error::other)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes outputs of imported tasks from output store, e.g. when tasks couldn't be stored
+pub fn delete_imported_outputs(records: &[TaskRecord], output_store: &OutputStore) {
+    for record in records {
+        output_store.delete(&record.id);
+    }
+}
+
+#[cfg(test)]
+mod test_export {
+    use crate::export::export::{
+        export_tasks, import_tasks, store_tasks, ExportFormat, ImportError,
+    };
+    use crate::outputs::outputs::OutputStore;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::task_store::task_store::{MemoryTaskStore, TaskRecord, TaskStore};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs;
+
+    /// Creates gzip compressed tarball with given files in given order
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, *content).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Creates finished task with given stdout, stored in output store if it is large
+    fn finished_task(id: &str, stdout: &str, output_store: &OutputStore) -> TaskRecord {
+        let mut status = GetStatusResponse::new_utc_status();
+        status.status = TaskStatusEnum::SUCCESS;
+        status.result.stdout = stdout.to_string();
+        output_store.spill(id, None, &mut status.result);
+
+        TaskRecord {
+            id: id.to_string(),
+            request: CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string()),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_ndjson_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 100, 10).unwrap();
+        let records = vec![
+            finished_task("first", "1\n", &output_store),
+            finished_task("second", "2\n", &output_store),
+        ];
+
+        let mut export = Vec::new();
+        export_tasks(&records, ExportFormat::Ndjson, None, &mut export).unwrap();
+        assert_eq!(String::from_utf8_lossy(&export).lines().count(), 2);
+
+        let imported = import_tasks(export.as_slice(), None).unwrap();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[1].id, "second");
+        assert_eq!(imported[1].status.result.stdout, "2\n");
+        assert_eq!(
+            imported[1].status.meta.created_at,
+            records[1].status.meta.created_at
+        );
+    }
+
+    #[test]
+    fn test_tarball_contains_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 100, 10).unwrap();
+        let large_output = "0123456789".repeat(50);
+        let records = vec![
+            finished_task("large", &large_output, &output_store),
+            finished_task("small", "small", &output_store),
+        ];
+
+        let mut export = Vec::new();
+        export_tasks(
+            &records,
+            ExportFormat::Tar,
+            Some(&output_store),
+            &mut export,
+        )
+        .unwrap();
+
+        // Without output store full output is put back into task
+        let imported = import_tasks(export.as_slice(), None).unwrap();
+        assert_eq!(imported[0].status.result.stdout, large_output);
+        assert!(imported[0].status.result.stdout_ref.is_none());
+        assert_eq!(imported[1].status.result.stdout, "small");
+
+        let import_dir = tempfile::tempdir().unwrap();
+        let import_output_store = OutputStore::open(import_dir.path(), 100, 10).unwrap();
+        let imported = import_tasks(export.as_slice(), Some(&import_output_store)).unwrap();
+        let stdout_ref = imported[0].status.result.stdout_ref.as_ref().unwrap();
+        assert_eq!(imported[0].status.result.stdout, "0123456789");
+        assert_eq!(
+            fs::read_to_string(import_output_store.path(stdout_ref)).unwrap(),
+            large_output
+        );
+    }
+
+    #[test]
+    fn test_import_rejects_paths_outside_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 1, 1).unwrap();
+
+        for id in ["..", "/", "a/b", "a/", ""] {
+            let mut record = finished_task("task", "", &output_store);
+            record.id = id.to_string();
+            let mut export = Vec::new();
+            export_tasks(&[record], ExportFormat::Ndjson, None, &mut export).unwrap();
+
+            let result = import_tasks(export.as_slice(), None);
+            assert!(
+                matches!(result, Err(ImportError::InvalidTask { line: 1, .. })),
+                "id {:?} was imported",
+                id
+            );
+        }
+
+        for file in ["/etc/passwd", "task/../../secret", "other/stdout", "task/"] {
+            let mut record = finished_task("task", "output", &output_store);
+            record.status.result.stdout_ref.as_mut().unwrap().file = file.to_string();
+            let mut export = Vec::new();
+            export_tasks(&[record], ExportFormat::Ndjson, None, &mut export).unwrap();
+
+            let result = import_tasks(export.as_slice(), None);
+            assert!(
+                matches!(result, Err(ImportError::InvalidTask { line: 1, .. })),
+                "output file {} was imported",
+                file
+            );
+        }
+
+        let record = finished_task("task", "output", &output_store);
+        let mut export = Vec::new();
+        export_tasks(&[record], ExportFormat::Ndjson, None, &mut export).unwrap();
+        assert!(import_tasks(export.as_slice(), None).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_import() {
+        let result = import_tasks("{\"id\": \"task\"}\n".as_bytes(), None);
+        assert!(matches!(
+            result,
+            Err(ImportError::InvalidTask { line: 1, .. })
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 100, 10).unwrap();
+        let records = vec![finished_task("task", "", &output_store)];
+        let task_store = MemoryTaskStore::new();
+        store_tasks(&records, None, &task_store).unwrap();
+        assert_eq!(
+            task_store.get("task").unwrap().unwrap().status,
+            TaskStatusEnum::SUCCESS
+        );
+
+        let result = store_tasks(&records, None, &task_store);
+        assert!(matches!(result, Err(ImportError::NotEmpty)));
+    }
+
+    #[test]
+    fn test_tarball_import_writes_only_referenced_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 5, 1).unwrap();
+        let record = finished_task("task", "output", &output_store);
+        let mut tasks = Vec::new();
+        export_tasks(&[record], ExportFormat::Ndjson, None, &mut tasks).unwrap();
+
+        let import_dir = tempfile::tempdir().unwrap();
+        let import_output_store = OutputStore::open(import_dir.path(), 5, 1).unwrap();
+        let export = tarball(&[
+            ("tasks.ndjson", &tasks),
+            ("outputs/task/stdout", b"output"),
+            ("outputs/other/stdout", b"other"),
+        ]);
+        import_tasks(export.as_slice(), Some(&import_output_store)).unwrap();
+        assert!(import_dir.path().join("task/stdout").exists());
+        assert!(!import_dir.path().join("other").exists());
+
+        // Outputs are read only after tasks that refer to them
+        let export = tarball(&[("outputs/task/stdout", b"output"), ("tasks.ndjson", &tasks)]);
+        let result = import_tasks(export.as_slice(), None);
+        assert!(matches!(result, Err(ImportError::MissingTasks)));
+    }
+
+    #[test]
+    fn test_failed_tarball_import_deletes_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 5, 1).unwrap();
+        let mut record = finished_task("task", "output", &output_store);
+        record.status.result.stderr = Some("errors".to_string());
+        output_store.spill("task", None, &mut record.status.result);
+        let mut tasks = Vec::new();
+        export_tasks(&[record], ExportFormat::Ndjson, None, &mut tasks).unwrap();
+
+        // Output that doesn't compress well, so that export is cut in its middle
+        let mut seed = 1u64;
+        let large_output: String = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                char::from(b'a' + (seed >> 59) as u8)
+            })
+            .collect();
+        let export = tarball(&[
+            ("tasks.ndjson", &tasks),
+            ("outputs/task/stdout", b"output"),
+            ("outputs/task/stderr", large_output.as_bytes()),
+        ]);
+
+        let import_dir = tempfile::tempdir().unwrap();
+        let import_output_store = OutputStore::open(import_dir.path(), 5, 1).unwrap();
+        let cut_export = &export[..export.len() * 3 / 4];
+        assert!(import_tasks(cut_export, Some(&import_output_store)).is_err());
+        assert!(!import_dir.path().join("task").exists());
+    }
+}
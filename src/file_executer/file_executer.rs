@@ -1,30 +1,170 @@
-use crate::server::models::requests::{CreateTaskRequest, TaskType};
+use crate::server::models::requests::{CreateTaskRequest, FailureKind, TaskType};
 use crate::server::models::responses::TaskStatusEnum;
 use base64::prelude::*;
-use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::Stdio;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
+use tokio::task;
 
-/// Creates temporary .bin file with permissions to open, write and execute it for everyone
-fn create_temporary_binary_file(decoded_file: &Vec<u8>, id: &str) -> (String, String) {
-    let path = format!("{}.bin", id);
-    let mut temporary_file = File::create(path.clone()).unwrap();
-    let _ = temporary_file.write_all(decoded_file.as_slice());
+/// Creates temporary .bin file in workspace with permissions to open, write and execute it for everyone.
+/// Returns path to execute it from workspace.
+fn create_temporary_binary_file(decoded_file: &[u8], id: &str, workspace: &Path) -> String {
+    let path = workspace.join(format!("{}.bin", id));
+    let mut temporary_file = File::create(path).unwrap();
+    let _ = temporary_file.write_all(decoded_file);
 
     let mut permissions = temporary_file.metadata().unwrap().permissions();
     permissions.set_mode(0o777);
     let _ = temporary_file.set_permissions(permissions);
 
-    let execute_path = format!("./{}.bin", id);
+    format!("./{}.bin", id)
+}
+
+/// Creates temporary working directory of task with its input files.
+/// Directory is removed when returned value is dropped, even if execution was interrupted.
+pub fn create_workspace(task: &CreateTaskRequest) -> io::Result<TempDir> {
+    let workspace = tempfile::tempdir()?;
+
+    for (path, base64_encoded_content) in &task.files {
+        let path = workspace.path().join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = BASE64_STANDARD
+            .decode(base64_encoded_content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, content)?;
+    }
+
+    Ok(workspace)
+}
+
+/// Reads artifacts of task from its workspace and returns them base64 encoded.
+/// Artifacts that task didn't create are omitted.
+pub fn collect_artifacts(task: &CreateTaskRequest, workspace: &Path) -> BTreeMap<String, String> {
+    task.artifacts
+        .iter()
+        .filter_map(|path| {
+            let content = fs::read(workspace.join(path)).ok()?;
+            Some((path.clone(), BASE64_STANDARD.encode(content)))
+        })
+        .collect()
+}
+
+/// Limits of child process
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessLimits {
+    /// Limit of address space in megabytes
+    pub memory_limit_mb: Option<u64>,
+    /// CPU cores that process and its children are pinned to, empty means no pinning
+    pub cpus: Vec<usize>,
+}
+
+/// Returns CPU cores that server process is allowed to run on
+pub fn available_cpus() -> io::Result<Vec<usize>> {
+    // SAFETY: cpu_set_t is plain data and is fully initialized by sched_getaffinity
+    unsafe {
+        let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &cpu_set))
+            .collect())
+    }
+}
+
+/// Creates command that runs in workspace, pipes stdout and stderr and kills
+/// the child process if its future is dropped (e.g. on server shutdown or timeout).
+/// Address space of child process is limited by memory limit and child process
+/// is pinned to given CPU cores (children it spawns inherit affinity).
+fn create_command(program: &str, workspace: &Path, limits: &ProcessLimits) -> Command {
+    let mut command = Command::new(program);
+    command
+        .current_dir(workspace)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    if let Some(memory_limit_mb) = limits.memory_limit_mb {
+        let memory_limit = memory_limit_mb.saturating_mul(1024 * 1024) as libc::rlim_t;
+        // SAFETY: setrlimit is async-signal-safe and closure doesn't allocate
+        unsafe {
+            command.pre_exec(move || {
+                let limit = libc::rlimit {
+                    rlim_cur: memory_limit,
+                    rlim_max: memory_limit,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    if !limits.cpus.is_empty() {
+        // SAFETY: cpu_set_t is plain data, it is filled before fork,
+        // sched_setaffinity is async-signal-safe and closure doesn't allocate
+        unsafe {
+            let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+            for &cpu in &limits.cpus {
+                libc::CPU_SET(cpu, &mut cpu_set);
+            }
+            command.pre_exec(move || {
+                if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
 
-    (path, execute_path)
+    command
 }
 
-/// Execute base64 encoded binary file (by creating temporary file with name of id)
-/// and returns output
+/// Spawns command, calls on_spawn with pid of spawned process,
+/// writes given stdin to it and waits for output
+async fn run_command(
+    mut command: Command,
+    stdin: Option<String>,
+    on_spawn: &(dyn Fn(u32) + Sync),
+) -> io::Result<std::process::Output> {
+    command.stdin(match stdin {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    });
+
+    let mut child = command.spawn()?;
+    if let Some(pid) = child.id() {
+        on_spawn(pid);
+    }
+    if let (Some(stdin), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+        // Writing in separate thread, so child that doesn't read stdin can't block us
+        task::spawn(async move {
+            let _ = child_stdin.write_all(stdin.as_bytes()).await;
+        });
+    }
+
+    child.wait_with_output().await
+}
+
+/// Error code of exec for files with unknown format
+const ENOEXEC: i32 = 8;
+
+/// Execute base64 encoded binary file (by creating temporary file with name of id
+/// in workspace) and returns output. Files that exec can't run (e.g. shell scripts
+/// without a shebang) are run with bash.
 ///
 /// # Examples
 ///
@@ -34,8 +174,11 @@ fn create_temporary_binary_file(decoded_file: &Vec<u8>, id: &str) -> (String, St
 /// let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475144";
 /// let base64_encoded_file = "ZWNobyBIZWxsbywgd29ybGQh"; // -> echo Hello, world!
 /// let arguments = "Hello, world!".to_string();
+/// let workspace = tempfile::tempdir().unwrap();
+///
+/// let limits = ProcessLimits::default();
 ///
-/// let output = binary_execute(id, base64_encoded_file, arguments).await;
+/// let output = binary_execute(id, base64_encoded_file, arguments, None, workspace.path(), &limits, &|_| {}).await;
 ///
 /// assert_eq!(output.stdout, "Hello, world!\n");
 /// assert_eq!(output.stderr, None);
@@ -45,24 +188,31 @@ pub async fn binary_execute(
     id: String,
     base64_encoded_file: String,
     arguments: String,
+    stdin: Option<String>,
+    workspace: &Path,
+    limits: &ProcessLimits,
+    on_spawn: &(dyn Fn(u32) + Sync),
 ) -> std::process::Output {
     let decoded_file = BASE64_STANDARD.decode(base64_encoded_file).unwrap();
-    let (temporary_file_path, execute_path) = create_temporary_binary_file(&decoded_file, &id);
-
-    let output = Command::new(execute_path)
-        .arg(arguments)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await
-        .unwrap();
-
-    let _ = fs::remove_file(temporary_file_path);
-
-    output
+    let execute_path = create_temporary_binary_file(&decoded_file, &id, workspace);
+
+    let mut command = create_command(&execute_path, workspace, limits);
+    command.arg(&arguments);
+    let output = run_command(command, stdin.clone(), on_spawn).await;
+
+    // Files without a shebang can't be executed directly,
+    // so run them as shell scripts like execvp does
+    match output {
+        Err(err) if err.raw_os_error() == Some(ENOEXEC) => {
+            let mut command = create_command("bash", workspace, limits);
+            command.arg(&execute_path).arg(arguments);
+            run_command(command, stdin, on_spawn).await.unwrap()
+        }
+        output => output.unwrap(),
+    }
 }
 
-/// Execute python script and returns output
+/// Execute python script in workspace and returns output
 ///
 /// # Examples
 ///
@@ -71,24 +221,152 @@ pub async fn binary_execute(
 ///
 /// let python_code = "print(Hello, world!)";
 /// let arguments = "".to_string();
+/// let workspace = tempfile::tempdir().unwrap();
 ///
-/// let output = python_execute(python_code, arguments).await;
+/// let limits = ProcessLimits::default();
+///
+/// let output = python_execute(python_code, arguments, None, workspace.path(), &limits, &|_| {}).await;
 ///
 /// assert_eq!(output.stdout, "Hello, world!");
 /// assert_eq!(output.stderr, None);
 /// assert_eq!(output.status.success(), true);
-pub async fn python_execute(python_code: String, arguments: String) -> std::process::Output {
-    let output = Command::new("python3")
-        .arg("-c")
-        .arg(python_code)
-        .arg(arguments)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await
-        .unwrap();
+pub async fn python_execute(
+    python_code: String,
+    arguments: String,
+    stdin: Option<String>,
+    workspace: &Path,
+    limits: &ProcessLimits,
+    on_spawn: &(dyn Fn(u32) + Sync),
+) -> std::process::Output {
+    let mut command = create_command("python3", workspace, limits);
+    command.arg("-c").arg(python_code).arg(arguments);
+
+    run_command(command, stdin, on_spawn).await.unwrap()
+}
+
+/// Result of task execution
+pub struct ExecutionResult {
+    pub stdout: String,
+    /// Stderr, if task failed or wrote to it. Stderr of successful task is kept,
+    /// so that workflow can pass it to dependent tasks.
+    pub stderr: Option<String>,
+    /// SUCCESS or ERROR
+    pub status: TaskStatusEnum,
+    /// Base64 encoded artifacts by their paths
+    pub artifacts: BTreeMap<String, String>,
+    /// Why task failed, None if it succeeded
+    pub failure: Option<FailureKind>,
+}
+
+impl ExecutionResult {
+    /// Creates result of task that failed before or instead of finishing its process
+    fn failed(stderr: String, failure: FailureKind) -> ExecutionResult {
+        ExecutionResult {
+            stdout: String::new(),
+            stderr: Some(stderr),
+            status: TaskStatusEnum::ERROR,
+            artifacts: BTreeMap::new(),
+            failure: Some(failure),
+        }
+    }
+}
+
+/// Execute python script or binary file in temporary workspace and returns its result.
+/// Task that exceeds its timeout is killed and finishes with error.
+pub async fn execute_task(task: CreateTaskRequest, id: String) -> ExecutionResult {
+    execute_task_on_cpus(task, id, &[], |_| {}).await
+}
+
+/// Execute task like execute_task, but pins its process to given CPU cores
+/// and calls on_spawn with pid of process when it is spawned
+pub async fn execute_task_on_cpus(
+    task: CreateTaskRequest,
+    id: String,
+    cpus: &[usize],
+    on_spawn: impl Fn(u32) + Send + Sync,
+) -> ExecutionResult {
+    let workspace = match create_workspace(&task) {
+        Ok(workspace) => workspace,
+        Err(err) => {
+            return ExecutionResult::failed(
+                format!("failed to create workspace: {}", err),
+                FailureKind::Error,
+            );
+        }
+    };
+    let artifacts_task = task.clone();
+
+    let task_type = task.task_type;
+    let code = task.file;
+    let arguments = task.args;
+    let stdin = task.stdin;
+    let limits = ProcessLimits {
+        memory_limit_mb: task.memory_limit_mb,
+        cpus: cpus.to_vec(),
+    };
 
-    output
+    let execution = async {
+        match task_type {
+            TaskType::Python => {
+                python_execute(code, arguments, stdin, workspace.path(), &limits, &on_spawn).await
+            }
+            TaskType::Bin => {
+                binary_execute(
+                    id,
+                    code,
+                    arguments,
+                    stdin,
+                    workspace.path(),
+                    &limits,
+                    &on_spawn,
+                )
+                .await
+            }
+        }
+    };
+
+    let output = match task.timeout_ms {
+        Some(timeout_ms) => {
+            match tokio::time::timeout(Duration::from_millis(timeout_ms), execution).await {
+                Ok(output) => output,
+                Err(_) => {
+                    return ExecutionResult::failed(
+                        format!("task exceeded timeout of {} ms", timeout_ms),
+                        FailureKind::Timeout,
+                    );
+                }
+            }
+        }
+        None => execution.await,
+    };
+
+    // Invalid UTF-8 written by task is replaced, so it can't fail the worker
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let artifacts = collect_artifacts(&artifacts_task, workspace.path());
+
+    if !output.status.success() {
+        let failure = match output.status.signal() {
+            Some(_) => FailureKind::Signal,
+            None => FailureKind::Error,
+        };
+
+        return ExecutionResult {
+            stdout,
+            stderr: Some(stderr),
+            status: TaskStatusEnum::ERROR,
+            artifacts,
+            failure: Some(failure),
+        };
+    }
+
+    ExecutionResult {
+        stdout,
+        stderr: (!stderr.is_empty()).then_some(stderr),
+        status: TaskStatusEnum::SUCCESS,
+        artifacts,
+        failure: None,
+    }
 }
 
 /// Execute python script or binary file and returns stdout, stderr and task status
@@ -113,23 +391,13 @@ pub async fn execute_file(
     task: CreateTaskRequest,
     id: String,
 ) -> (String, Option<String>, TaskStatusEnum) {
-    let task_type = task.task_type;
-    let code = task.file;
-    let arguments = task.args;
-
-    let output = match task_type {
-        TaskType::Python => python_execute(code, arguments).await,
-        TaskType::Bin => binary_execute(id, code, arguments).await,
-    };
-
-    let stdout = String::from_utf8(output.stdout).unwrap();
-    let stderr = String::from_utf8(output.stderr).unwrap();
+    let execution_result = execute_task(task, id).await;
 
-    if !output.status.success() {
-        return (stdout, Some(stderr), TaskStatusEnum::ERROR);
-    }
-
-    (stdout, None, TaskStatusEnum::SUCCESS)
+    (
+        execution_result.stdout,
+        execution_result.stderr,
+        execution_result.status,
+    )
 }
 
 #[cfg(test)]
@@ -250,3 +518,183 @@ print(sys.argv[1])"
         assert_eq!(task_status, TaskStatusEnum::SUCCESS);
     }
 }
+
+#[cfg(test)]
+mod test_workspace {
+    use crate::file_executer::file_executer::execute_task;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use base64::prelude::*;
+
+    #[tokio::test]
+    async fn test_stdin() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475147".to_string();
+        let base64_encoded_file = BASE64_STANDARD.encode("read line; echo \"got $line\"");
+        let arguments = "".to_string();
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Bin, base64_encoded_file, arguments);
+        create_task_request.stdin = Some("Hello\n".to_string());
+        let execution_result = execute_task(create_task_request, id).await;
+        assert_eq!(execution_result.stdout, "got Hello\n");
+        assert_eq!(execution_result.stderr, None);
+        assert_eq!(execution_result.status, TaskStatusEnum::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn test_files_and_artifacts() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475148".to_string();
+        let python_code = "import sys
+data = open('input/data.txt').read()
+open('result.txt', 'w').write(data.upper())
+print('warning', file=sys.stderr)"
+            .to_string();
+        let arguments = "".to_string();
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.files.insert(
+            "input/data.txt".to_string(),
+            BASE64_STANDARD.encode("hello"),
+        );
+        create_task_request.artifacts = vec!["result.txt".to_string(), "missing.txt".to_string()];
+        let execution_result = execute_task(create_task_request, id).await;
+        assert_eq!(execution_result.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(execution_result.stderr, Some("warning\n".to_string()));
+        assert_eq!(execution_result.artifacts.len(), 1);
+        assert_eq!(
+            execution_result.artifacts["result.txt"],
+            BASE64_STANDARD.encode("HELLO")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workspace_error_fails_task() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475149".to_string();
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        create_task_request
+            .files
+            .insert("data.txt".to_string(), "not base64!".to_string());
+
+        let execution_result = execute_task(create_task_request, id).await;
+        assert_eq!(execution_result.status, TaskStatusEnum::ERROR);
+        assert!(execution_result
+            .stderr
+            .unwrap()
+            .starts_with("failed to create workspace"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_output() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475150".to_string();
+        let python_code = "import sys; sys.stdout.buffer.write(b'ok\\xff')".to_string();
+
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, "".to_string());
+        let execution_result = execute_task(create_task_request, id).await;
+        assert_eq!(execution_result.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(execution_result.stdout, "ok\u{fffd}");
+    }
+
+    #[test]
+    fn test_validate_paths() {
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string());
+        create_task_request.artifacts = vec!["out/result.txt".to_string()];
+        assert!(create_task_request.validate().is_ok());
+
+        create_task_request.artifacts = vec!["../result.txt".to_string()];
+        assert!(create_task_request.validate().is_err());
+
+        create_task_request.artifacts = vec!["/etc/passwd".to_string()];
+        assert!(create_task_request.validate().is_err());
+
+        create_task_request.artifacts = vec![];
+        create_task_request
+            .files
+            .insert("data.txt".to_string(), "not base64!".to_string());
+        assert!(create_task_request.validate().is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_limits {
+    use crate::file_executer::file_executer::{
+        available_cpus, execute_file, execute_task, execute_task_on_cpus,
+    };
+    use crate::server::models::requests::{CreateTaskRequest, FailureKind, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475149".to_string();
+        let python_code = "import time; time.sleep(10)".to_string();
+        let arguments = "".to_string();
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.timeout_ms = Some(200);
+        let (_, stderr, task_status) = execute_file(create_task_request, id).await;
+        assert_eq!(stderr, Some("task exceeded timeout of 200 ms".to_string()));
+        assert_eq!(task_status, TaskStatusEnum::ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475150".to_string();
+        let python_code = "data = bytearray(512 * 1024 * 1024)".to_string();
+        let arguments = "".to_string();
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.memory_limit_mb = Some(128);
+        let (_, stderr, task_status) = execute_file(create_task_request, id).await;
+        assert!(stderr.unwrap().contains("MemoryError"));
+        assert_eq!(task_status, TaskStatusEnum::ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_cpu_affinity() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475152".to_string();
+        let cpu = *available_cpus().unwrap().last().unwrap();
+        let python_code =
+            "import os, subprocess; print(sorted(os.sched_getaffinity(0))); subprocess.run(['nproc'])"
+                .to_string();
+
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, "".to_string());
+        let execution_result = execute_task_on_cpus(create_task_request, id, &[cpu], |_| {}).await;
+        assert_eq!(execution_result.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(execution_result.stdout, format!("[{}]\n1\n", cpu));
+    }
+
+    #[tokio::test]
+    async fn test_failure_kind() {
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475151".to_string();
+        let python_code = "import os, signal; os.kill(os.getpid(), signal.SIGKILL)".to_string();
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, "".to_string());
+        let execution_result = execute_task(create_task_request, id.clone()).await;
+        assert_eq!(execution_result.status, TaskStatusEnum::ERROR);
+        assert_eq!(execution_result.failure, Some(FailureKind::Signal));
+
+        let python_code = "exit(3)".to_string();
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, "".to_string());
+        let execution_result = execute_task(create_task_request, id.clone()).await;
+        assert_eq!(execution_result.failure, Some(FailureKind::Error));
+
+        let python_code = "import time; time.sleep(10)".to_string();
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, "".to_string());
+        create_task_request.timeout_ms = Some(100);
+        let execution_result = execute_task(create_task_request, id.clone()).await;
+        assert_eq!(execution_result.failure, Some(FailureKind::Timeout));
+
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let execution_result = execute_task(create_task_request, id).await;
+        assert_eq!(execution_result.failure, None);
+    }
+}
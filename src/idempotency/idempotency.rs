@@ -0,0 +1,312 @@
+use crate::server::models::requests::CreateTaskRequest;
+use crate::server::server::TaskStatus;
+use crate::task_store::task_store::{IdempotencyKeyRecord, TaskStoreError};
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// Default time in seconds that idempotency key is remembered
+pub const DEFAULT_IDEMPOTENCY_WINDOW_SECONDS: u64 = 24 * 60 * 60;
+
+/// Result of looking up idempotency key
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyLookup {
+    /// Key wasn't used in window, task should be created
+    New,
+    /// Key was used with same request, contains id of created task
+    Existing(String),
+    /// Key was used with different request
+    Conflict,
+}
+
+/// Lock of creation of task with one key and amount of requests that hold or wait for it
+struct CreationLock {
+    lock: Arc<AsyncMutex<()>>,
+    users: usize,
+}
+
+/// Held while task with idempotency key is created. Lock of key
+/// is removed when last request that holds or waits for it is done.
+pub struct CreationGuard<'a> {
+    idempotency_keys: &'a IdempotencyKeys,
+    entry_key: (String, String),
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for CreationGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        let mut creation_locks = self.idempotency_keys.creation_locks.lock().unwrap();
+        if let Some(creation_lock) = creation_locks.get_mut(&self.entry_key) {
+            creation_lock.users -= 1;
+            if creation_lock.users == 0 {
+                creation_locks.remove(&self.entry_key);
+            }
+        }
+    }
+}
+
+/// Idempotency keys of clients mapped to the first task created with them.
+/// Keys are kept in task store (and journal), so they survive restart of server.
+pub struct IdempotencyKeys {
+    /// Time that key is remembered after first use
+    window: Mutex<Duration>,
+    /// Locks by client id and key, so that concurrent requests with same key
+    /// create only one task, while requests with other keys don't wait
+    creation_locks: Mutex<HashMap<(String, String), CreationLock>>,
+}
+
+impl IdempotencyKeys {
+    /// Creates empty idempotency keys storage with default window
+    pub fn new() -> IdempotencyKeys {
+        IdempotencyKeys {
+            window: Mutex::new(Duration::from_secs(DEFAULT_IDEMPOTENCY_WINDOW_SECONDS)),
+            creation_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until no other task with same key of request's client is being created
+    pub async fn lock_creation(&self, key: &str, request: &CreateTaskRequest) -> CreationGuard<'_> {
+        let entry_key = entry_key(key, request);
+        let lock = {
+            let mut creation_locks = self.creation_locks.lock().unwrap();
+            let creation_lock =
+                creation_locks
+                    .entry(entry_key.clone())
+                    .or_insert_with(|| CreationLock {
+                        lock: Arc::new(AsyncMutex::new(())),
+                        users: 0,
+                    });
+            creation_lock.users += 1;
+            creation_lock.lock.clone()
+        };
+
+        // Guard is created before waiting, so lock is released even if waiting is cancelled
+        let mut creation_guard = CreationGuard {
+            idempotency_keys: self,
+            entry_key,
+            guard: None,
+        };
+        creation_guard.guard = Some(lock.lock_owned().await);
+
+        creation_guard
+    }
+
+    /// Sets time that key is remembered after first use
+    pub fn set_window(&self, window: Duration) {
+        *self.window.lock().unwrap() = window;
+    }
+
+    /// Returns time that key is remembered after first use
+    pub fn window(&self) -> Duration {
+        *self.window.lock().unwrap()
+    }
+
+    /// Returns id of task that was created with key of request's client,
+    /// or conflict if key was used with different request.
+    /// Keys that are out of window but weren't purged yet are not used.
+    pub fn lookup(
+        &self,
+        task_status: &TaskStatus,
+        key: &str,
+        request: &CreateTaskRequest,
+    ) -> Result<IdempotencyLookup, TaskStoreError> {
+        let (client_id, key) = entry_key(key, request);
+        let record = task_status
+            .get_idempotency_key(&client_id, &key)?
+            .filter(|record| record.created_at > self.window_start());
+
+        Ok(match record {
+            None => IdempotencyLookup::New,
+            Some(record) if record.fingerprint == fingerprint(request) => {
+                IdempotencyLookup::Existing(record.task_id)
+            }
+            Some(_) => IdempotencyLookup::Conflict,
+        })
+    }
+
+    /// Remembers task created with key of request's client
+    pub fn insert(
+        &self,
+        task_status: &TaskStatus,
+        key: &str,
+        request: &CreateTaskRequest,
+        task_id: &str,
+    ) -> Result<(), TaskStoreError> {
+        let (client_id, key) = entry_key(key, request);
+        task_status.put_idempotency_key(&IdempotencyKeyRecord {
+            client_id,
+            key,
+            fingerprint: fingerprint(request),
+            task_id: task_id.to_string(),
+            created_at: Utc::now().timestamp_millis(),
+        })
+    }
+
+    /// Forgets keys that are out of window. Store deletes them by index
+    /// of time of first use, so remembered keys are not scanned.
+    pub fn purge_expired(&self, task_status: &TaskStatus) {
+        task_status.purge_idempotency_keys(self.window_start() + 1);
+    }
+
+    /// Unix time in milliseconds, keys first used at or before it are out of window
+    fn window_start(&self) -> i64 {
+        let window = i64::try_from(self.window().as_millis()).unwrap_or(i64::MAX);
+        Utc::now().timestamp_millis().saturating_sub(window)
+    }
+}
+
+impl Default for IdempotencyKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keys are separate for every client
+fn entry_key(key: &str, request: &CreateTaskRequest) -> (String, String) {
+    (
+        request.client_id.clone().unwrap_or_default(),
+        key.to_string(),
+    )
+}
+
+/// Hash of request payload, key itself is not part of payload.
+/// Hash is stable between versions of server, since it is stored.
+fn fingerprint(request: &CreateTaskRequest) -> String {
+    let mut request = request.clone();
+    request.idempotency_key = None;
+
+    hex::encode(Sha256::digest(serde_json::to_string(&request).unwrap()))
+}
+
+#[cfg(test)]
+mod test_idempotency {
+    use crate::idempotency::idempotency::{IdempotencyKeys, IdempotencyLookup};
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::server::TaskStatus;
+    use crate::task_store::sqlite_task_store::SqliteTaskStore;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_lookup() {
+        let idempotency_keys = IdempotencyKeys::new();
+        let task_status = TaskStatus::new();
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        assert_eq!(
+            idempotency_keys
+                .lookup(&task_status, "key", &request)
+                .unwrap(),
+            IdempotencyLookup::New
+        );
+
+        idempotency_keys
+            .insert(&task_status, "key", &request, "task")
+            .unwrap();
+        assert_eq!(
+            idempotency_keys
+                .lookup(&task_status, "key", &request)
+                .unwrap(),
+            IdempotencyLookup::Existing("task".to_string())
+        );
+
+        let mut other_request = request.clone();
+        other_request.args = "1".to_string();
+        assert_eq!(
+            idempotency_keys
+                .lookup(&task_status, "key", &other_request)
+                .unwrap(),
+            IdempotencyLookup::Conflict
+        );
+
+        let mut other_client_request = request.clone();
+        other_client_request.client_id = Some("other".to_string());
+        assert_eq!(
+            idempotency_keys
+                .lookup(&task_status, "key", &other_client_request)
+                .unwrap(),
+            IdempotencyLookup::New
+        );
+    }
+
+    #[tokio::test]
+    async fn test_creation_is_locked_per_key() {
+        let idempotency_keys = IdempotencyKeys::new();
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+
+        let guard = idempotency_keys.lock_creation("key", &request).await;
+        let other_key = timeout(
+            Duration::from_millis(100),
+            idempotency_keys.lock_creation("other", &request),
+        )
+        .await;
+        assert!(other_key.is_ok());
+        drop(other_key);
+
+        let same_key = timeout(
+            Duration::from_millis(100),
+            idempotency_keys.lock_creation("key", &request),
+        )
+        .await;
+        assert!(same_key.is_err());
+
+        drop(guard);
+        assert!(idempotency_keys.creation_locks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_window() {
+        let idempotency_keys = IdempotencyKeys::new();
+        idempotency_keys.set_window(Duration::ZERO);
+        let task_status = TaskStatus::new();
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+
+        idempotency_keys
+            .insert(&task_status, "key", &request, "task")
+            .unwrap();
+        assert_eq!(
+            idempotency_keys
+                .lookup(&task_status, "key", &request)
+                .unwrap(),
+            IdempotencyLookup::New
+        );
+
+        std::thread::sleep(Duration::from_millis(5));
+        idempotency_keys.purge_expired(&task_status);
+        assert!(task_status
+            .get_idempotency_key("", "key")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_keys_outlive_server() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("tasks.sqlite");
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+
+        let task_status = TaskStatus::with_store(Arc::new(SqliteTaskStore::open(&path).unwrap()));
+        IdempotencyKeys::new()
+            .insert(&task_status, "key", &request, "task")
+            .unwrap();
+        drop(task_status);
+
+        let task_status = TaskStatus::with_store(Arc::new(SqliteTaskStore::open(&path).unwrap()));
+        assert_eq!(
+            IdempotencyKeys::new()
+                .lookup(&task_status, "key", &request)
+                .unwrap(),
+            IdempotencyLookup::Existing("task".to_string())
+        );
+    }
+}
@@ -0,0 +1,655 @@
+use crate::server::models::requests::{JobDefinition, OverlapPolicy};
+use crate::server::models::responses::{GetJobResponse, GetStatusResponse, JobRun, TaskStatusEnum};
+use crate::server::server::TaskStatus;
+use crate::worker_pool::worker_pool::{TaskInfo, WorkerPool};
+
+use chrono::prelude::*;
+use chrono_tz::Tz;
+use cron::Schedule;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+use uuid::Uuid;
+
+/// Amount of last runs that are kept for every job
+pub const JOB_RUNS_HISTORY: usize = 10;
+
+/// Time of one wait for previous task of job with queue overlap policy,
+/// waiting is repeated until previous task finishes
+const OVERLAP_WAIT: Duration = Duration::from_secs(60);
+
+/// Source of current time that schedules of jobs are computed from
+pub type Clock = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+/// Error for job funcs
+#[derive(Debug, Clone)]
+pub enum JobError {
+    /// Job with given id doesn't exist
+    NotExist,
+    /// Job definition is invalid
+    InvalidJob(String),
+    /// Statuses of runs of job can't be read from task store
+    Storage(String),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JobError::NotExist => write!(f, "job with that id doesn't exist!"),
+            JobError::InvalidJob(message) => write!(f, "invalid job: {}", message),
+            JobError::Storage(message) => write!(f, "failed to read runs of job: {}", message),
+        }
+    }
+}
+
+/// Task created by one firing of job
+struct JobRunRecord {
+    task_id: String,
+    fired_at: DateTime<Utc>,
+}
+
+/// Recurring job with parsed schedule and its last runs
+struct Job {
+    definition: JobDefinition,
+    schedule: Schedule,
+    timezone: Tz,
+    /// Last runs, newest first
+    runs: VecDeque<JobRunRecord>,
+    /// Tokio thread that fires job by schedule
+    timer: JoinHandle<()>,
+}
+
+/// Struct that keeps recurring jobs and creates tasks by their schedules
+pub struct JobScheduler {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+    clock: Clock,
+}
+
+impl JobScheduler {
+    /// Creates job scheduler that puts tasks of jobs into given worker pool
+    pub fn new(worker_pool: Arc<WorkerPool>, task_status: TaskStatus) -> JobScheduler {
+        JobScheduler {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            worker_pool,
+            task_status,
+            clock: Arc::new(Utc::now),
+        }
+    }
+
+    /// Replaces source of current time (e.g. with clock driven by paused tokio time in tests)
+    pub fn with_clock(mut self, clock: Clock) -> JobScheduler {
+        self.clock = clock;
+        self
+    }
+
+    /// Creates job from definition and starts its timer, returns id of job
+    pub fn create_job(&self, definition: JobDefinition) -> Result<String, JobError> {
+        let (schedule, timezone) = parse_definition(&definition)?;
+        self.worker_pool
+            .check_queue(&definition.task)
+            .map_err(JobError::InvalidJob)?;
+        let id = Uuid::new_v4().to_string();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let timer = self.start_timer(&id, schedule.clone(), timezone);
+        jobs.insert(
+            id.clone(),
+            Job {
+                definition,
+                schedule,
+                timezone,
+                runs: VecDeque::new(),
+                timer,
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// Replaces definition of job and restarts its timer. Runs of job are kept.
+    pub fn update_job(
+        &self,
+        id: &str,
+        definition: JobDefinition,
+    ) -> Result<GetJobResponse, JobError> {
+        let (schedule, timezone) = parse_definition(&definition)?;
+        self.worker_pool
+            .check_queue(&definition.task)
+            .map_err(JobError::InvalidJob)?;
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(id).ok_or(JobError::NotExist)?;
+        job.timer.abort();
+        job.timer = self.start_timer(id, schedule.clone(), timezone);
+        job.definition = definition;
+        job.schedule = schedule;
+        job.timezone = timezone;
+
+        self.job_response(id, job)
+    }
+
+    /// Stops and removes job. Tasks that were already created are not touched.
+    pub fn delete_job(&self, id: &str) -> Result<GetJobResponse, JobError> {
+        let job = self
+            .jobs
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or(JobError::NotExist)?;
+        job.timer.abort();
+
+        self.job_response(id, &job)
+    }
+
+    pub fn get_job(&self, id: &str) -> Result<GetJobResponse, JobError> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(id).ok_or(JobError::NotExist)?;
+
+        self.job_response(id, job)
+    }
+
+    /// Returns all jobs ordered by id
+    pub fn get_jobs(&self) -> Result<Vec<GetJobResponse>, JobError> {
+        let jobs = self.jobs.lock().unwrap();
+        let mut responses = jobs
+            .iter()
+            .map(|(id, job)| self.job_response(id, job))
+            .collect::<Result<Vec<GetJobResponse>, JobError>>()?;
+        responses.sort_by(|first, second| first.id.cmp(&second.id));
+
+        Ok(responses)
+    }
+
+    fn job_response(&self, id: &str, job: &Job) -> Result<GetJobResponse, JobError> {
+        let runs = job
+            .runs
+            .iter()
+            .map(|run| {
+                let status = self
+                    .task_status
+                    .get_status_by_id(&run.task_id)
+                    .map_err(|err| JobError::Storage(err.to_string()))?;
+                Ok(JobRun {
+                    task_id: run.task_id.clone(),
+                    fired_at: run.fired_at.to_string(),
+                    status: status.status,
+                })
+            })
+            .collect::<Result<_, JobError>>()?;
+
+        Ok(GetJobResponse {
+            id: id.to_string(),
+            definition: job.definition.clone(),
+            next_run_at: job
+                .schedule
+                .after(&(self.clock)().with_timezone(&job.timezone))
+                .next()
+                .map(|next_run_at| next_run_at.with_timezone(&Utc).to_string()),
+            runs,
+        })
+    }
+
+    /// Spawns tokio thread that sleeps until next time of schedule and fires job
+    fn start_timer(&self, id: &str, schedule: Schedule, timezone: Tz) -> JoinHandle<()> {
+        let id = id.to_string();
+        let jobs = self.jobs.clone();
+        let worker_pool = self.worker_pool.clone();
+        let task_status = self.task_status.clone();
+        let clock = self.clock.clone();
+
+        task::spawn(async move {
+            while let Some(next_run_at) = schedule.after(&clock().with_timezone(&timezone)).next() {
+                let delay = (next_run_at.with_timezone(&Utc) - clock())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO);
+                tokio::time::sleep(delay).await;
+
+                fire_job(&id, &jobs, &worker_pool, &task_status, &clock).await;
+            }
+        })
+    }
+}
+
+/// Converts day of week field of standard cron (0-7, both 0 and 7 are Sunday)
+/// to numbering of cron crate (1-7, 1 is Sunday). Items with day names are kept.
+fn convert_days_of_week(field: &str) -> Result<String, String> {
+    let parse_day = |day: &str| match day.parse::<u32>() {
+        Ok(day) if day <= 7 => Ok(day),
+        _ => Err(format!("invalid day of week {}", day)),
+    };
+
+    let mut items = Vec::new();
+    for item in field.split(',') {
+        if item == "*" || item == "?" || item.chars().any(|c| c.is_ascii_alphabetic()) {
+            items.push(item.to_string());
+            continue;
+        }
+
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+        let step = match step {
+            Some(step) => match step.parse::<usize>() {
+                Ok(step) if step > 0 => step,
+                _ => return Err(format!("invalid step {}", step)),
+            },
+            None => 1,
+        };
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None if range == "*" => (0, 6),
+            None if step > 1 => (parse_day(range)?, 6),
+            None => (parse_day(range)?, parse_day(range)?),
+        };
+        if first > last {
+            return Err(format!("invalid range of days {}", range));
+        }
+
+        items.extend(
+            (first..=last)
+                .step_by(step)
+                .map(|day| (day % 7 + 1).to_string()),
+        );
+    }
+
+    Ok(items.join(","))
+}
+
+/// Parses cron expression and timezone of job definition.
+/// Cron expressions without seconds field are standard cron expressions
+/// that are run at the start of minute.
+fn parse_definition(definition: &JobDefinition) -> Result<(Schedule, Tz), JobError> {
+    if definition.task.run_at.is_some() || definition.task.delay_ms.is_some() {
+        return Err(JobError::InvalidJob(
+            "task of job can't have run_at or delay_ms".to_string(),
+        ));
+    }
+    definition.task.validate().map_err(JobError::InvalidJob)?;
+
+    let fields: Vec<&str> = definition.schedule.split_whitespace().collect();
+    let expression = match fields.as_slice() {
+        [minutes, hours, days, months, days_of_week] => {
+            let days_of_week = convert_days_of_week(days_of_week).map_err(|err| {
+                JobError::InvalidJob(format!(
+                    "invalid cron expression {}: {}",
+                    definition.schedule, err
+                ))
+            })?;
+            format!(
+                "0 {} {} {} {} {}",
+                minutes, hours, days, months, days_of_week
+            )
+        }
+        _ => definition.schedule.clone(),
+    };
+    let schedule = Schedule::from_str(&expression).map_err(|err| {
+        JobError::InvalidJob(format!(
+            "invalid cron expression {}: {}",
+            definition.schedule, err
+        ))
+    })?;
+
+    let timezone = definition
+        .timezone
+        .parse::<Tz>()
+        .map_err(|_| JobError::InvalidJob(format!("unknown timezone {}", definition.timezone)))?;
+
+    Ok((schedule, timezone))
+}
+
+/// Returns true if task of previous run of job is not finished yet
+fn is_overlapping(status: &TaskStatusEnum) -> bool {
+    matches!(
+        status,
+        TaskStatusEnum::SCHEDULED
+            | TaskStatusEnum::BLOCKED
+            | TaskStatusEnum::WAIT
+            | TaskStatusEnum::RUNNING
+    )
+}
+
+/// Creates task of job and puts it into queue. If previous task of job is still
+/// not finished, overlap policy says whether to skip firing, to hold it until
+/// previous task finishes or to cancel previous task.
+async fn fire_job(
+    id: &str,
+    jobs: &Mutex<HashMap<String, Job>>,
+    worker_pool: &WorkerPool,
+    task_status: &TaskStatus,
+    clock: &Clock,
+) {
+    if worker_pool.is_shutting_down() {
+        return;
+    }
+
+    let (previous_task_id, overlap_policy) = {
+        let jobs = jobs.lock().unwrap();
+        let Some(job) = jobs.get(id) else {
+            return;
+        };
+
+        (
+            job.runs.front().map(|run| run.task_id.clone()),
+            job.definition.overlap_policy,
+        )
+    };
+    // Store is called after lock is released, so slow stores don't block other jobs
+    let previous_task_id = match previous_task_id {
+        Some(task_id) => {
+            task_status
+                .blocking(move |task_status| {
+                    let is_running = task_status
+                        .get_status_by_id(&task_id)
+                        .is_ok_and(|status| is_overlapping(&status.status));
+                    is_running.then_some(task_id)
+                })
+                .await
+        }
+        None => None,
+    };
+
+    if let Some(previous_task_id) = previous_task_id {
+        match overlap_policy {
+            OverlapPolicy::Skip => return,
+            OverlapPolicy::Queue => {
+                // Timer doesn't fire job again while it waits, so at most one firing is held
+                loop {
+                    match task_status
+                        .wait_for_finished_task(&previous_task_id, OVERLAP_WAIT)
+                        .await
+                    {
+                        Ok(status) if !is_overlapping(&status.status) => break,
+                        Ok(_) => {}
+                        Err(err) => {
+                            eprintln!("failed to wait for previous run of job {}: {}", id, err);
+                            return;
+                        }
+                    }
+                }
+                if worker_pool.is_shutting_down() {
+                    return;
+                }
+            }
+            OverlapPolicy::Replace => {
+                let task_id = previous_task_id.clone();
+                let is_cancelled = task_status
+                    .blocking(move |task_status| task_status.clone().cancel_waiting_task(&task_id))
+                    .await;
+                if !is_cancelled {
+                    worker_pool.kill_running_task(&previous_task_id);
+                }
+            }
+        }
+    }
+
+    let Some(task) = jobs
+        .lock()
+        .unwrap()
+        .get(id)
+        .map(|job| job.definition.task.clone())
+    else {
+        return;
+    };
+    let mut status = GetStatusResponse::new_utc_status();
+    status.meta.job_id = Some(id.to_string());
+    let stored_task = task.clone();
+    let task_id = match task_status
+        .blocking(move |task_status| task_status.add_new_task(&stored_task, status))
+        .await
+    {
+        Ok(task_id) => task_id,
+        Err(err) => {
+            eprintln!("failed to store task of job {}: {}", id, err);
+            return;
+        }
+    };
+
+    if let Some(job) = jobs.lock().unwrap().get_mut(id) {
+        job.runs.push_front(JobRunRecord {
+            task_id: task_id.clone(),
+            fired_at: clock(),
+        });
+        job.runs.truncate(JOB_RUNS_HISTORY);
+    }
+    let task_info = TaskInfo::new(task_id, task, task_status.clone());
+
+    worker_pool.do_task(task_info).await;
+}
+
+#[cfg(test)]
+mod test_jobs {
+    use crate::jobs::jobs::{parse_definition, Clock, JobScheduler};
+    use crate::server::models::requests::{
+        CreateTaskRequest, FailureKind, JobDefinition, OverlapPolicy, TaskType,
+    };
+    use crate::server::models::responses::{GetStatusResult, TaskAttempt, TaskStatusEnum};
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use chrono::prelude::*;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Clock that follows paused tokio time and starts in the middle of second,
+    /// so job that fires every second fires 0.5s, 1.5s, 2.5s... after creation
+    fn paused_clock() -> Clock {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+            + chrono::Duration::milliseconds(500);
+        let started = tokio::time::Instant::now();
+
+        Arc::new(move || start + chrono::Duration::from_std(started.elapsed()).unwrap())
+    }
+
+    /// Creates job scheduler without workers, so tasks of jobs stay in queue
+    /// until test finishes them
+    fn create_job_scheduler() -> (JobScheduler, TaskStatus) {
+        let worker_pool = Arc::new(WorkerPool::new(0));
+        let task_status = TaskStatus::new();
+
+        (
+            JobScheduler::new(worker_pool, task_status.clone()).with_clock(paused_clock()),
+            task_status,
+        )
+    }
+
+    fn job_definition(overlap_policy: OverlapPolicy) -> JobDefinition {
+        JobDefinition {
+            schedule: "* * * * * *".to_string(),
+            timezone: "UTC".to_string(),
+            task: CreateTaskRequest::new(
+                TaskType::Python,
+                "print('tick')".to_string(),
+                "".to_string(),
+            ),
+            overlap_policy,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_job_creates_linked_tasks() {
+        let (job_scheduler, mut task_status) = create_job_scheduler();
+
+        let id = job_scheduler
+            .create_job(job_definition(OverlapPolicy::Skip))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 1);
+        assert_eq!(job.next_run_at.unwrap(), "2024-01-01 00:00:02 UTC");
+        task_status.cancel_waiting_task(&job.runs[0].task_id);
+
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 2);
+        assert_eq!(job.runs[0].status, TaskStatusEnum::WAIT);
+
+        let status = task_status.get_status_by_id(&job.runs[0].task_id).unwrap();
+        assert_eq!(status.meta.job_id, Some(id.clone()));
+
+        job_scheduler.delete_job(&id).unwrap();
+        assert!(job_scheduler.get_job(&id).is_err());
+        assert!(job_scheduler.get_jobs().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_skip_overlapping_run() {
+        let (job_scheduler, _) = create_job_scheduler();
+
+        let id = job_scheduler
+            .create_job(job_definition(OverlapPolicy::Skip))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(3000)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 1);
+        assert_eq!(job.runs[0].status, TaskStatusEnum::WAIT);
+
+        job_scheduler.delete_job(&id).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduled_run_is_overlapping() {
+        let (job_scheduler, task_status) = create_job_scheduler();
+
+        let id = job_scheduler
+            .create_job(job_definition(OverlapPolicy::Skip))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let task_id = job_scheduler.get_job(&id).unwrap().runs[0].task_id.clone();
+        // Task waits for retry after failed attempt
+        let mut retrying_task_status = task_status.clone();
+        retrying_task_status.start_running_task(&task_id, "default", 0, vec![]);
+        let attempt = TaskAttempt {
+            attempt: 1,
+            started_at: Utc::now().to_string(),
+            finished_at: Utc::now().to_string(),
+            status: TaskStatusEnum::ERROR,
+            result: GetStatusResult {
+                stdout: String::new(),
+                stderr: None,
+                artifacts: BTreeMap::new(),
+                stdout_ref: None,
+                stderr_ref: None,
+            },
+            failure: Some(FailureKind::Error),
+        };
+        retrying_task_status.retry_task(&task_id, attempt, Utc::now() + chrono::Duration::hours(1));
+        assert_eq!(
+            task_status.get_status_by_id(&task_id).unwrap().status,
+            TaskStatusEnum::SCHEDULED
+        );
+
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+        assert_eq!(job_scheduler.get_job(&id).unwrap().runs.len(), 1);
+
+        job_scheduler.delete_job(&id).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_overlapping_run() {
+        let (job_scheduler, mut task_status) = create_job_scheduler();
+
+        let id = job_scheduler
+            .create_job(job_definition(OverlapPolicy::Queue))
+            .unwrap();
+
+        // Second firing is held while first task waits in queue
+        tokio::time::sleep(Duration::from_millis(3000)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 1);
+
+        task_status.cancel_waiting_task(&job.runs[0].task_id);
+        // Held firing creates task as soon as previous one finishes
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 2);
+        assert_eq!(job.runs[0].status, TaskStatusEnum::WAIT);
+        assert_eq!(job.runs[1].status, TaskStatusEnum::CANCELLED);
+        assert_eq!(job.runs[0].fired_at, "2024-01-01 00:00:03.500 UTC");
+
+        job_scheduler.delete_job(&id).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replace_overlapping_run() {
+        let (job_scheduler, _) = create_job_scheduler();
+
+        let id = job_scheduler
+            .create_job(job_definition(OverlapPolicy::Replace))
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+        let job = job_scheduler.get_job(&id).unwrap();
+        assert_eq!(job.runs.len(), 2);
+        assert_eq!(job.runs[0].status, TaskStatusEnum::WAIT);
+        assert_eq!(job.runs[1].status, TaskStatusEnum::CANCELLED);
+
+        job_scheduler.delete_job(&id).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_job() {
+        let worker_pool = Arc::new(WorkerPool::new(1));
+        let job_scheduler = JobScheduler::new(worker_pool, TaskStatus::new());
+
+        let mut definition = job_definition(OverlapPolicy::Skip);
+        definition.schedule = "every day".to_string();
+        assert!(job_scheduler.create_job(definition).is_err());
+
+        let mut definition = job_definition(OverlapPolicy::Skip);
+        definition.timezone = "Mars/Olympus".to_string();
+        assert!(job_scheduler.create_job(definition).is_err());
+
+        let mut definition = job_definition(OverlapPolicy::Skip);
+        definition.schedule = "0 3 * * *".to_string();
+        definition.timezone = "Europe/Moscow".to_string();
+        let id = job_scheduler.create_job(definition).unwrap();
+        assert!(job_scheduler.get_job(&id).unwrap().next_run_at.is_some());
+
+        let definition = job_definition(OverlapPolicy::Skip);
+        assert!(job_scheduler.update_job("random-UUID", definition).is_err());
+
+        let mut definition = job_definition(OverlapPolicy::Skip);
+        definition.schedule = "* * * * 8".to_string();
+        assert!(job_scheduler.create_job(definition).is_err());
+    }
+
+    /// Returns days of week of runs of standard cron schedule during week from Monday 2024-01-01
+    fn days_of_week(schedule: &str) -> Vec<Weekday> {
+        let mut definition = job_definition(OverlapPolicy::Skip);
+        definition.schedule = schedule.to_string();
+        let (schedule, _) = parse_definition(&definition).unwrap();
+
+        let start = Utc.with_ymd_and_hms(2023, 12, 31, 23, 59, 59).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 8, 0, 0, 0).unwrap();
+        let mut days: Vec<Weekday> = schedule
+            .after(&start)
+            .take_while(|run_at| *run_at < end)
+            .map(|run_at| run_at.weekday())
+            .collect();
+        days.dedup();
+
+        days
+    }
+
+    #[test]
+    fn test_standard_days_of_week() {
+        use Weekday::*;
+
+        assert_eq!(days_of_week("0 3 * * 1"), vec![Mon]);
+        assert_eq!(days_of_week("* * * * 1-5"), vec![Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(days_of_week("* * * * 0"), vec![Sun]);
+        assert_eq!(days_of_week("0 0 * * 7"), vec![Sun]);
+        assert_eq!(days_of_week("0 0 * * 5-7"), vec![Fri, Sat, Sun]);
+        assert_eq!(days_of_week("0 0 * * */2"), vec![Tue, Thu, Sat, Sun]);
+        assert_eq!(days_of_week("0 0 * * MON,3"), vec![Mon, Wed]);
+        assert_eq!(days_of_week("0 0 * * *").len(), 7);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::server::models::requests::CreateTaskRequest;
+use crate::server::models::responses::GetStatusResponse;
+use crate::task_store::task_store::{IdempotencyKeyRecord, TaskRecord};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Default interval in seconds between journal compactions
+pub const DEFAULT_COMPACTION_INTERVAL_SECONDS: u64 = 300;
+
+/// What to do with tasks that were running when server stopped
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum InterruptedTaskPolicy {
+    /// Mark interrupted tasks as ERROR
+    Error,
+    /// Put interrupted tasks back into the queue
+    Requeue,
+}
+
+/// Line of journal file
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum JournalEntry {
+    /// Task was created with given request
+    Submitted {
+        id: String,
+        request: Box<CreateTaskRequest>,
+        status: GetStatusResponse,
+    },
+    /// Task status was changed
+    Transition {
+        id: String,
+        status: GetStatusResponse,
+    },
+    /// Finished task was removed by retention policy
+    Evicted { id: String },
+    /// Task was created with idempotency key
+    IdempotencyKey { record: IdempotencyKeyRecord },
+    /// Idempotency keys first used before given unix time in milliseconds were forgotten
+    IdempotencyKeysPurged { created_before: i64 },
+}
+
+/// Append-only log of task submissions and status transitions
+/// stored as newline-delimited json
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Opens journal file for appending, creates it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Journal> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = open_for_append(&path)?;
+
+        // Line may be left unfinished if server crashed while writing it
+        let content = fs::read(&path)?;
+        if content.last().is_some_and(|&byte| byte != b'\n') {
+            writeln!(file)?;
+        }
+
+        Ok(Journal {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records new task with its request and initial status.
+    /// Entry is synced to disk, so accepted task survives crash of server.
+    pub fn record_submission(
+        &self,
+        id: &str,
+        request: &CreateTaskRequest,
+        status: &GetStatusResponse,
+    ) -> io::Result<()> {
+        let entry = JournalEntry::Submitted {
+            id: id.to_string(),
+            request: Box::new(request.clone()),
+            status: status.clone(),
+        };
+        self.append(&entry, true)
+    }
+
+    /// Records new status of task
+    pub fn record_transition(&self, id: &str, status: &GetStatusResponse) -> io::Result<()> {
+        let entry = JournalEntry::Transition {
+            id: id.to_string(),
+            status: status.clone(),
+        };
+        self.append(&entry, false)
+    }
+
+    /// Records that finished task was removed by retention policy
+    pub fn record_eviction(&self, id: &str) -> io::Result<()> {
+        self.append(&JournalEntry::Evicted { id: id.to_string() }, false)
+    }
+
+    /// Records task created with idempotency key. Entry is synced to disk
+    /// with submission of task, so retried request finds task after crash.
+    pub fn record_idempotency_key(&self, record: &IdempotencyKeyRecord) -> io::Result<()> {
+        let entry = JournalEntry::IdempotencyKey {
+            record: record.clone(),
+        };
+        self.append(&entry, true)
+    }
+
+    /// Records that idempotency keys first used before given time were forgotten
+    pub fn record_idempotency_key_purge(&self, created_before: i64) -> io::Result<()> {
+        self.append(
+            &JournalEntry::IdempotencyKeysPurged { created_before },
+            false,
+        )
+    }
+
+    /// Writes entry as one line in single write, so that failed write
+    /// can leave at most one unfinished line. Syncs file to disk if asked.
+    fn append(&self, entry: &JournalEntry, sync: bool) -> io::Result<()> {
+        let line = serde_json::to_string(entry).unwrap() + "\n";
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        if sync {
+            file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads journal and returns latest state of every task in order of submission
+    pub fn replay(&self) -> io::Result<Vec<TaskRecord>> {
+        let _file = self.file.lock().unwrap();
+        Ok(read_journal(&self.path)?.0)
+    }
+
+    /// Reads journal and returns idempotency keys that weren't purged
+    pub fn replay_idempotency_keys(&self) -> io::Result<Vec<IdempotencyKeyRecord>> {
+        let _file = self.file.lock().unwrap();
+        Ok(read_journal(&self.path)?.1)
+    }
+
+    /// Rewrites journal so that it contains one entry with latest status per task
+    pub fn compact(&self) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let (records, idempotency_keys) = read_journal(&self.path)?;
+
+        let temporary_path = self.path.with_extension("compact");
+        let mut temporary_file = File::create(&temporary_path)?;
+        let entries = records
+            .into_iter()
+            .map(|record| JournalEntry::Submitted {
+                id: record.id,
+                request: Box::new(record.request),
+                status: record.status,
+            })
+            .chain(
+                idempotency_keys
+                    .into_iter()
+                    .map(|record| JournalEntry::IdempotencyKey { record }),
+            );
+        for entry in entries {
+            writeln!(temporary_file, "{}", serde_json::to_string(&entry).unwrap())?;
+        }
+        temporary_file.sync_all()?;
+
+        fs::rename(&temporary_path, &self.path)?;
+        *file = open_for_append(&self.path)?;
+
+        Ok(())
+    }
+}
+
+fn open_for_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Folds journal entries into latest state of every task and idempotency keys
+/// that weren't purged. Broken lines (e.g. partially written on crash) are skipped.
+fn read_journal(path: &Path) -> io::Result<(Vec<TaskRecord>, Vec<IdempotencyKeyRecord>)> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut order = Vec::new();
+    let mut records: HashMap<String, TaskRecord> = HashMap::new();
+    let mut idempotency_keys: HashMap<(String, String), IdempotencyKeyRecord> = HashMap::new();
+
+    for line in reader.lines() {
+        let entry = match serde_json::from_str::<JournalEntry>(&line?) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        match entry {
+            JournalEntry::Submitted {
+                id,
+                request,
+                status,
+            } => {
+                if !records.contains_key(&id) {
+                    order.push(id.clone());
+                }
+                records.insert(
+                    id.clone(),
+                    TaskRecord {
+                        id,
+                        request: *request,
+                        status,
+                    },
+                );
+            }
+            JournalEntry::Transition { id, status } => {
+                if let Some(record) = records.get_mut(&id) {
+                    record.status = status;
+                }
+            }
+            // Only id of evicted task is kept, so that it is still reported as evicted
+            JournalEntry::Evicted { id } => {
+                if let Some(record) = records.get_mut(&id) {
+                    record.request = CreateTaskRequest::new(
+                        record.request.task_type,
+                        String::new(),
+                        String::new(),
+                    );
+                    record.status = GetStatusResponse::new_evicted_status();
+                }
+            }
+            JournalEntry::IdempotencyKey { record } => {
+                idempotency_keys.insert((record.client_id.clone(), record.key.clone()), record);
+            }
+            JournalEntry::IdempotencyKeysPurged { created_before } => {
+                idempotency_keys.retain(|_, record| record.created_at >= created_before);
+            }
+        }
+    }
+
+    let records = order
+        .into_iter()
+        .filter_map(|id| records.remove(&id))
+        .collect();
+    let mut idempotency_keys: Vec<IdempotencyKeyRecord> = idempotency_keys.into_values().collect();
+    idempotency_keys.sort_by_key(|record| record.created_at);
+
+    Ok((records, idempotency_keys))
+}
+
+#[cfg(test)]
+mod test_journal {
+    use crate::journal::journal::Journal;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::task_store::task_store::IdempotencyKeyRecord;
+    use std::fs;
+
+    #[test]
+    fn test_replay_latest_status() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut status = GetStatusResponse::new_utc_status();
+        journal
+            .record_submission("first", &request, &status)
+            .unwrap();
+        journal
+            .record_submission("second", &request, &status)
+            .unwrap();
+
+        status.status = TaskStatusEnum::RUNNING;
+        journal.record_transition("first", &status).unwrap();
+
+        let records = journal.replay().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, "first");
+        assert_eq!(records[0].status.status, TaskStatusEnum::RUNNING);
+        assert_eq!(records[1].id, "second");
+        assert_eq!(records[1].status.status, TaskStatusEnum::WAIT);
+    }
+
+    #[test]
+    fn test_compact() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut status = GetStatusResponse::new_utc_status();
+        journal
+            .record_submission("first", &request, &status)
+            .unwrap();
+        status.status = TaskStatusEnum::RUNNING;
+        journal.record_transition("first", &status).unwrap();
+        status.status = TaskStatusEnum::SUCCESS;
+        journal.record_transition("first", &status).unwrap();
+
+        journal.compact().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        journal
+            .record_submission("second", &request, &status)
+            .unwrap();
+        let records = journal.replay().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].status.status, TaskStatusEnum::SUCCESS);
+    }
+
+    #[test]
+    fn test_evicted_task_keeps_only_id() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut status = GetStatusResponse::new_utc_status();
+        status.status = TaskStatusEnum::SUCCESS;
+        status.result.stdout = "1".to_string();
+        journal
+            .record_submission("first", &request, &status)
+            .unwrap();
+        journal.record_eviction("first").unwrap();
+        journal.compact().unwrap();
+
+        let records = journal.replay().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status.status, TaskStatusEnum::EVICTED);
+        assert_eq!(records[0].status.result.stdout, "");
+        assert_eq!(records[0].request.file, "");
+    }
+
+    #[test]
+    fn test_skip_broken_line() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let status = GetStatusResponse::new_utc_status();
+        journal
+            .record_submission("first", &request, &status)
+            .unwrap();
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "{\"event\": \"transi",
+        )
+        .unwrap();
+
+        let records = journal.replay().unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let record = |key: &str, created_at| IdempotencyKeyRecord {
+            client_id: String::new(),
+            key: key.to_string(),
+            fingerprint: "fingerprint".to_string(),
+            task_id: "task".to_string(),
+            created_at,
+        };
+        journal.record_idempotency_key(&record("old", 10)).unwrap();
+        journal.record_idempotency_key(&record("new", 30)).unwrap();
+        journal.record_idempotency_key_purge(20).unwrap();
+        assert_eq!(
+            journal.replay_idempotency_keys().unwrap(),
+            vec![record("new", 30)]
+        );
+
+        journal.compact().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert_eq!(
+            journal.replay_idempotency_keys().unwrap(),
+            vec![record("new", 30)]
+        );
+        assert!(journal.replay().unwrap().is_empty());
+    }
+}
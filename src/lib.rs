@@ -1,4 +1,13 @@
+pub mod callbacks;
+pub mod export;
 pub mod file_executer;
+pub mod idempotency;
 pub mod input_parser;
+pub mod jobs;
+pub mod journal;
+pub mod outputs;
+pub mod retention;
 pub mod server;
+pub mod task_store;
 pub mod worker_pool;
+pub mod workflows;
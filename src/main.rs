@@ -1,17 +1,233 @@
 use clap::Parser;
-use tasksolver::input_parser::ServerStartArguments;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::sync::Arc;
+use std::time::Duration;
+use tasksolver::callbacks::callbacks::CallbackConfig;
+use tasksolver::export::export::{
+    check_empty, delete_imported_outputs, export_tasks, import_tasks, store_tasks, stored_tasks,
+};
+use tasksolver::input_parser::{AdminCommand, ServerStartArguments};
+use tasksolver::journal::journal::Journal;
+use tasksolver::outputs::outputs::OutputStore;
+use tasksolver::retention::retention::RetentionPolicy;
 use tasksolver::server::server::TaskSolverServer;
+use tasksolver::task_store::postgres_task_store::PostgresTaskStore;
+use tasksolver::task_store::sqlite_task_store::SqliteTaskStore;
+use tasksolver::task_store::task_store::{MemoryTaskStore, TaskStore, TaskStoreKind};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Waits for SIGINT or SIGTERM
+async fn wait_for_shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+/// Runs admin command with tasks of journal or persistent task store of server
+fn run_admin_command(
+    command: AdminCommand,
+    server_start_arguments: &ServerStartArguments,
+) -> Result<(), String> {
+    let journal = server_start_arguments
+        .journal
+        .as_ref()
+        .map(Journal::open)
+        .transpose()
+        .map_err(|err| format!("failed to open journal: {}", err))?;
+    let task_store: Arc<dyn TaskStore> = match server_start_arguments.task_store {
+        TaskStoreKind::Memory if journal.is_none() => {
+            return Err(
+                "tasks in memory are lost on exit, set --journal or --task-store".to_string(),
+            );
+        }
+        TaskStoreKind::Memory => Arc::new(MemoryTaskStore::new()),
+        TaskStoreKind::Sqlite => Arc::new(
+            SqliteTaskStore::open(&server_start_arguments.sqlite_path)
+                .map_err(|err| format!("failed to open sqlite task store: {}", err))?,
+        ),
+        TaskStoreKind::Postgres => {
+            let postgres_url = server_start_arguments.postgres_url.as_deref().unwrap();
+            Arc::new(
+                PostgresTaskStore::connect(postgres_url)
+                    .map_err(|err| format!("failed to connect to postgres: {}", err))?,
+            )
+        }
+    };
+    let output_store = server_start_arguments
+        .output_dir
+        .as_ref()
+        .map(|output_dir| {
+            OutputStore::open(
+                output_dir,
+                server_start_arguments.output_spill_threshold,
+                server_start_arguments.output_preview_bytes,
+            )
+        })
+        .transpose()
+        .map_err(|err| format!("failed to open output directory: {}", err))?;
+
+    match command {
+        AdminCommand::Export { format, output } => {
+            let records = stored_tasks(journal.as_ref(), task_store.as_ref())
+                .map_err(|err| format!("failed to read tasks: {}", err))?;
+            let result = match output {
+                Some(output) => File::create(output).and_then(|file| {
+                    export_tasks(
+                        &records,
+                        format,
+                        output_store.as_ref(),
+                        BufWriter::new(file),
+                    )
+                }),
+                None => export_tasks(&records, format, output_store.as_ref(), io::stdout().lock()),
+            };
+            result.map_err(|err| format!("failed to export tasks: {}", err))?;
+            eprintln!("exported {} tasks", records.len());
+        }
+        AdminCommand::Import { input } => {
+            let file =
+                File::open(input).map_err(|err| format!("failed to open export: {}", err))?;
+            // Emptiness is checked first, so that outputs are not written for nothing
+            check_empty(journal.as_ref(), task_store.as_ref())
+                .map_err(|err| format!("failed to import tasks: {}", err))?;
+            let records = import_tasks(file, output_store.as_ref())
+                .map_err(|err| format!("failed to import tasks: {}", err))?;
+            if let Err(err) = store_tasks(&records, journal.as_ref(), task_store.as_ref()) {
+                if let Some(output_store) = &output_store {
+                    delete_imported_outputs(&records, output_store);
+                }
+                return Err(format!("failed to import tasks: {}", err));
+            }
+            eprintln!("imported {} tasks", records.len());
+        }
+    }
+
+    Ok(())
+}
 
 /// Runs tasksolver with arguments from command line
 #[tokio::main]
 async fn main() {
-    let server_start_arguments = ServerStartArguments::parse();
+    let mut server_start_arguments = ServerStartArguments::parse();
+    if let Some(command) = server_start_arguments.command.take() {
+        if let Err(err) = run_admin_command(command, &server_start_arguments) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let tasksolver_server = TaskSolverServer::new(
         server_start_arguments.workers_count,
         server_start_arguments.address,
         server_start_arguments.port,
+    )
+    .with_grace_period(Duration::from_secs(server_start_arguments.grace_period))
+    .with_compaction_interval(Duration::from_secs(
+        server_start_arguments.journal_compaction_interval,
+    ))
+    .with_client_weights(server_start_arguments.client_weights.into_iter().collect())
+    .with_backfill_window(Duration::from_millis(
+        server_start_arguments.backfill_window_ms,
+    ))
+    .with_idempotency_window(Duration::from_secs(
+        server_start_arguments.idempotency_window,
+    ))
+    .with_retention(RetentionPolicy {
+        max_age: server_start_arguments
+            .retention_max_age
+            .map(Duration::from_secs),
+        max_finished: server_start_arguments.retention_max_tasks,
+        max_output_bytes: server_start_arguments.retention_max_output_bytes,
+    })
+    .with_callbacks(CallbackConfig {
+        default_url: server_start_arguments.callback_url,
+        secret: server_start_arguments.callback_secret,
+        max_attempts: server_start_arguments.callback_max_attempts,
+        backoff: Duration::from_millis(server_start_arguments.callback_backoff_ms),
+        allow_private_hosts: server_start_arguments.callback_allow_private_hosts,
+    })
+    .with_admin_export(server_start_arguments.enable_admin_export);
+    let tasksolver_server = match server_start_arguments.max_wait_ms {
+        Some(max_wait_ms) => {
+            tasksolver_server.with_default_max_wait(Duration::from_millis(max_wait_ms))
+        }
+        None => tasksolver_server,
+    };
+    let tasksolver_server = server_start_arguments
+        .queues
+        .into_iter()
+        .fold(tasksolver_server, TaskSolverServer::with_queue);
+    let tasksolver_server = match server_start_arguments.cpus {
+        Some(cpus) => tasksolver_server.with_cpus(cpus),
+        None => tasksolver_server,
+    };
+    let tasksolver_server = server_start_arguments
+        .concurrency_limits
+        .into_iter()
+        .fold(tasksolver_server, TaskSolverServer::with_concurrency_limit);
+    let tasksolver_server = server_start_arguments.routes.iter().fold(
+        tasksolver_server,
+        |tasksolver_server, (task_type, queue)| tasksolver_server.with_route(*task_type, queue),
     );
 
-    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await;
+    let tasksolver_server = match server_start_arguments.task_store {
+        TaskStoreKind::Memory => tasksolver_server,
+        TaskStoreKind::Sqlite => tasksolver_server.with_task_store(
+            Arc::new(
+                SqliteTaskStore::open(&server_start_arguments.sqlite_path)
+                    .expect("failed to open sqlite task store"),
+            ),
+            server_start_arguments.interrupted_task_policy,
+        ),
+        TaskStoreKind::Postgres => {
+            let postgres_url = server_start_arguments.postgres_url.as_deref().unwrap();
+            let task_store = Arc::new(
+                PostgresTaskStore::connect(postgres_url).expect("failed to connect to postgres"),
+            );
+            tasksolver_server
+                .with_task_store(
+                    task_store.clone(),
+                    server_start_arguments.interrupted_task_policy,
+                )
+                .with_shared_queue(task_store)
+        }
+    };
+    let tasksolver_server = match &server_start_arguments.output_dir {
+        Some(output_dir) => tasksolver_server.with_output_store(
+            OutputStore::open(
+                output_dir,
+                server_start_arguments.output_spill_threshold,
+                server_start_arguments.output_preview_bytes,
+            )
+            .expect("failed to open output directory"),
+        ),
+        None => tasksolver_server,
+    };
+    let tasksolver_server = match &server_start_arguments.journal {
+        Some(path) => tasksolver_server.with_journal(
+            Journal::open(path).expect("failed to open journal"),
+            server_start_arguments.interrupted_task_policy,
+        ),
+        None => tasksolver_server,
+    };
+
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    tokio::spawn(async move {
+        wait_for_shutdown_signal().await;
+        shutdown_handle.shutdown();
+    });
+
+    let tasksolver_handle = match tasksolver_server.start_tasksolver_server().await {
+        Ok(tasksolver_handle) => tasksolver_handle,
+        Err(err) => {
+            eprintln!("failed to restore tasks: {}", err);
+            std::process::exit(1);
+        }
+    };
     let _ = tasksolver_handle.await;
 }
@@ -0,0 +1,347 @@
+use crate::server::models::responses::{GetStatusResult, OutputRef};
+
+use std::fs;
+use std::io::{self, Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use warp::http::{header, StatusCode};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+/// Default size in bytes of output that is stored in file instead of task
+pub const DEFAULT_SPILL_THRESHOLD_BYTES: usize = 64 * 1024;
+
+/// Default size in bytes of preview of stored output that is kept in task
+pub const DEFAULT_PREVIEW_BYTES: usize = 4 * 1024;
+
+/// Output stream of task
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    fn name(&self) -> &'static str {
+        match self {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+impl FromStr for OutputStream {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "stdout" => Ok(OutputStream::Stdout),
+            "stderr" => Ok(OutputStream::Stderr),
+            _ => Err(format!("unknown output stream {}", value)),
+        }
+    }
+}
+
+/// Stores outputs of tasks that are larger than threshold in files of output directory,
+/// one directory per task. Task keeps only preview of such output and reference to its file.
+pub struct OutputStore {
+    dir: PathBuf,
+    threshold: usize,
+    preview_bytes: usize,
+}
+
+impl OutputStore {
+    /// Opens output directory, creates it if it doesn't exist
+    pub fn open(dir: &Path, threshold: usize, preview_bytes: usize) -> io::Result<OutputStore> {
+        fs::create_dir_all(dir)?;
+
+        Ok(OutputStore {
+            dir: dir.to_path_buf(),
+            threshold,
+            preview_bytes,
+        })
+    }
+
+    /// Moves stdout and stderr of result of task or its attempt that are larger than
+    /// threshold to files and leaves their previews in result.
+    /// Output that can't be written to file stays in result.
+    pub fn spill(&self, id: &str, attempt: Option<u32>, result: &mut GetStatusResult) {
+        if let Some(stdout_ref) =
+            self.spill_output(id, attempt, OutputStream::Stdout, &mut result.stdout)
+        {
+            result.stdout_ref = Some(stdout_ref);
+        }
+
+        if let Some(stderr) = &mut result.stderr {
+            if let Some(stderr_ref) = self.spill_output(id, attempt, OutputStream::Stderr, stderr) {
+                result.stderr_ref = Some(stderr_ref);
+            }
+        }
+    }
+
+    fn spill_output(
+        &self,
+        id: &str,
+        attempt: Option<u32>,
+        stream: OutputStream,
+        output: &mut String,
+    ) -> Option<OutputRef> {
+        if output.len() <= self.threshold {
+            return None;
+        }
+
+        let file = match attempt {
+            Some(attempt) => format!("{}/attempt-{}.{}", id, attempt, stream.name()),
+            None => format!("{}/{}", id, stream.name()),
+        };
+        let path = self.dir.join(&file);
+        fs::create_dir_all(path.parent().unwrap()).ok()?;
+        fs::write(&path, output.as_bytes()).ok()?;
+
+        let size = output.len() as u64;
+        output.truncate(preview_length(output, self.preview_bytes));
+        let url = match attempt {
+            Some(attempt) => format!("/tasks/{}/output/{}?attempt={}", id, stream.name(), attempt),
+            None => format!("/tasks/{}/output/{}", id, stream.name()),
+        };
+
+        Some(OutputRef { size, url, file })
+    }
+
+    /// Returns path of file that output reference points to
+    pub fn path(&self, output_ref: &OutputRef) -> PathBuf {
+        self.dir.join(&output_ref.file)
+    }
+
+    /// Writes output of imported task to file with given path relative to output directory
+    pub fn write_file(&self, file: &str, output: &mut impl Read) -> io::Result<()> {
+        let path = self.dir.join(file);
+        fs::create_dir_all(path.parent().unwrap())?;
+        io::copy(output, &mut fs::File::create(path)?)?;
+
+        Ok(())
+    }
+
+    /// Deletes files with outputs of task and its attempts
+    pub fn delete(&self, id: &str) {
+        let _ = fs::remove_dir_all(self.dir.join(id));
+    }
+}
+
+/// Returns length of longest prefix of output that is not longer than given
+/// amount of bytes and doesn't split UTF-8 character
+fn preview_length(output: &str, max_length: usize) -> usize {
+    if output.len() <= max_length {
+        return output.len();
+    }
+
+    let mut length = max_length;
+    while !output.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    length
+}
+
+/// Output of task that is kept in task or stored in file
+pub enum OutputContent {
+    Inline(String),
+    File(PathBuf),
+}
+
+/// Part of output requested by Range header
+#[derive(Debug, PartialEq)]
+pub enum OutputRange {
+    /// Whole output: there is no Range header or it is ignored
+    Full,
+    /// Bytes from start to end, both inclusive
+    Partial(u64, u64),
+    /// Range starts after end of output
+    NotSatisfiable,
+}
+
+/// Parses Range header of request of output with given size. Only single byte ranges
+/// are supported, malformed headers and headers with several ranges are ignored.
+pub fn parse_range(header: Option<&str>, size: u64) -> OutputRange {
+    let Some(range) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+        return OutputRange::Full;
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return OutputRange::Full;
+    };
+
+    if start.is_empty() {
+        // Suffix range: last bytes of output
+        return match end.parse::<u64>() {
+            Ok(0) => OutputRange::NotSatisfiable,
+            Ok(_) if size == 0 => OutputRange::NotSatisfiable,
+            Ok(length) => OutputRange::Partial(size.saturating_sub(length), size - 1),
+            Err(_) => OutputRange::Full,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return OutputRange::Full;
+    };
+    let end = match end {
+        "" => u64::MAX,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return OutputRange::Full,
+        },
+    };
+    if start >= size {
+        return OutputRange::NotSatisfiable;
+    }
+
+    OutputRange::Partial(start, end.min(size - 1))
+}
+
+/// Creates reply with part of output requested by Range header. Whole output is
+/// replied with 200, part with 206 and range after end of output with 416.
+/// Output from file is streamed without reading it into memory.
+pub async fn output_reply(content: OutputContent, range: Option<&str>) -> io::Result<Response> {
+    let size = match &content {
+        OutputContent::Inline(output) => output.len() as u64,
+        OutputContent::File(path) => tokio::fs::metadata(path).await?.len(),
+    };
+
+    let builder = warp::http::Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(header::ACCEPT_RANGES, "bytes");
+    let (builder, start, length) = match parse_range(range, size) {
+        OutputRange::Full => (builder.status(StatusCode::OK), 0, size),
+        OutputRange::Partial(start, end) => (
+            builder.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            ),
+            start,
+            end - start + 1,
+        ),
+        OutputRange::NotSatisfiable => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+
+    let body = match content {
+        OutputContent::Inline(output) => {
+            let start = start as usize;
+            Body::from(output.into_bytes()[start..start + length as usize].to_vec())
+        }
+        OutputContent::File(path) => {
+            let mut file = tokio::fs::File::open(path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            Body::wrap_stream(ReaderStream::new(file.take(length)))
+        }
+    };
+
+    Ok(builder
+        .header(header::CONTENT_LENGTH, length)
+        .body(body)
+        .unwrap())
+}
+
+#[cfg(test)]
+mod test_outputs {
+    use crate::outputs::outputs::{
+        output_reply, parse_range, OutputContent, OutputRange, OutputStore,
+    };
+    use crate::server::models::responses::GetStatusResult;
+    use std::collections::BTreeMap;
+    use warp::http::StatusCode;
+    use warp::hyper::body::to_bytes;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range(None, 10), OutputRange::Full);
+        assert_eq!(
+            parse_range(Some("bytes=2-5"), 10),
+            OutputRange::Partial(2, 5)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=2-"), 10),
+            OutputRange::Partial(2, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=8-20"), 10),
+            OutputRange::Partial(8, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-3"), 10),
+            OutputRange::Partial(7, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-30"), 10),
+            OutputRange::Partial(0, 9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=10-"), 10),
+            OutputRange::NotSatisfiable
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-0"), 10),
+            OutputRange::NotSatisfiable
+        );
+        assert_eq!(parse_range(Some("bytes=0-1,4-5"), 10), OutputRange::Full);
+        assert_eq!(parse_range(Some("bytes=5-2"), 10), OutputRange::Full);
+        assert_eq!(parse_range(Some("lines=1-2"), 10), OutputRange::Full);
+    }
+
+    #[tokio::test]
+    async fn test_large_output_is_spilled() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_store = OutputStore::open(dir.path(), 10, 4).unwrap();
+
+        let mut result = GetStatusResult {
+            stdout: "ééééé-0123456789".to_string(),
+            stderr: Some("short".to_string()),
+            artifacts: BTreeMap::new(),
+            stdout_ref: None,
+            stderr_ref: None,
+        };
+        output_store.spill("task", Some(2), &mut result);
+
+        assert_eq!(result.stdout, "éé");
+        assert_eq!(result.stderr.as_deref(), Some("short"));
+        assert!(result.stderr_ref.is_none());
+        let stdout_ref = result.stdout_ref.unwrap();
+        assert_eq!(stdout_ref.size, 21);
+        assert_eq!(stdout_ref.url, "/tasks/task/output/stdout?attempt=2");
+
+        let path = output_store.path(&stdout_ref);
+        let reply = output_reply(OutputContent::File(path.clone()), Some("bytes=11-14"))
+            .await
+            .unwrap();
+        assert_eq!(reply.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(reply.headers()["content-range"], "bytes 11-14/21");
+        assert_eq!(to_bytes(reply.into_body()).await.unwrap(), "0123");
+
+        let reply = output_reply(OutputContent::File(path.clone()), None)
+            .await
+            .unwrap();
+        assert_eq!(reply.status(), StatusCode::OK);
+        assert_eq!(
+            to_bytes(reply.into_body()).await.unwrap(),
+            "ééééé-0123456789"
+        );
+
+        output_store.delete("task");
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_range_after_end_of_output() {
+        let reply = output_reply(OutputContent::Inline("abc".to_string()), Some("bytes=3-"))
+            .await
+            .unwrap();
+        assert_eq!(reply.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(reply.headers()["content-range"], "bytes */3");
+    }
+}
@@ -0,0 +1,324 @@
+use crate::server::models::responses::{GetStatusResponse, GetStatusResult, OutputRef};
+
+use chrono::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Time that ids of evicted tasks are remembered in memory after eviction
+pub const EVICTED_IDS_MAX_AGE_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+/// Limits of finished tasks that are kept in memory. Limits that are not set are unlimited.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetentionPolicy {
+    /// Time that finished task is kept after it finished
+    pub max_age: Option<Duration>,
+    /// Maximum amount of finished tasks
+    pub max_finished: Option<usize>,
+    /// Maximum total size of outputs (stdout, stderr and artifacts) of finished tasks
+    pub max_output_bytes: Option<usize>,
+}
+
+/// Finished task that is kept in memory
+struct RetainedTask {
+    /// Number of last use, tasks with smaller numbers are evicted first
+    last_use: u64,
+    finished_at: DateTime<Utc>,
+    output_bytes: usize,
+}
+
+#[derive(Default)]
+struct RetentionState {
+    policy: RetentionPolicy,
+    /// Finished tasks by id
+    tasks: HashMap<String, RetainedTask>,
+    /// Ids of finished tasks by number of their last use
+    by_last_use: BTreeMap<u64, String>,
+    next_use: u64,
+    /// Total size of outputs of finished tasks
+    output_bytes: usize,
+    /// Ids of evicted tasks, stored as numbers to take less memory
+    evicted: HashSet<u128>,
+    /// Ids of evicted tasks with time of eviction, oldest first
+    evicted_order: VecDeque<(DateTime<Utc>, u128)>,
+}
+
+impl RetentionState {
+    fn touch(&mut self, id: &str) {
+        let next_use = self.next_use;
+        if let Some(task) = self.tasks.get_mut(id) {
+            self.by_last_use.remove(&task.last_use);
+            task.last_use = next_use;
+            self.by_last_use.insert(next_use, id.to_string());
+            self.next_use += 1;
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some(task) = self.tasks.remove(id) {
+            self.by_last_use.remove(&task.last_use);
+            self.output_bytes -= task.output_bytes;
+        }
+        let now = Utc::now();
+        if let Ok(uuid) = Uuid::parse_str(id) {
+            if self.evicted.insert(uuid.as_u128()) {
+                self.evicted_order.push_back((now, uuid.as_u128()));
+            }
+        }
+        self.forget_evicted_before(now - chrono::Duration::seconds(EVICTED_IDS_MAX_AGE_SECONDS));
+    }
+
+    /// Forgets ids of tasks evicted before given time, they are reported as not existing then
+    fn forget_evicted_before(&mut self, time: DateTime<Utc>) {
+        while let Some(&(evicted_at, uuid)) = self.evicted_order.front() {
+            if evicted_at >= time {
+                break;
+            }
+            self.evicted_order.pop_front();
+            self.evicted.remove(&uuid);
+        }
+    }
+
+    /// Removes least recently used tasks while amount of tasks
+    /// or size of their outputs exceeds limits
+    fn evict_over_limits(&mut self) -> Vec<String> {
+        let mut evicted = Vec::new();
+        loop {
+            let over_count = self
+                .policy
+                .max_finished
+                .is_some_and(|max_finished| self.tasks.len() > max_finished);
+            let over_bytes = self
+                .policy
+                .max_output_bytes
+                .is_some_and(|max_output_bytes| self.output_bytes > max_output_bytes);
+            if !over_count && !over_bytes {
+                return evicted;
+            }
+
+            let Some((_, id)) = self.by_last_use.pop_first() else {
+                return evicted;
+            };
+            self.remove(&id);
+            evicted.push(id);
+        }
+    }
+}
+
+/// Tracks finished tasks and decides which of them are evicted
+/// according to retention policy
+#[derive(Default)]
+pub struct Retention {
+    state: Mutex<RetentionState>,
+}
+
+impl Retention {
+    /// Creates retention without limits
+    pub fn new() -> Retention {
+        Retention::default()
+    }
+
+    /// Sets limits of finished tasks
+    pub fn set_policy(&self, policy: RetentionPolicy) {
+        self.state.lock().unwrap().policy = policy;
+    }
+
+    /// Returns limits of finished tasks
+    pub fn policy(&self) -> RetentionPolicy {
+        self.state.lock().unwrap().policy.clone()
+    }
+
+    /// Starts tracking finished task as most recently used.
+    /// Returns ids of tasks that must be evicted to stay within limits.
+    pub fn track(&self, id: &str, status: &GetStatusResponse) -> Vec<String> {
+        let finished_at = status
+            .meta
+            .finished_at
+            .as_ref()
+            .and_then(|finished_at| finished_at.parse::<DateTime<Utc>>().ok())
+            .unwrap_or_else(Utc::now);
+        let output_bytes = output_bytes(status);
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(task) = state.tasks.remove(id) {
+            state.by_last_use.remove(&task.last_use);
+            state.output_bytes -= task.output_bytes;
+        }
+
+        let last_use = state.next_use;
+        state.next_use += 1;
+        state.by_last_use.insert(last_use, id.to_string());
+        state.output_bytes += output_bytes;
+        state.tasks.insert(
+            id.to_string(),
+            RetainedTask {
+                last_use,
+                finished_at,
+                output_bytes,
+            },
+        );
+
+        state.evict_over_limits()
+    }
+
+    /// Marks finished task as most recently used
+    pub fn touch(&self, id: &str) {
+        self.state.lock().unwrap().touch(id);
+    }
+
+    /// Returns ids of tasks that finished longer than max age ago and stops tracking them
+    pub fn evict_expired(&self) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        let Some(max_age) = state
+            .policy
+            .max_age
+            .and_then(|max_age| chrono::Duration::from_std(max_age).ok())
+        else {
+            return Vec::new();
+        };
+
+        let now = Utc::now();
+        let expired: Vec<String> = state
+            .tasks
+            .iter()
+            .filter(|(_, task)| now - task.finished_at > max_age)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            state.remove(id);
+        }
+
+        expired
+    }
+
+    /// Remembers that task was evicted (e.g. when it is restored from journal)
+    pub fn mark_evicted(&self, id: &str) {
+        self.state.lock().unwrap().remove(id);
+    }
+
+    /// Returns true if task was evicted
+    pub fn is_evicted(&self, id: &str) -> bool {
+        Uuid::parse_str(id)
+            .is_ok_and(|uuid| self.state.lock().unwrap().evicted.contains(&uuid.as_u128()))
+    }
+
+    /// Returns amount of tracked finished tasks and total size of their outputs
+    pub fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.tasks.len(), state.output_bytes)
+    }
+}
+
+/// Returns size of stdout, stderr and artifacts of task and of all its attempts.
+/// Outputs that are stored in files are counted with their full size.
+fn output_bytes(status: &GetStatusResponse) -> usize {
+    let output_size = |output: &str, output_ref: &Option<OutputRef>| {
+        output_ref
+            .as_ref()
+            .map_or(output.len(), |output_ref| output_ref.size as usize)
+    };
+    let result_bytes = |result: &GetStatusResult| {
+        output_size(&result.stdout, &result.stdout_ref)
+            + result
+                .stderr
+                .as_ref()
+                .map_or(0, |stderr| output_size(stderr, &result.stderr_ref))
+            + result.artifacts.values().map(String::len).sum::<usize>()
+    };
+
+    result_bytes(&status.result)
+        + status
+            .attempts
+            .iter()
+            .map(|attempt| result_bytes(&attempt.result))
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod test_retention {
+    use crate::retention::retention::{Retention, RetentionPolicy};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use chrono::prelude::*;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn finished_status(stdout: &str, finished_at: DateTime<Utc>) -> GetStatusResponse {
+        let mut status = GetStatusResponse::new_utc_status();
+        status.status = TaskStatusEnum::SUCCESS;
+        status.result.stdout = stdout.to_string();
+        status.meta.finished_at = Some(finished_at.to_string());
+
+        status
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let retention = Retention::new();
+        retention.set_policy(RetentionPolicy {
+            max_finished: Some(2),
+            max_output_bytes: Some(10),
+            ..RetentionPolicy::default()
+        });
+        let ids: Vec<String> = (0..4).map(|_| Uuid::new_v4().to_string()).collect();
+
+        assert!(retention
+            .track(&ids[0], &finished_status("aaaa", Utc::now()))
+            .is_empty());
+        assert!(retention
+            .track(&ids[1], &finished_status("bbbb", Utc::now()))
+            .is_empty());
+        retention.touch(&ids[0]);
+
+        // Third task exceeds amount of tasks, least recently used one is evicted
+        assert_eq!(
+            retention.track(&ids[2], &finished_status("cccc", Utc::now())),
+            vec![ids[1].clone()]
+        );
+        // Fourth task exceeds output budget
+        assert_eq!(
+            retention.track(&ids[3], &finished_status("dddddddd", Utc::now())),
+            vec![ids[0].clone(), ids[2].clone()]
+        );
+
+        assert_eq!(retention.usage(), (1, 8));
+        assert!(retention.is_evicted(&ids[0]));
+        assert!(!retention.is_evicted(&ids[3]));
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let retention = Retention::new();
+        retention.set_policy(RetentionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            ..RetentionPolicy::default()
+        });
+        let old_id = Uuid::new_v4().to_string();
+        let new_id = Uuid::new_v4().to_string();
+
+        retention.track(
+            &old_id,
+            &finished_status("", Utc::now() - Duration::from_secs(120)),
+        );
+        retention.track(&new_id, &finished_status("", Utc::now()));
+
+        assert_eq!(retention.evict_expired(), vec![old_id.clone()]);
+        assert!(retention.is_evicted(&old_id));
+        assert_eq!(retention.usage(), (1, 0));
+    }
+
+    #[test]
+    fn test_forget_old_evicted_ids() {
+        let retention = Retention::new();
+        let id = Uuid::new_v4().to_string();
+        retention.mark_evicted(&id);
+        assert!(retention.is_evicted(&id));
+
+        let mut state = retention.state.lock().unwrap();
+        state.forget_evicted_before(Utc::now() - Duration::from_secs(60));
+        assert_eq!(state.evicted.len(), 1);
+        state.forget_evicted_before(Utc::now() + Duration::from_secs(60));
+        assert!(state.evicted.is_empty());
+        assert!(state.evicted_order.is_empty());
+    }
+}
@@ -1,68 +1,941 @@
 use std::convert::Infallible;
 
+use crate::export::export;
+use crate::idempotency::idempotency::{IdempotencyKeys, IdempotencyLookup};
+use crate::jobs::jobs::{JobError, JobScheduler};
+use crate::outputs::outputs::{OutputContent, OutputStream};
 use crate::worker_pool::worker_pool::{TaskInfo, WorkerPool};
+use crate::workflows::workflows::{WorkflowError, WorkflowScheduler};
+use chrono::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufWriter, Seek};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
 
-use super::models::requests::{CreateTaskRequest, GetStatusRequest};
-use super::models::responses::{CreateTaskResponse, GetStatusResponse, GetTaskCountResponse};
+use super::models::requests::{
+    CancelTaskRequest, CreateTaskBatchRequest, CreateTaskRequest, CreateWorkflowRequest,
+    DeleteJobRequest, ExportQuery, GetJobRequest, GetStatusBatchRequest, GetStatusRequest,
+    GetTaskMetricsRequest, GetWorkflowRequest, JobDefinition, ListTasksRequest, SortOrder,
+    TaskOutputQuery, TaskSortField, UpdateJobRequest, WaitTaskRequest,
+};
+use super::models::responses::{
+    CreateJobResponse, CreateTaskBatchItem, CreateTaskBatchResponse, CreateTaskResponse,
+    CreateTaskWaitResponse, CreateWorkflowResponse, GetJobResponse, GetJobsResponse,
+    GetScheduledTasksResponse, GetStatusBatchResponse, GetStatusResponse, GetStatusResult,
+    GetTaskCountResponse, GetTaskMetricsResponse, GetWorkflowResponse, ListTasksResponse,
+    TaskEventsResponse, TaskListItem, TaskStatusEnum,
+};
 use super::server::TaskStatus;
+use crate::task_store::task_store::{TaskRecord, TaskStoreError};
+use base64::prelude::*;
 
-// Error for get status func
-// Returns ff task with given id doesn't exist
+/// Error for create task func
 #[derive(Debug, Clone)]
-struct TaskNotExistError;
+pub enum CreateTaskError {
+    /// Server is shutting down and doesn't accept new tasks
+    ShuttingDown,
+    /// Request contains invalid field
+    InvalidRequest(String),
+    /// Idempotency key was already used with different request
+    IdempotencyKeyReused,
+    /// Task can't be stored
+    Storage(String),
+}
+
+impl std::fmt::Display for CreateTaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CreateTaskError::ShuttingDown => {
+                write!(f, "server is shutting down and doesn't accept new tasks!")
+            }
+            CreateTaskError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            CreateTaskError::IdempotencyKeyReused => write!(
+                f,
+                "idempotency key was already used with different request!"
+            ),
+            CreateTaskError::Storage(message) => write!(f, "failed to store task: {}", message),
+        }
+    }
+}
+
+/// Error for list tasks func
+#[derive(Debug, Clone)]
+pub enum ListTasksError {
+    /// Filter, limit or cursor is invalid
+    InvalidRequest(String),
+    /// Tasks can't be read from task store
+    Storage(String),
+}
+
+impl std::fmt::Display for ListTasksError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ListTasksError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            ListTasksError::Storage(message) => write!(f, "failed to read tasks: {}", message),
+        }
+    }
+}
+
+/// Error for cancel task func
+#[derive(Debug, Clone)]
+pub enum CancelTaskError {
+    /// Task with given id doesn't exist
+    NotExist,
+    /// Task is already running or finished
+    AlreadyStarted,
+    /// Task can't be read from task store
+    Storage(String),
+}
+
+impl std::fmt::Display for CancelTaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CancelTaskError::NotExist => write!(f, "task with that id doesn't exist!"),
+            CancelTaskError::AlreadyStarted => write!(f, "task has already started!"),
+            CancelTaskError::Storage(message) => write!(f, "failed to read task: {}", message),
+        }
+    }
+}
+
+/// Error for get task events func
+#[derive(Debug, Clone)]
+pub enum TaskEventsError {
+    /// Task with given id doesn't exist or was evicted
+    NotExist,
+    /// Task can't be read from task store
+    Storage(String),
+}
+
+impl std::fmt::Display for TaskEventsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaskEventsError::NotExist => write!(f, "task with that id doesn't exist!"),
+            TaskEventsError::Storage(message) => write!(f, "failed to read task: {}", message),
+        }
+    }
+}
+
+/// Error for get task output func
+#[derive(Debug, Clone)]
+pub enum TaskOutputError {
+    /// Task or its attempt doesn't exist, task was evicted or file of output was deleted
+    NotExist,
+    /// Task can't be read from task store
+    Storage(String),
+}
+
+impl std::fmt::Display for TaskOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaskOutputError::NotExist => write!(f, "output of task doesn't exist!"),
+            TaskOutputError::Storage(message) => write!(f, "failed to read task: {}", message),
+        }
+    }
+}
+
+/// Maximum amount of tasks in one batch request
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+/// Error for batch funcs
+#[derive(Debug, Clone)]
+pub enum BatchError {
+    /// Batch has more items than allowed
+    TooBig,
+    /// Server is shutting down and doesn't accept new tasks
+    ShuttingDown,
+    /// Tasks can't be read from task store
+    Storage(String),
+}
 
-impl std::fmt::Display for TaskNotExistError {
+impl std::fmt::Display for BatchError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "task with that id doesn't exist!")
+        match self {
+            BatchError::TooBig => write!(f, "batch can't have more than {} items!", MAX_BATCH_SIZE),
+            BatchError::ShuttingDown => {
+                write!(f, "server is shutting down and doesn't accept new tasks!")
+            }
+            BatchError::Storage(message) => write!(f, "failed to read tasks: {}", message),
+        }
     }
 }
 
+/// Returns time when task should be put into queue from run_at or delay_ms
+/// fields of request, or None if task should be put into queue right away
+fn resolve_run_at(request: &CreateTaskRequest) -> Result<Option<DateTime<Utc>>, CreateTaskError> {
+    let run_at = match (&request.run_at, request.delay_ms) {
+        (Some(_), Some(_)) => {
+            return Err(CreateTaskError::InvalidRequest(
+                "only one of run_at and delay_ms can be set".to_string(),
+            ))
+        }
+        (Some(run_at), None) => run_at.parse::<DateTime<Utc>>().map_err(|_| {
+            CreateTaskError::InvalidRequest(format!("run_at is not a valid time: {}", run_at))
+        })?,
+        (None, Some(delay_ms)) => i64::try_from(delay_ms)
+            .ok()
+            .and_then(chrono::TimeDelta::try_milliseconds)
+            .and_then(|delay| Utc::now().checked_add_signed(delay))
+            .ok_or_else(|| CreateTaskError::InvalidRequest("delay_ms is too big".to_string()))?,
+        (None, None) => return Ok(None),
+    };
+
+    Ok((run_at > Utc::now()).then_some(run_at))
+}
+
 /// Handler for /create_task endpoint
 /// Gets create task request and push it to the task queue.
 /// Creates default get status response and insert it into
 /// task status hashmap by generated uuid, then return
-/// response with id of task.
+/// response with id of task. Tasks with run_at or delay_ms
+/// are scheduled and put into queue later. If server is shutting down,
+/// returns error.
 pub async fn create_task(
     request: CreateTaskRequest,
     worker_pool: Arc<WorkerPool>,
     task_status: TaskStatus,
-) -> Result<CreateTaskResponse, Infallible> {
-    let id = task_status.add_new_task();
+) -> Result<CreateTaskResponse, CreateTaskError> {
+    if worker_pool.is_shutting_down() {
+        return Err(CreateTaskError::ShuttingDown);
+    }
+
+    request
+        .validate()
+        .map_err(CreateTaskError::InvalidRequest)?;
+    worker_pool
+        .check_queue(&request)
+        .map_err(CreateTaskError::InvalidRequest)?;
+    let run_at = resolve_run_at(&request)?;
+    let status = match run_at {
+        Some(run_at) => GetStatusResponse::new_scheduled_status(run_at),
+        None => GetStatusResponse::new_utc_status(),
+    };
+    let (request, id) = task_status
+        .blocking(move |task_status| {
+            let id = task_status.add_new_task(&request, status);
+            (request, id)
+        })
+        .await;
+    let id = id.map_err(|err| CreateTaskError::Storage(err.to_string()))?;
 
     let task_info = TaskInfo::new(id.to_string(), request, task_status);
-    worker_pool.do_task(task_info).await;
+    match run_at {
+        Some(run_at) => worker_pool.schedule_task(task_info, run_at),
+        None => worker_pool.do_task(task_info).await,
+    }
 
     let response = CreateTaskResponse { id };
 
     Ok(response)
 }
 
+/// Handler for /create_task endpoint for requests with idempotency key.
+/// If key was already used with same request, returns id of the first created task
+/// instead of creating new one. If key was used with different request, returns error.
+pub async fn create_task_once(
+    request: CreateTaskRequest,
+    idempotency_keys: Arc<IdempotencyKeys>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> Result<CreateTaskResponse, CreateTaskError> {
+    let Some(key) = request.idempotency_key.clone() else {
+        return create_task(request, worker_pool, task_status).await;
+    };
+
+    let _creation_guard = idempotency_keys.lock_creation(&key, &request).await;
+    let lookup = {
+        let (idempotency_keys, key, request) =
+            (idempotency_keys.clone(), key.clone(), request.clone());
+        task_status
+            .blocking(move |task_status| idempotency_keys.lookup(task_status, &key, &request))
+            .await
+            .map_err(|err| CreateTaskError::Storage(err.to_string()))?
+    };
+    match lookup {
+        IdempotencyLookup::Existing(id) => Ok(CreateTaskResponse { id }),
+        IdempotencyLookup::Conflict => Err(CreateTaskError::IdempotencyKeyReused),
+        IdempotencyLookup::New => {
+            let response = create_task(request.clone(), worker_pool, task_status.clone()).await?;
+            let (idempotency_keys, id) = (idempotency_keys.clone(), response.id.clone());
+            // Task is already created, so failure to remember key is only logged
+            task_status
+                .blocking(move |task_status| {
+                    if let Err(err) = idempotency_keys.insert(task_status, &key, &request, &id) {
+                        eprintln!("failed to store idempotency key of task {}: {}", id, err);
+                    }
+                })
+                .await;
+
+            Ok(response)
+        }
+    }
+}
+
+/// Default time in milliseconds that requests wait for task to finish
+pub const DEFAULT_WAIT_TIMEOUT_MS: u64 = 30_000;
+
+/// Maximum time in milliseconds that requests wait for task to finish
+pub const MAX_WAIT_TIMEOUT_MS: u64 = 300_000;
+
+/// Returns time of waiting for task from timeout of request, limited by maximum
+fn wait_timeout(timeout_ms: Option<u64>) -> Duration {
+    Duration::from_millis(
+        timeout_ms
+            .unwrap_or(DEFAULT_WAIT_TIMEOUT_MS)
+            .min(MAX_WAIT_TIMEOUT_MS),
+    )
+}
+
+/// Handler for /create_task endpoint with wait=true.
+/// Creates task like create_task_once and waits until it is finished or timeout
+/// elapses, then returns id of task with its status.
+pub async fn create_task_and_wait(
+    request: CreateTaskRequest,
+    timeout_ms: Option<u64>,
+    idempotency_keys: Arc<IdempotencyKeys>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> Result<CreateTaskWaitResponse, CreateTaskError> {
+    let id = create_task_once(request, idempotency_keys, worker_pool, task_status.clone())
+        .await?
+        .id;
+
+    let mut status = task_status
+        .wait_for_finished_task(&id, wait_timeout(timeout_ms))
+        .await
+        .map_err(|err| CreateTaskError::Storage(err.to_string()))?;
+    status.events.clear();
+
+    Ok(CreateTaskWaitResponse { id, status })
+}
+
+/// Handler for /wait_task endpoint
+/// Waits until task is finished or timeout elapses and returns its status.
+/// Status of task that doesn't exist is returned right away.
+pub async fn wait_task(
+    request: WaitTaskRequest,
+    task_status: TaskStatus,
+) -> Result<GetStatusResponse, TaskStoreError> {
+    let mut status = task_status
+        .wait_for_finished_task(&request.id, wait_timeout(request.timeout_ms))
+        .await?;
+    status.events.clear();
+
+    Ok(status)
+}
+
+/// Handler for /create_task_batch endpoint
+/// Creates every task of batch like /create_task does and returns
+/// ids of created tasks or errors of invalid ones in order of requests.
+pub async fn create_task_batch(
+    request: CreateTaskBatchRequest,
+    idempotency_keys: Arc<IdempotencyKeys>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> Result<CreateTaskBatchResponse, BatchError> {
+    if request.tasks.len() > MAX_BATCH_SIZE {
+        return Err(BatchError::TooBig);
+    }
+    if worker_pool.is_shutting_down() {
+        return Err(BatchError::ShuttingDown);
+    }
+
+    let mut tasks = Vec::new();
+    for task_request in request.tasks {
+        let result = create_task_once(
+            task_request,
+            idempotency_keys.clone(),
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await;
+
+        tasks.push(match result {
+            Ok(response) => CreateTaskBatchItem {
+                id: Some(response.id),
+                error: None,
+            },
+            Err(err) => CreateTaskBatchItem {
+                id: None,
+                error: Some(err.to_string()),
+            },
+        });
+    }
+
+    Ok(CreateTaskBatchResponse { tasks })
+}
+
+/// Handler for /cancel_task endpoint
+/// Cancels task that is scheduled or waits in queue and
+/// returns its status. Running and finished tasks can't be cancelled.
+pub async fn cancel_task(
+    request: CancelTaskRequest,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> Result<GetStatusResponse, CancelTaskError> {
+    let id = request.id;
+    let storage_error = |err: TaskStoreError| CancelTaskError::Storage(err.to_string());
+
+    task_status
+        .blocking(move |task_status| {
+            let mut task_status = task_status.clone();
+            match task_status
+                .get_status_by_id(&id)
+                .map_err(storage_error)?
+                .status
+            {
+                TaskStatusEnum::NOTEXIST => return Err(CancelTaskError::NotExist),
+                TaskStatusEnum::SCHEDULED if worker_pool.cancel_scheduled_task(&id) => {}
+                _ => {
+                    if !task_status.cancel_waiting_task(&id) {
+                        return Err(CancelTaskError::AlreadyStarted);
+                    }
+                }
+            }
+
+            let mut status = task_status.get_status_by_id(&id).map_err(storage_error)?;
+            status.events.clear();
+
+            Ok(status)
+        })
+        .await
+}
+
+/// Handler for /get_scheduled_tasks endpoint
+/// Returns tasks that wait for their scheduled time
+pub async fn get_scheduled_tasks(
+    worker_pool: Arc<WorkerPool>,
+) -> Result<GetScheduledTasksResponse, Infallible> {
+    let response = GetScheduledTasksResponse {
+        tasks: worker_pool.get_scheduled_tasks(),
+    };
+
+    Ok(response)
+}
+
 /// Handler for /get_status endpoint
 /// Gets get status request and fetch task status
 /// by that id. If that id doesn't exist, return json with
-/// error message.
+/// error message. History of task is returned only if it is requested.
 pub async fn get_status(
     request: GetStatusRequest,
     task_status: TaskStatus,
-) -> Result<GetStatusResponse, Infallible> {
+) -> Result<GetStatusResponse, TaskStoreError> {
     let id = request.id;
+    let mut status = task_status
+        .blocking(move |task_status| task_status.get_status_by_id(&id))
+        .await?;
+    if !request.include_events {
+        status.events.clear();
+    }
+
+    Ok(status)
+}
+
+/// Handler for /tasks/{id}/events endpoint
+/// Returns history of task status, oldest event first
+pub async fn get_task_events(
+    id: String,
+    task_status: TaskStatus,
+) -> Result<TaskEventsResponse, TaskEventsError> {
+    let task_id = id.clone();
+    let status = task_status
+        .blocking(move |task_status| task_status.get_status_by_id(&task_id))
+        .await
+        .map_err(|err| TaskEventsError::Storage(err.to_string()))?;
+    if matches!(
+        status.status,
+        TaskStatusEnum::NOTEXIST | TaskStatusEnum::EVICTED
+    ) {
+        return Err(TaskEventsError::NotExist);
+    }
+
+    Ok(TaskEventsResponse {
+        id,
+        events: status.events,
+    })
+}
+
+/// Handler for /tasks/{id}/output/{stream} endpoint
+/// Returns stdout or stderr of task or of its attempt: file with full output
+/// if output was too large to keep in task, otherwise output kept in task
+pub async fn get_task_output(
+    id: String,
+    stream: OutputStream,
+    query: TaskOutputQuery,
+    task_status: TaskStatus,
+) -> Result<OutputContent, TaskOutputError> {
+    let status = task_status
+        .blocking(move |task_status| task_status.get_status_by_id(&id))
+        .await
+        .map_err(|err| TaskOutputError::Storage(err.to_string()))?;
+    if matches!(
+        status.status,
+        TaskStatusEnum::NOTEXIST | TaskStatusEnum::EVICTED
+    ) {
+        return Err(TaskOutputError::NotExist);
+    }
+
+    let result = match query.attempt {
+        Some(attempt) => {
+            status
+                .attempts
+                .into_iter()
+                .find(|task_attempt| task_attempt.attempt == attempt)
+                .ok_or(TaskOutputError::NotExist)?
+                .result
+        }
+        None => status.result,
+    };
+    let (output, output_ref) = match stream {
+        OutputStream::Stdout => (result.stdout, result.stdout_ref),
+        OutputStream::Stderr => (result.stderr.unwrap_or_default(), result.stderr_ref),
+    };
+
+    match output_ref {
+        Some(output_ref) => task_status
+            .output_path(&output_ref)
+            .map(OutputContent::File)
+            .ok_or(TaskOutputError::NotExist),
+        None => Ok(OutputContent::Inline(output)),
+    }
+}
+
+/// Handler for /admin/export endpoint
+/// Writes all tasks with their requests, statuses and outputs in requested format
+/// to temporary file and returns it. Tasks are written while they are read from store.
+pub async fn export_tasks(
+    query: ExportQuery,
+    task_status: TaskStatus,
+) -> Result<std::fs::File, std::io::Error> {
+    task::spawn_blocking(move || {
+        let mut file = tempfile::tempfile()?;
+        let output_store = task_status.output_store();
+        let mut exporter = export::TaskExporter::new(
+            query.format,
+            output_store.as_deref(),
+            BufWriter::new(&mut file),
+        )?;
+        let mut result = Ok(());
+        task_status
+            .for_each_task(&mut |record| {
+                if result.is_ok() {
+                    result = exporter.write(record);
+                }
+            })
+            .map_err(std::io::Error::other)?;
+        result?;
+        exporter.finish()?;
+        file.rewind()?;
+
+        Ok(file)
+    })
+    .await
+    .unwrap()
+}
+
+/// Handler for /get_status_batch endpoint
+/// Returns statuses of tasks by their ids, tasks that don't exist have status NOTEXIST
+pub async fn get_status_batch(
+    request: GetStatusBatchRequest,
+    task_status: TaskStatus,
+) -> Result<GetStatusBatchResponse, BatchError> {
+    if request.ids.len() > MAX_BATCH_SIZE {
+        return Err(BatchError::TooBig);
+    }
+
+    let tasks = task_status
+        .blocking(move |task_status| {
+            request
+                .ids
+                .into_iter()
+                .map(|id| {
+                    let mut status = task_status.get_status_by_id(&id)?;
+                    status.events.clear();
+                    Ok((id, status))
+                })
+                .collect::<Result<_, TaskStoreError>>()
+        })
+        .await
+        .map_err(|err| BatchError::Storage(err.to_string()))?;
 
-    Ok(task_status.get_status_by_id(&id))
+    Ok(GetStatusBatchResponse { tasks })
+}
+
+/// Amount of tasks in list tasks response if request doesn't set limit
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Maximum amount of tasks in list tasks response
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Key that listed tasks are sorted by, tasks with same time are sorted by id
+type SortKey = (Option<DateTime<Utc>>, String);
+
+/// Parses UTC time of list tasks filter
+fn parse_time_filter(
+    name: &str,
+    value: &Option<String>,
+) -> Result<Option<DateTime<Utc>>, ListTasksError> {
+    value
+        .as_ref()
+        .map(|value| {
+            value.parse::<DateTime<Utc>>().map_err(|_| {
+                ListTasksError::InvalidRequest(format!(
+                    "{} must be UTC time in RFC 3339 format",
+                    name
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Returns true if time is in range [after, before). Missing time is in range
+/// only if range is not set.
+fn is_in_range(
+    time: Option<DateTime<Utc>>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+) -> bool {
+    if after.is_none() && before.is_none() {
+        return true;
+    }
+
+    time.is_some_and(|time| {
+        after.is_none_or(|after| time >= after) && before.is_none_or(|before| time < before)
+    })
+}
+
+/// Returns true if task has all labels of filter with same values
+/// and its metadata matches metadata of filter
+fn is_matching_labels_and_metadata(
+    record: &TaskRecord,
+    labels: &BTreeMap<String, String>,
+    metadata: &Option<serde_json::Value>,
+) -> bool {
+    labels
+        .iter()
+        .all(|(name, value)| record.request.labels.get(name) == Some(value))
+        && metadata.as_ref().is_none_or(|metadata| {
+            record
+                .request
+                .metadata
+                .as_ref()
+                .is_some_and(|task_metadata| is_matching_metadata(task_metadata, metadata))
+        })
+}
+
+/// Fields of json object of filter must be in json object of task with same values,
+/// other json of filter must be equal to json of task
+fn is_matching_metadata(task_metadata: &serde_json::Value, filter: &serde_json::Value) -> bool {
+    match (task_metadata, filter) {
+        (serde_json::Value::Object(task_metadata), serde_json::Value::Object(filter)) => filter
+            .iter()
+            .all(|(name, value)| task_metadata.get(name) == Some(value)),
+        _ => task_metadata == filter,
+    }
+}
+
+/// Encodes sort key of last listed task as opaque cursor
+fn encode_cursor((time, id): &SortKey) -> String {
+    let time = time.map(|time| time.to_rfc3339_opts(SecondsFormat::Nanos, true));
+    BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_string(&(time, id)).unwrap())
+}
+
+fn decode_cursor(cursor: &str) -> Option<SortKey> {
+    let json = BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let (time, id): (Option<String>, String) = serde_json::from_slice(&json).ok()?;
+    let time = match time {
+        Some(time) => Some(time.parse::<DateTime<Utc>>().ok()?),
+        None => None,
+    };
+
+    Some((time, id))
+}
+
+/// Removes stdout, stderr and artifacts of task and its attempts
+fn omit_output(status: &mut GetStatusResponse) {
+    let empty_result = || GetStatusResult {
+        stdout: String::new(),
+        stderr: None,
+        artifacts: BTreeMap::new(),
+        stdout_ref: None,
+        stderr_ref: None,
+    };
+
+    status.result = empty_result();
+    for attempt in &mut status.attempts {
+        attempt.result = empty_result();
+    }
+}
+
+/// Handler for /list_tasks endpoint
+/// Returns page of tasks that match all filters of request in requested order.
+/// Cursor of response is used to get next page.
+pub async fn list_tasks(
+    request: ListTasksRequest,
+    task_status: TaskStatus,
+) -> Result<ListTasksResponse, ListTasksError> {
+    let limit = request.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(ListTasksError::InvalidRequest(format!(
+            "limit must be from 1 to {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let created_after = parse_time_filter("created_after", &request.created_after)?;
+    let created_before = parse_time_filter("created_before", &request.created_before)?;
+    let finished_after = parse_time_filter("finished_after", &request.finished_after)?;
+    let finished_before = parse_time_filter("finished_before", &request.finished_before)?;
+    let cursor = request
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            decode_cursor(cursor)
+                .ok_or_else(|| ListTasksError::InvalidRequest("invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let is_output_omitted = request.omit_output;
+    let (keys, records) = task_status
+        .blocking(move |task_status| {
+            // Only keys of page and of one more task are kept while tasks are searched
+            let mut keys = BTreeSet::new();
+            task_status.for_each_task(&mut |record| {
+                let meta = &record.status.meta;
+                let created_at = meta.created_at.parse::<DateTime<Utc>>().ok();
+                let finished_at = meta
+                    .finished_at
+                    .as_ref()
+                    .and_then(|finished_at| finished_at.parse::<DateTime<Utc>>().ok());
+
+                let is_matching = (request.status.is_empty()
+                    || request.status.contains(&record.status.status))
+                    && request
+                        .task_type
+                        .is_none_or(|task_type| task_type == record.request.task_type)
+                    && request
+                        .client_id
+                        .as_ref()
+                        .is_none_or(|client_id| meta.client_id.as_ref() == Some(client_id))
+                    && is_matching_labels_and_metadata(record, &request.labels, &request.metadata)
+                    && is_in_range(created_at, created_after, created_before)
+                    && is_in_range(finished_at, finished_after, finished_before);
+                if !is_matching {
+                    return;
+                }
+
+                let time = match request.sort_by {
+                    TaskSortField::CreatedAt => created_at,
+                    TaskSortField::FinishedAt => finished_at,
+                };
+                let key = (time, record.id.clone());
+                let is_after_cursor = cursor.as_ref().is_none_or(|cursor| match request.order {
+                    SortOrder::Asc => key > *cursor,
+                    SortOrder::Desc => key < *cursor,
+                });
+                if !is_after_cursor {
+                    return;
+                }
+
+                keys.insert(key);
+                if keys.len() > limit + 1 {
+                    match request.order {
+                        SortOrder::Asc => keys.pop_last(),
+                        SortOrder::Desc => keys.pop_first(),
+                    };
+                }
+            })?;
+
+            let mut keys: Vec<SortKey> = keys.into_iter().collect();
+            if request.order == SortOrder::Desc {
+                keys.reverse();
+            }
+            // Tasks that were deleted after they were found are skipped
+            let records = keys
+                .iter()
+                .take(limit)
+                .filter_map(|(_, id)| task_status.get_task(id).transpose())
+                .collect::<Result<Vec<TaskRecord>, TaskStoreError>>()?;
+
+            Ok((keys, records))
+        })
+        .await
+        .map_err(|err: TaskStoreError| ListTasksError::Storage(err.to_string()))?;
+
+    let next_cursor = (keys.len() > limit).then(|| encode_cursor(&keys[limit - 1]));
+    let tasks = records
+        .into_iter()
+        .map(|record| {
+            let mut status = record.status;
+            status.events.clear();
+            if is_output_omitted {
+                omit_output(&mut status);
+            }
+
+            TaskListItem {
+                id: record.id,
+                task_type: record.request.task_type,
+                status,
+            }
+        })
+        .collect();
+
+    Ok(ListTasksResponse { tasks, next_cursor })
 }
 
 /// Handler for /get_task_count endpoint
-/// Returns amount of tasks in task queue
+/// Returns amount of tasks in task queue, in total and by client,
+/// and information about every named queue
 pub async fn get_task_count(
     worker_pool: Arc<WorkerPool>,
 ) -> Result<GetTaskCountResponse, Infallible> {
     let response = GetTaskCountResponse {
         tasks: worker_pool.get_task_amount(),
+        clients: worker_pool.get_task_amount_by_client(),
+        queues: worker_pool.get_queues_info(),
+        limits: worker_pool.get_limits_info(),
     };
 
     Ok(response)
 }
 
+/// Handler for /get_task_metrics endpoint
+/// Returns amount of tasks that match all filters of request, in total and by status,
+/// and average duration of matching finished tasks
+pub async fn get_task_metrics(
+    request: GetTaskMetricsRequest,
+    task_status: TaskStatus,
+) -> Result<GetTaskMetricsResponse, TaskStoreError> {
+    let mut response = GetTaskMetricsResponse {
+        tasks: 0,
+        statuses: BTreeMap::new(),
+        average_duration_ms: None,
+    };
+    let mut durations_sum_ms = 0.0;
+    let mut durations_count = 0;
+
+    task_status
+        .blocking(move |task_status| {
+            task_status.for_each_task(&mut |record| {
+                let meta = &record.status.meta;
+                let is_matching = request
+                    .task_type
+                    .is_none_or(|task_type| task_type == record.request.task_type)
+                    && request
+                        .client_id
+                        .as_ref()
+                        .is_none_or(|client_id| meta.client_id.as_ref() == Some(client_id))
+                    && is_matching_labels_and_metadata(record, &request.labels, &request.metadata);
+                if !is_matching {
+                    return;
+                }
+
+                response.tasks += 1;
+                *response
+                    .statuses
+                    .entry(record.status.status.clone())
+                    .or_insert(0) += 1;
+
+                let started_at = meta
+                    .started_at
+                    .as_ref()
+                    .and_then(|started_at| started_at.parse::<DateTime<Utc>>().ok());
+                let finished_at = meta
+                    .finished_at
+                    .as_ref()
+                    .and_then(|finished_at| finished_at.parse::<DateTime<Utc>>().ok());
+                if let (Some(started_at), Some(finished_at)) = (started_at, finished_at) {
+                    durations_sum_ms += (finished_at - started_at).num_milliseconds() as f64;
+                    durations_count += 1;
+                }
+            })?;
+
+            if durations_count > 0 {
+                response.average_duration_ms = Some(durations_sum_ms / durations_count as f64);
+            }
+
+            Ok(response)
+        })
+        .await
+}
+
+/// Handler for /create_job endpoint
+/// Creates recurring job that creates task by cron schedule
+/// and returns id of job.
+pub async fn create_job(
+    definition: JobDefinition,
+    job_scheduler: Arc<JobScheduler>,
+) -> Result<CreateJobResponse, JobError> {
+    let id = job_scheduler.create_job(definition)?;
+
+    Ok(CreateJobResponse { id })
+}
+
+/// Handler for /get_job endpoint
+/// Returns job definition, time of next run and last runs of job
+pub async fn get_job(
+    request: GetJobRequest,
+    job_scheduler: Arc<JobScheduler>,
+) -> Result<GetJobResponse, JobError> {
+    task::spawn_blocking(move || job_scheduler.get_job(&request.id))
+        .await
+        .unwrap()
+}
+
+/// Handler for /get_jobs endpoint
+/// Returns all recurring jobs
+pub async fn get_jobs(job_scheduler: Arc<JobScheduler>) -> Result<GetJobsResponse, JobError> {
+    let jobs = task::spawn_blocking(move || job_scheduler.get_jobs())
+        .await
+        .unwrap()?;
+
+    Ok(GetJobsResponse { jobs })
+}
+
+/// Handler for /update_job endpoint
+/// Replaces definition of job and returns updated job
+pub async fn update_job(
+    request: UpdateJobRequest,
+    job_scheduler: Arc<JobScheduler>,
+) -> Result<GetJobResponse, JobError> {
+    task::spawn_blocking(move || job_scheduler.update_job(&request.id, request.definition))
+        .await
+        .unwrap()
+}
+
+/// Handler for /delete_job endpoint
+/// Stops job and returns it. Tasks created by job are kept.
+pub async fn delete_job(
+    request: DeleteJobRequest,
+    job_scheduler: Arc<JobScheduler>,
+) -> Result<GetJobResponse, JobError> {
+    task::spawn_blocking(move || job_scheduler.delete_job(&request.id))
+        .await
+        .unwrap()
+}
+
+/// Handler for /create_workflow endpoint
+/// Creates tasks of workflow and returns id of workflow with
+/// ids of tasks by their names. Tasks are put into queue once
+/// all their dependencies succeed.
+pub async fn create_workflow(
+    request: CreateWorkflowRequest,
+    workflow_scheduler: Arc<WorkflowScheduler>,
+) -> Result<CreateWorkflowResponse, WorkflowError> {
+    workflow_scheduler.create_workflow(request).await
+}
+
+/// Handler for /get_workflow endpoint
+/// Returns aggregate status of workflow and statuses of its tasks
+pub async fn get_workflow(
+    request: GetWorkflowRequest,
+    workflow_scheduler: Arc<WorkflowScheduler>,
+) -> Result<GetWorkflowResponse, WorkflowError> {
+    task::spawn_blocking(move || workflow_scheduler.get_workflow(&request.id))
+        .await
+        .unwrap()
+}
+
 #[cfg(test)]
 mod test_create_task {
     use crate::server::handlers::create_task;
@@ -77,8 +950,7 @@ mod test_create_task {
     async fn test_create_python_task() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
         let task_status = TaskStatus::new();
 
         let python_code = "print('Hello, world!')".to_string();
@@ -91,9 +963,9 @@ mod test_create_task {
                 .await
                 .unwrap();
         let id = create_task_response.id;
-        let status = task_status.task_status_chashmap.get(&id);
+        let status = task_status.task_store.get(&id).unwrap();
 
-        assert_eq!(status.is_some(), true);
+        assert!(status.is_some());
 
         let get_status_response = status.unwrap();
         assert_eq!(get_status_response.status, TaskStatusEnum::WAIT);
@@ -103,8 +975,7 @@ mod test_create_task {
     async fn test_create_binary_task() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
         let task_status = TaskStatus::new();
 
         let base64_encoded_file = BASE64_STANDARD.encode("echo Hello, world!");
@@ -118,9 +989,9 @@ mod test_create_task {
                 .await
                 .unwrap();
         let id = create_task_response.id;
-        let status = task_status.task_status_chashmap.get(&id);
+        let status = task_status.task_store.get(&id).unwrap();
 
-        assert_eq!(status.is_some(), true);
+        assert!(status.is_some());
 
         let get_status_response = status.unwrap();
         assert_eq!(get_status_response.status, TaskStatusEnum::WAIT);
@@ -135,14 +1006,12 @@ mod test_get_status {
     use crate::server::server::TaskStatus;
     use crate::worker_pool::worker_pool::WorkerPool;
     use std::sync::Arc;
-    use tokio::sync::Mutex;
 
     #[tokio::test]
     async fn test_get_status_of_python_task() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
         let task_status = TaskStatus::new();
         let task_status_clone = task_status.clone();
 
@@ -156,11 +1025,14 @@ mod test_get_status {
                 .await
                 .unwrap();
         let id = create_task_response.id;
-        let status = task_status.task_status_chashmap.get(&id);
+        let status = task_status.task_store.get(&id).unwrap();
 
-        assert_eq!(status.is_some(), true);
+        assert!(status.is_some());
 
-        let get_status_request = GetStatusRequest { id };
+        let get_status_request = GetStatusRequest {
+            id,
+            include_events: false,
+        };
         let result = get_status(get_status_request, task_status_clone)
             .await
             .unwrap();
@@ -174,7 +1046,10 @@ mod test_get_status {
         let task_status_clone = task_status.clone();
         let id = "random-UUID".to_string();
 
-        let get_status_request = GetStatusRequest { id };
+        let get_status_request = GetStatusRequest {
+            id,
+            include_events: false,
+        };
         let result = get_status(get_status_request, task_status_clone)
             .await
             .unwrap();
@@ -196,8 +1071,7 @@ mod test_get_task_count {
     async fn test_get_queue_count_of_one_task() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
         let task_status = TaskStatus::new();
 
         let python_code = "print('Hello, world!')".to_string();
@@ -213,19 +1087,49 @@ mod test_get_task_count {
         .await
         .unwrap();
         let id = create_task_response.id;
-        let status = task_status.task_status_chashmap.get(&id);
-        assert_eq!(status.is_some(), true);
+        let status = task_status.task_store.get(&id).unwrap();
+        assert!(status.is_some());
 
         let result = get_task_count(worker_pool).await.unwrap();
         assert_eq!(result.tasks, 1);
     }
 
+    #[tokio::test]
+    async fn test_get_queue_count_by_client() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        for client_id in ["first", "first", "second"] {
+            let mut create_task_request =
+                CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+            create_task_request.client_id = Some(client_id.to_string());
+
+            let create_task_response = create_task(
+                create_task_request,
+                worker_pool.clone(),
+                task_status.clone(),
+            )
+            .await
+            .unwrap();
+            let status = task_status
+                .get_status_by_id(&create_task_response.id)
+                .unwrap();
+            assert_eq!(status.meta.client_id, Some(client_id.to_string()));
+        }
+
+        let result = get_task_count(worker_pool).await.unwrap();
+        assert_eq!(result.tasks, 3);
+        assert_eq!(result.clients["first"], 2);
+        assert_eq!(result.clients["second"], 1);
+    }
+
     #[tokio::test]
     async fn test_get_queue_count_with_no_task() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
 
         let result = get_task_count(worker_pool).await.unwrap();
         assert_eq!(result.tasks, 0);
@@ -235,8 +1139,7 @@ mod test_get_task_count {
     async fn test_get_queue_count_with_many_tasks() {
         let workers_count = 4;
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
 
         let task_status = TaskStatus::new();
 
@@ -279,3 +1182,1639 @@ mod test_get_task_count {
         assert_eq!(result.tasks, 3);
     }
 }
+
+#[cfg(test)]
+mod test_idempotency_key {
+    use crate::idempotency::idempotency::IdempotencyKeys;
+    use crate::server::handlers::{create_task_once, CreateTaskError};
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_create_task_once() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+        let idempotency_keys = Arc::new(IdempotencyKeys::new());
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        create_task_request.idempotency_key = Some("key".to_string());
+
+        let first_response = create_task_once(
+            create_task_request.clone(),
+            idempotency_keys.clone(),
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap();
+        let second_response = create_task_once(
+            create_task_request.clone(),
+            idempotency_keys.clone(),
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(first_response.id, second_response.id);
+        assert_eq!(worker_pool.get_task_amount(), 1);
+
+        create_task_request.file = "print(2)".to_string();
+        let result = create_task_once(
+            create_task_request,
+            idempotency_keys,
+            worker_pool.clone(),
+            task_status,
+        )
+        .await;
+        assert!(matches!(result, Err(CreateTaskError::IdempotencyKeyReused)));
+        assert_eq!(worker_pool.get_task_amount(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_batch {
+    use crate::idempotency::idempotency::IdempotencyKeys;
+    use crate::server::handlers::{create_task_batch, get_status_batch, MAX_BATCH_SIZE};
+    use crate::server::models::requests::{
+        CreateTaskBatchRequest, CreateTaskRequest, GetStatusBatchRequest, TaskType,
+    };
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_create_task_batch() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+        let idempotency_keys = Arc::new(IdempotencyKeys::new());
+
+        let valid_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut invalid_request = valid_request.clone();
+        invalid_request.delay_ms = Some(100);
+        invalid_request.run_at = Some("2024-11-10T03:00:00Z".to_string());
+
+        let request = CreateTaskBatchRequest {
+            tasks: vec![valid_request.clone(), invalid_request, valid_request],
+        };
+        let response = create_task_batch(
+            request,
+            idempotency_keys,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.tasks.len(), 3);
+        assert!(response.tasks[0].id.is_some());
+        assert!(response.tasks[1].id.is_none());
+        assert!(response.tasks[1].error.is_some());
+        assert!(response.tasks[2].id.is_some());
+        assert_eq!(worker_pool.get_task_amount(), 2);
+
+        let ids = vec![
+            response.tasks[0].id.clone().unwrap(),
+            "random-UUID".to_string(),
+        ];
+        let response = get_status_batch(GetStatusBatchRequest { ids }, task_status)
+            .await
+            .unwrap();
+        assert_eq!(response.tasks.len(), 2);
+        assert_eq!(
+            response.tasks["random-UUID"].status,
+            TaskStatusEnum::NOTEXIST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_limit() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+        let idempotency_keys = Arc::new(IdempotencyKeys::new());
+
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let request = CreateTaskBatchRequest {
+            tasks: vec![create_task_request; MAX_BATCH_SIZE + 1],
+        };
+        let result = create_task_batch(
+            request,
+            idempotency_keys,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(worker_pool.get_task_amount(), 0);
+
+        let ids = vec!["random-UUID".to_string(); MAX_BATCH_SIZE + 1];
+        let result = get_status_batch(GetStatusBatchRequest { ids }, task_status).await;
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_queues {
+    use crate::file_executer::file_executer::available_cpus;
+    use crate::server::handlers::{create_task, get_task_count};
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::concurrency_limits::ConcurrencyLimit;
+    use crate::worker_pool::worker_pool::{QueueConfig, WorkerPool};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_tasks_are_routed_to_queues() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        worker_pool.add_queue(QueueConfig::new("heavy", 0));
+        worker_pool.set_route(TaskType::Bin, "heavy");
+        let task_status = TaskStatus::new();
+
+        let python_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let bin_request = CreateTaskRequest::new(TaskType::Bin, "".to_string(), "".to_string());
+        let mut python_heavy_request = python_request.clone();
+        python_heavy_request.queue = Some("heavy".to_string());
+
+        for request in [python_request, bin_request, python_heavy_request] {
+            create_task(request, worker_pool.clone(), task_status.clone())
+                .await
+                .unwrap();
+        }
+
+        let result = get_task_count(worker_pool.clone()).await.unwrap();
+        assert_eq!(result.tasks, 3);
+        assert_eq!(result.queues["default"].tasks, 1);
+        assert_eq!(result.queues["heavy"].tasks, 2);
+        assert_eq!(result.queues["heavy"].workers, 0);
+
+        let mut unknown_queue_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        unknown_queue_request.queue = Some("unknown".to_string());
+        let result = create_task(unknown_queue_request, worker_pool, task_status).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limits() {
+        let workers_count = 2;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        worker_pool
+            .add_concurrency_limit(ConcurrencyLimit::from_str("label:tool=matlab=1").unwrap());
+        let task_status = TaskStatus::new();
+
+        let mut ids = Vec::new();
+        for label in [Some("matlab"), Some("matlab"), None] {
+            let mut create_task_request = CreateTaskRequest::new(
+                TaskType::Python,
+                "import time; time.sleep(0.3)".to_string(),
+                "".to_string(),
+            );
+            if let Some(label) = label {
+                create_task_request
+                    .labels
+                    .insert("tool".to_string(), label.to_string());
+            }
+            let id = create_task(
+                create_task_request,
+                worker_pool.clone(),
+                task_status.clone(),
+            )
+            .await
+            .unwrap()
+            .id;
+            ids.push(id);
+        }
+
+        // Second labeled task waits for the first one, unlabeled task runs next to it
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let statuses: Vec<TaskStatusEnum> = ids
+            .iter()
+            .map(|id| task_status.get_status_by_id(id).unwrap().status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                TaskStatusEnum::RUNNING,
+                TaskStatusEnum::WAIT,
+                TaskStatusEnum::RUNNING
+            ]
+        );
+
+        let result = get_task_count(worker_pool.clone()).await.unwrap();
+        let limit_info = &result.limits["label:tool=matlab"];
+        assert_eq!(limit_info.limit, 1);
+        assert_eq!(limit_info.running, 1);
+        assert_eq!(limit_info.waiting, 1);
+
+        while !ids.iter().all(|id| {
+            task_status
+                .get_status_by_id(id)
+                .unwrap()
+                .status
+                .is_finished()
+        }) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let result = get_task_count(worker_pool).await.unwrap();
+        assert_eq!(result.limits["label:tool=matlab"].running, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_report_their_cpus() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let cpu = available_cpus().unwrap()[0];
+        worker_pool.set_cpus(vec![cpu]);
+        let task_status = TaskStatus::new();
+
+        let create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        while !task_status
+            .get_status_by_id(&id)
+            .unwrap()
+            .status
+            .is_finished()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = task_status.get_status_by_id(&id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.meta.cpus, vec![cpu]);
+    }
+
+    #[tokio::test]
+    async fn test_task_slots_are_checked() {
+        let workers_count = 2;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        for (slots, is_valid) in [(0, false), (2, true), (3, false)] {
+            let mut create_task_request =
+                CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+            create_task_request.slots = Some(slots);
+            let result = create_task(
+                create_task_request,
+                worker_pool.clone(),
+                task_status.clone(),
+            )
+            .await;
+            assert_eq!(result.is_ok(), is_valid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_defaults() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let mut queue_config = QueueConfig::new("short", 1);
+        queue_config.timeout_ms = Some(200);
+        worker_pool.add_queue(queue_config);
+        let task_status = TaskStatus::new();
+
+        let mut create_task_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "import time; time.sleep(10)".to_string(),
+            "".to_string(),
+        );
+        create_task_request.queue = Some("short".to_string());
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let result = get_task_count(worker_pool.clone()).await.unwrap();
+        assert_eq!(result.queues["short"].idle_workers, 0);
+
+        while !task_status
+            .get_status_by_id(&id)
+            .unwrap()
+            .status
+            .is_finished()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = task_status.get_status_by_id(&id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::ERROR);
+        assert_eq!(
+            status.result.stderr,
+            Some("task exceeded timeout of 200 ms".to_string())
+        );
+
+        // Worker is marked idle right after it records result of task
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let result = get_task_count(worker_pool).await.unwrap();
+        assert_eq!(result.queues["short"].idle_workers, 1);
+    }
+}
+
+#[cfg(test)]
+mod test_retry {
+    use crate::server::handlers::create_task;
+    use crate::server::models::requests::{CreateTaskRequest, FailureKind, RetryPolicy, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use base64::prelude::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn retry_policy(max_attempts: u32, retry_on: Vec<FailureKind>) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff_ms: 50,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: None,
+            retry_on,
+        }
+    }
+
+    async fn wait_for_finish(task_status: &TaskStatus, id: &str) {
+        while !task_status
+            .get_status_by_id(id)
+            .unwrap()
+            .status
+            .is_finished()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_task_succeeds_after_retries() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+        let counter_dir = tempfile::tempdir().unwrap();
+        let counter_path = counter_dir.path().join("counter");
+
+        let python_code = "import os, sys
+path = sys.argv[1]
+count = int(open(path).read()) + 1 if os.path.exists(path) else 1
+open(path, 'w').write(str(count))
+open('attempt.txt', 'w').write(str(count))
+print(count)
+if count < 3:
+    sys.exit(1)"
+            .to_string();
+        let mut create_task_request = CreateTaskRequest::new(
+            TaskType::Python,
+            python_code,
+            counter_path.to_str().unwrap().to_string(),
+        );
+        create_task_request.retry = Some(retry_policy(5, vec![FailureKind::Error]));
+        create_task_request.artifacts = vec!["attempt.txt".to_string()];
+
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+        wait_for_finish(&task_status, &id).await;
+
+        let status = task_status.get_status_by_id(&id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.result.stdout, "3\n");
+        assert_eq!(status.attempts.len(), 3);
+        assert_eq!(status.attempts[0].attempt, 1);
+        assert_eq!(status.attempts[0].status, TaskStatusEnum::ERROR);
+        assert_eq!(status.attempts[0].failure, Some(FailureKind::Error));
+        assert_eq!(status.attempts[1].result.stdout, "2\n");
+        assert_eq!(
+            status.attempts[1].result.artifacts["attempt.txt"],
+            BASE64_STANDARD.encode("2")
+        );
+        assert_eq!(status.attempts[2].status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.attempts[2].failure, None);
+    }
+
+    #[tokio::test]
+    async fn test_task_is_not_retried() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let mut timeout_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "import time; time.sleep(10)".to_string(),
+            "".to_string(),
+        );
+        timeout_request.timeout_ms = Some(100);
+        timeout_request.retry = Some(retry_policy(3, vec![FailureKind::Error]));
+
+        let mut error_request =
+            CreateTaskRequest::new(TaskType::Python, "exit(1)".to_string(), "".to_string());
+        error_request.retry = Some(retry_policy(2, vec![FailureKind::Error]));
+
+        let timeout_id = create_task(timeout_request, worker_pool.clone(), task_status.clone())
+            .await
+            .unwrap()
+            .id;
+        let error_id = create_task(error_request, worker_pool.clone(), task_status.clone())
+            .await
+            .unwrap()
+            .id;
+        wait_for_finish(&task_status, &timeout_id).await;
+        wait_for_finish(&task_status, &error_id).await;
+
+        let status = task_status.get_status_by_id(&timeout_id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::ERROR);
+        assert_eq!(status.attempts.len(), 1);
+        assert_eq!(status.attempts[0].failure, Some(FailureKind::Timeout));
+
+        // Task is not retried after it used all attempts
+        let status = task_status.get_status_by_id(&error_id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::ERROR);
+        assert_eq!(status.attempts.len(), 2);
+
+        let mut invalid_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        invalid_request.retry = Some(retry_policy(0, vec![FailureKind::Error]));
+        assert!(create_task(invalid_request, worker_pool, task_status)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut retry = retry_policy(5, vec![FailureKind::Error]);
+        retry.max_backoff_ms = Some(150);
+
+        assert_eq!(retry.backoff(1), Duration::from_millis(50));
+        assert_eq!(retry.backoff(2), Duration::from_millis(100));
+        assert_eq!(retry.backoff(3), Duration::from_millis(150));
+        assert!(retry.should_retry(4, FailureKind::Error));
+        assert!(!retry.should_retry(5, FailureKind::Error));
+        assert!(!retry.should_retry(1, FailureKind::Signal));
+    }
+}
+
+#[cfg(test)]
+mod test_task_events {
+    use crate::server::handlers::{create_task, get_status, get_task_events, TaskEventsError};
+    use crate::server::models::requests::{
+        CreateTaskRequest, FailureKind, GetStatusRequest, RetryPolicy, TaskType,
+    };
+    use crate::server::models::responses::{TaskEventKind, TaskStatusEnum};
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_events_of_retried_task() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, "exit(1)".to_string(), "".to_string());
+        create_task_request.retry = Some(RetryPolicy {
+            max_attempts: 2,
+            backoff_ms: 50,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: None,
+            retry_on: vec![FailureKind::Error],
+        });
+
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+        while !task_status
+            .get_status_by_id(&id)
+            .unwrap()
+            .status
+            .is_finished()
+        {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let events = get_task_events(id.clone(), task_status.clone())
+            .await
+            .unwrap()
+            .events;
+        let statuses: Vec<TaskStatusEnum> =
+            events.iter().map(|event| event.status.clone()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                TaskStatusEnum::WAIT,
+                TaskStatusEnum::WAIT,
+                TaskStatusEnum::RUNNING,
+                TaskStatusEnum::RUNNING,
+                TaskStatusEnum::SCHEDULED,
+                TaskStatusEnum::WAIT,
+                TaskStatusEnum::WAIT,
+                TaskStatusEnum::RUNNING,
+                TaskStatusEnum::RUNNING,
+                TaskStatusEnum::ERROR,
+            ]
+        );
+
+        let queued = TaskEventKind::Queued {
+            queue: "default".to_string(),
+        };
+        let dispatched = TaskEventKind::Dispatched {
+            queue: "default".to_string(),
+            worker: 0,
+            cpus: Vec::new(),
+        };
+        assert_eq!(events[0].kind, TaskEventKind::Submitted);
+        assert_eq!(events[1].kind, queued);
+        assert_eq!(events[2].kind, dispatched);
+        assert!(matches!(events[3].kind, TaskEventKind::Started { pid } if pid > 0));
+        assert!(matches!(
+            events[4].kind,
+            TaskEventKind::Retrying { attempt: 1, .. }
+        ));
+        assert_eq!(events[5].kind, TaskEventKind::Ready);
+        assert_eq!(events[6].kind, queued);
+        assert_eq!(events[7].kind, dispatched);
+        assert_eq!(
+            events[9].kind,
+            TaskEventKind::Finished {
+                failure: Some(FailureKind::Error)
+            }
+        );
+
+        // Events are returned with status only if they are requested
+        let mut get_status_request = GetStatusRequest {
+            id: id.clone(),
+            include_events: false,
+        };
+        let status = get_status(get_status_request.clone(), task_status.clone())
+            .await
+            .unwrap();
+        assert!(status.events.is_empty());
+
+        get_status_request.include_events = true;
+        let status = get_status(get_status_request, task_status.clone())
+            .await
+            .unwrap();
+        assert_eq!(status.events, events);
+    }
+
+    #[tokio::test]
+    async fn test_events_of_not_exist_task() {
+        let task_status = TaskStatus::new();
+        let id = "fb85a3a0-7e7f-4a20-8ced-65b3b2475144".to_string();
+
+        assert!(matches!(
+            get_task_events(id, task_status).await,
+            Err(TaskEventsError::NotExist)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test_wait_task {
+    use crate::idempotency::idempotency::IdempotencyKeys;
+    use crate::server::handlers::{cancel_task, create_task, create_task_and_wait, wait_task};
+    use crate::server::models::requests::{
+        CancelTaskRequest, CreateTaskRequest, TaskType, WaitTaskRequest,
+    };
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_create_task_and_wait() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+        let idempotency_keys = Arc::new(IdempotencyKeys::new());
+
+        let create_task_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "print('Hello, world!')".to_string(),
+            "".to_string(),
+        );
+        let response = create_task_and_wait(
+            create_task_request,
+            Some(10_000),
+            idempotency_keys,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(response.status.result.stdout, "Hello, world!\n");
+        assert_eq!(
+            task_status.get_status_by_id(&response.id).unwrap().status,
+            TaskStatusEnum::SUCCESS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_waiting_is_woken_by_transition() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let mut create_task_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "print('Hello, world!')".to_string(),
+            "".to_string(),
+        );
+        create_task_request.run_at = Some("2999-01-01T00:00:00Z".to_string());
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let started_at = Instant::now();
+        let wait_task_request = WaitTaskRequest {
+            id: id.clone(),
+            timeout_ms: Some(10_000),
+        };
+        let waiting = tokio::spawn(wait_task(wait_task_request, task_status.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let cancel_task_request = CancelTaskRequest { id };
+        cancel_task(cancel_task_request, worker_pool, task_status)
+            .await
+            .unwrap();
+
+        let status = waiting.await.unwrap().unwrap();
+        assert_eq!(status.status, TaskStatusEnum::CANCELLED);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_times_out() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let create_task_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "print('Hello, world!')".to_string(),
+            "".to_string(),
+        );
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let started_at = Instant::now();
+        let wait_task_request = WaitTaskRequest {
+            id,
+            timeout_ms: Some(200),
+        };
+        let status = wait_task(wait_task_request, task_status.clone())
+            .await
+            .unwrap();
+        assert_eq!(status.status, TaskStatusEnum::WAIT);
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+
+        // Task that doesn't exist is not awaited
+        let started_at = Instant::now();
+        let wait_task_request = WaitTaskRequest {
+            id: "fb85a3a0-7e7f-4a20-8ced-65b3b2475144".to_string(),
+            timeout_ms: Some(10_000),
+        };
+        let status = wait_task(wait_task_request, task_status).await.unwrap();
+        assert_eq!(status.status, TaskStatusEnum::NOTEXIST);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[cfg(test)]
+mod test_expiration {
+    use crate::server::handlers::create_task;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use chrono::prelude::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_expired_tasks_are_not_started() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        worker_pool.set_default_max_wait(Duration::from_millis(100));
+        let task_status = TaskStatus::new();
+
+        let long_request = CreateTaskRequest::new(
+            TaskType::Python,
+            "import time; time.sleep(0.3)".to_string(),
+            "".to_string(),
+        );
+        let mut max_wait_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        max_wait_request.max_wait_ms = Some(100);
+        let default_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut expires_at_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        expires_at_request.expires_at = Some((Utc::now() + Duration::from_secs(60)).to_rfc3339());
+
+        let mut ids = Vec::new();
+        for request in [
+            long_request,
+            max_wait_request,
+            default_request,
+            expires_at_request,
+        ] {
+            let id = create_task(request, worker_pool.clone(), task_status.clone())
+                .await
+                .unwrap()
+                .id;
+            ids.push(id);
+        }
+
+        while !ids.iter().all(|id| {
+            task_status
+                .get_status_by_id(id)
+                .unwrap()
+                .status
+                .is_finished()
+        }) {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let statuses: Vec<TaskStatusEnum> = ids
+            .iter()
+            .map(|id| task_status.get_status_by_id(id).unwrap().status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                TaskStatusEnum::SUCCESS,
+                TaskStatusEnum::EXPIRED,
+                TaskStatusEnum::EXPIRED,
+                TaskStatusEnum::SUCCESS
+            ]
+        );
+
+        let expired_status = task_status.get_status_by_id(&ids[1]).unwrap();
+        assert!(expired_status.meta.started_at.is_none());
+        assert!(expired_status.meta.finished_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_expiration() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let mut both_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        both_request.expires_at = Some(Utc::now().to_rfc3339());
+        both_request.max_wait_ms = Some(100);
+        let mut invalid_time_request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        invalid_time_request.expires_at = Some("tomorrow".to_string());
+
+        for request in [both_request, invalid_time_request] {
+            let result = create_task(request, worker_pool.clone(), task_status.clone()).await;
+            assert!(result.is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_list_tasks {
+    use crate::server::handlers::{create_task, list_tasks};
+    use crate::server::models::requests::{
+        CreateTaskRequest, ListTasksRequest, SortOrder, TaskSortField, TaskType,
+    };
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use chrono::prelude::*;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    /// Creates waiting tasks: python tasks of team-a and bin tasks of team-b
+    async fn create_tasks(task_status: &TaskStatus, count: usize) -> Vec<String> {
+        let worker_pool = Arc::new(WorkerPool::new(0));
+
+        let mut ids = Vec::new();
+        for index in 0..count {
+            let (task_type, team) = match index % 2 {
+                0 => (TaskType::Python, "team-a"),
+                _ => (TaskType::Bin, "team-b"),
+            };
+            let mut request = CreateTaskRequest::new(task_type, "".to_string(), "".to_string());
+            request.labels.insert("team".to_string(), team.to_string());
+            request.client_id = Some(team.to_string());
+
+            let id = create_task(request, worker_pool.clone(), task_status.clone())
+                .await
+                .unwrap()
+                .id;
+            ids.push(id);
+        }
+
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let task_status = TaskStatus::new();
+        let ids = create_tasks(&task_status, 4).await;
+        let mut finishing_task_status = task_status.clone();
+        finishing_task_status.finish_running_task(
+            &ids[2],
+            "output".to_string(),
+            None,
+            TaskStatusEnum::SUCCESS,
+            BTreeMap::new(),
+            None,
+        );
+
+        let request = ListTasksRequest {
+            task_type: Some(TaskType::Python),
+            order: SortOrder::Asc,
+            ..ListTasksRequest::default()
+        };
+        let response = list_tasks(request, task_status.clone()).await.unwrap();
+        let listed_ids: Vec<String> = response.tasks.iter().map(|task| task.id.clone()).collect();
+        assert_eq!(listed_ids, vec![ids[0].clone(), ids[2].clone()]);
+        assert_eq!(response.tasks[0].status.meta.labels["team"], "team-a");
+        assert!(response.next_cursor.is_none());
+
+        let request = ListTasksRequest {
+            status: vec![TaskStatusEnum::SUCCESS],
+            labels: BTreeMap::from([("team".to_string(), "team-a".to_string())]),
+            client_id: Some("team-a".to_string()),
+            finished_after: Some("2000-01-01T00:00:00Z".to_string()),
+            ..ListTasksRequest::default()
+        };
+        let response = list_tasks(request.clone(), task_status.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.tasks.len(), 1);
+        assert_eq!(response.tasks[0].status.result.stdout, "output");
+
+        let request = ListTasksRequest {
+            omit_output: true,
+            ..request
+        };
+        let response = list_tasks(request, task_status.clone()).await.unwrap();
+        assert_eq!(response.tasks[0].status.result.stdout, "");
+
+        let request = ListTasksRequest {
+            client_id: Some("team-b".to_string()),
+            created_before: Some("2000-01-01T00:00:00Z".to_string()),
+            ..ListTasksRequest::default()
+        };
+        let response = list_tasks(request, task_status.clone()).await.unwrap();
+        assert!(response.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pagination() {
+        let task_status = TaskStatus::new();
+        let ids = create_tasks(&task_status, 5).await;
+
+        for order in [SortOrder::Asc, SortOrder::Desc] {
+            let mut listed_ids = Vec::new();
+            let mut cursor = None;
+            loop {
+                let request = ListTasksRequest {
+                    sort_by: TaskSortField::CreatedAt,
+                    order,
+                    limit: Some(2),
+                    cursor,
+                    ..ListTasksRequest::default()
+                };
+                let response = list_tasks(request, task_status.clone()).await.unwrap();
+                assert!(response.tasks.len() <= 2);
+                listed_ids.extend(response.tasks.into_iter().map(|task| task.id));
+
+                cursor = response.next_cursor;
+                if cursor.is_none() {
+                    break;
+                }
+            }
+
+            // Tasks created in same instant are sorted by id
+            let mut expected_ids = ids.clone();
+            let created_at = |id: &String| {
+                let created_at = task_status.get_status_by_id(id).unwrap().meta.created_at;
+                created_at.parse::<DateTime<Utc>>().unwrap()
+            };
+            expected_ids.sort_by_key(|id| (created_at(id), id.clone()));
+            if order == SortOrder::Desc {
+                expected_ids.reverse();
+            }
+            assert_eq!(listed_ids, expected_ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request() {
+        let task_status = TaskStatus::new();
+
+        for request in [
+            ListTasksRequest {
+                cursor: Some("broken".to_string()),
+                ..ListTasksRequest::default()
+            },
+            ListTasksRequest {
+                limit: Some(0),
+                ..ListTasksRequest::default()
+            },
+            ListTasksRequest {
+                created_after: Some("yesterday".to_string()),
+                ..ListTasksRequest::default()
+            },
+        ] {
+            assert!(list_tasks(request, task_status.clone()).await.is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_task_metadata {
+    use crate::server::handlers::{create_task, get_status, get_task_metrics, list_tasks};
+    use crate::server::models::requests::{
+        CreateTaskRequest, GetStatusRequest, GetTaskMetricsRequest, ListTasksRequest, TaskType,
+        MAX_LABELS, MAX_LABEL_LENGTH, MAX_METADATA_BYTES,
+    };
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    /// Creates waiting task of given student and problem
+    async fn create_submission(task_status: &TaskStatus, student: &str, problem: u64) -> String {
+        let worker_pool = Arc::new(WorkerPool::new(0));
+        let mut request = CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string());
+        request
+            .labels
+            .insert("student".to_string(), student.to_string());
+        request.metadata = Some(json!({"problem": problem, "attempt": {"number": 1}}));
+
+        create_task(request, worker_pool, task_status.clone())
+            .await
+            .unwrap()
+            .id
+    }
+
+    #[tokio::test]
+    async fn test_labels_and_metadata_are_echoed() {
+        let task_status = TaskStatus::new();
+        let id = create_submission(&task_status, "alice", 1).await;
+
+        let request = GetStatusRequest {
+            id,
+            include_events: false,
+        };
+        let response = get_status(request, task_status).await.unwrap();
+        assert_eq!(response.meta.labels["student"], "alice");
+        assert_eq!(
+            response.meta.metadata,
+            Some(json!({"problem": 1, "attempt": {"number": 1}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_metrics() {
+        let task_status = TaskStatus::new();
+        let alice_first = create_submission(&task_status, "alice", 1).await;
+        create_submission(&task_status, "alice", 2).await;
+        create_submission(&task_status, "bob", 1).await;
+        let mut finishing_task_status = task_status.clone();
+        finishing_task_status.start_running_task(&alice_first, "default", 0, vec![0]);
+        finishing_task_status.finish_running_task(
+            &alice_first,
+            "".to_string(),
+            None,
+            TaskStatusEnum::SUCCESS,
+            BTreeMap::new(),
+            None,
+        );
+
+        let request = ListTasksRequest {
+            labels: BTreeMap::from([("student".to_string(), "alice".to_string())]),
+            metadata: Some(json!({"problem": 1})),
+            ..ListTasksRequest::default()
+        };
+        let response = list_tasks(request, task_status.clone()).await.unwrap();
+        assert_eq!(response.tasks.len(), 1);
+        assert_eq!(response.tasks[0].id, alice_first);
+
+        let request = ListTasksRequest {
+            metadata: Some(json!({"attempt": {"number": 2}})),
+            ..ListTasksRequest::default()
+        };
+        let response = list_tasks(request, task_status.clone()).await.unwrap();
+        assert!(response.tasks.is_empty());
+
+        let request = GetTaskMetricsRequest {
+            labels: BTreeMap::from([("student".to_string(), "alice".to_string())]),
+            ..GetTaskMetricsRequest::default()
+        };
+        let response = get_task_metrics(request, task_status.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.tasks, 2);
+        assert_eq!(response.statuses[&TaskStatusEnum::SUCCESS], 1);
+        assert_eq!(response.statuses[&TaskStatusEnum::WAIT], 1);
+        assert!(response.average_duration_ms.is_some());
+
+        let request = GetTaskMetricsRequest {
+            metadata: Some(json!({"problem": 1})),
+            ..GetTaskMetricsRequest::default()
+        };
+        let response = get_task_metrics(request, task_status).await.unwrap();
+        assert_eq!(response.tasks, 2);
+    }
+
+    #[tokio::test]
+    async fn test_size_limits() {
+        let worker_pool = Arc::new(WorkerPool::new(0));
+        let task_status = TaskStatus::new();
+        let new_request =
+            || CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string());
+
+        let mut many_labels_request = new_request();
+        for index in 0..=MAX_LABELS {
+            many_labels_request
+                .labels
+                .insert(format!("label-{}", index), "".to_string());
+        }
+        let mut long_label_request = new_request();
+        long_label_request
+            .labels
+            .insert("student".to_string(), "a".repeat(MAX_LABEL_LENGTH + 1));
+        let mut empty_label_request = new_request();
+        empty_label_request
+            .labels
+            .insert("".to_string(), "alice".to_string());
+        let mut large_metadata_request = new_request();
+        large_metadata_request.metadata = Some(json!("a".repeat(MAX_METADATA_BYTES)));
+
+        for request in [
+            many_labels_request,
+            long_label_request,
+            empty_label_request,
+            large_metadata_request,
+        ] {
+            let result = create_task(request, worker_pool.clone(), task_status.clone()).await;
+            assert!(result.is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_retention {
+    use crate::journal::journal::Journal;
+    use crate::retention::retention::RetentionPolicy;
+    use crate::server::handlers::create_task;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::server::server::TaskStatus;
+    use crate::task_store::sqlite_task_store::SqliteTaskStore;
+    use crate::task_store::task_store::TaskRecord;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_least_recently_used_tasks_are_evicted() {
+        let workers_count = 1;
+
+        let directory = tempfile::tempdir().unwrap();
+        let journal = Arc::new(Journal::open(directory.path().join("journal.jsonl")).unwrap());
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::with_journal(journal.clone());
+        task_status.set_retention_policy(RetentionPolicy {
+            max_finished: Some(2),
+            ..RetentionPolicy::default()
+        });
+
+        let mut ids: Vec<String> = Vec::new();
+        for output in ["first", "second", "third"] {
+            let request = CreateTaskRequest::new(
+                TaskType::Python,
+                format!("print('{}')", output),
+                "".to_string(),
+            );
+            let id = create_task(request, worker_pool.clone(), task_status.clone())
+                .await
+                .unwrap()
+                .id;
+            while !task_status
+                .get_status_by_id(&id)
+                .unwrap()
+                .status
+                .is_finished()
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            // First task is used after second one, so second one is evicted
+            if let Some(first_id) = ids.first() {
+                task_status.get_status_by_id(first_id).unwrap();
+            }
+            ids.push(id);
+        }
+
+        let statuses: Vec<TaskStatusEnum> = ids
+            .iter()
+            .map(|id| task_status.get_status_by_id(id).unwrap().status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                TaskStatusEnum::SUCCESS,
+                TaskStatusEnum::EVICTED,
+                TaskStatusEnum::SUCCESS
+            ]
+        );
+        assert_eq!(
+            task_status
+                .get_status_by_id(&Uuid::new_v4().to_string())
+                .unwrap()
+                .status,
+            TaskStatusEnum::NOTEXIST
+        );
+
+        // Evicted task is still reported as evicted after restart
+        let restored_task_status = TaskStatus::new();
+        for record in journal.replay().unwrap() {
+            restored_task_status.restore_task(&record).unwrap();
+        }
+        assert_eq!(
+            restored_task_status
+                .get_status_by_id(&ids[1])
+                .unwrap()
+                .status,
+            TaskStatusEnum::EVICTED
+        );
+        assert_eq!(
+            restored_task_status
+                .get_status_by_id(&ids[2])
+                .unwrap()
+                .result
+                .stdout,
+            "third\n"
+        );
+    }
+
+    #[test]
+    fn test_persistent_store_keeps_evicted_task_marker() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("tasks.sqlite");
+        let task_status = TaskStatus::with_store(Arc::new(SqliteTaskStore::open(&path).unwrap()));
+        task_status.set_retention_policy(RetentionPolicy {
+            max_finished: Some(0),
+            ..RetentionPolicy::default()
+        });
+
+        let mut status = GetStatusResponse::new_utc_status();
+        status.status = TaskStatusEnum::SUCCESS;
+        status.result.stdout = "1".to_string();
+        let record = TaskRecord {
+            id: Uuid::new_v4().to_string(),
+            request: CreateTaskRequest::new(
+                TaskType::Python,
+                "print(1)".to_string(),
+                "".to_string(),
+            ),
+            status,
+        };
+        task_status.restore_task(&record).unwrap();
+        drop(task_status);
+
+        // Other server (or same one after restart) sees marker of evicted task
+        let task_status = TaskStatus::with_store(Arc::new(SqliteTaskStore::open(&path).unwrap()));
+        let status = task_status.get_status_by_id(&record.id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::EVICTED);
+        assert_eq!(status.result.stdout, "");
+        assert_eq!(
+            task_status
+                .get_task(&record.id)
+                .unwrap()
+                .unwrap()
+                .request
+                .file,
+            ""
+        );
+        assert!(task_status.list_tasks().unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_shutdown {
+    use crate::server::handlers::create_task;
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_create_task_after_shutdown() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        worker_pool.shutdown(Duration::from_secs(1)).await;
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let create_task_request = CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+
+        let result = create_task(create_task_request, worker_pool, task_status).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_queued_and_killed_tasks() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "import time; time.sleep(30)".to_string();
+        let arguments = "".to_string();
+        let create_task_request = CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        let running_id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let create_task_request = CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        let queued_id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        while task_status.get_status_by_id(&running_id).unwrap().status != TaskStatusEnum::RUNNING {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        worker_pool.shutdown(Duration::from_millis(200)).await;
+
+        let running_status = task_status.get_status_by_id(&running_id).unwrap();
+        assert_eq!(running_status.status, TaskStatusEnum::CANCELLED);
+        assert!(running_status.meta.finished_at.is_some());
+
+        let queued_status = task_status.get_status_by_id(&queued_id).unwrap();
+        assert_eq!(queued_status.status, TaskStatusEnum::CANCELLED);
+        assert!(queued_status.meta.started_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_running_task() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "import time; time.sleep(0.2); print('done')".to_string();
+        let arguments = "".to_string();
+        let create_task_request = CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        while task_status.get_status_by_id(&id).unwrap().status != TaskStatusEnum::RUNNING {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        worker_pool.shutdown(Duration::from_secs(10)).await;
+
+        let status = task_status.get_status_by_id(&id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(status.result.stdout, "done\n");
+    }
+}
+
+#[cfg(test)]
+mod test_scheduled_task {
+    use crate::server::handlers::{cancel_task, create_task, get_scheduled_tasks};
+    use crate::server::models::requests::{CancelTaskRequest, CreateTaskRequest, TaskType};
+    use crate::server::models::responses::TaskStatusEnum;
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_delayed_task_runs_later() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.delay_ms = Some(200);
+
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let status = task_status.get_status_by_id(&id).unwrap();
+        assert_eq!(status.status, TaskStatusEnum::SCHEDULED);
+        assert!(status.meta.scheduled_for.is_some());
+
+        let scheduled_tasks = get_scheduled_tasks(worker_pool.clone()).await.unwrap();
+        assert_eq!(scheduled_tasks.tasks.len(), 1);
+        assert_eq!(scheduled_tasks.tasks[0].id, id);
+
+        while task_status.get_status_by_id(&id).unwrap().status != TaskStatusEnum::SUCCESS {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let scheduled_tasks = get_scheduled_tasks(worker_pool).await.unwrap();
+        assert!(scheduled_tasks.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_scheduled_task() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.run_at = Some("2999-01-01T00:00:00Z".to_string());
+
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let cancel_task_request = CancelTaskRequest { id: id.clone() };
+        let result = cancel_task(
+            cancel_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.status, TaskStatusEnum::CANCELLED);
+
+        let scheduled_tasks = get_scheduled_tasks(worker_pool.clone()).await.unwrap();
+        assert!(scheduled_tasks.tasks.is_empty());
+
+        let cancel_task_request = CancelTaskRequest { id };
+        let result = cancel_task(cancel_task_request, worker_pool, task_status).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_waiting_task() {
+        let workers_count = 0;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let create_task_request = CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        let id = create_task(
+            create_task_request,
+            worker_pool.clone(),
+            task_status.clone(),
+        )
+        .await
+        .unwrap()
+        .id;
+
+        let cancel_task_request = CancelTaskRequest { id };
+        let result = cancel_task(cancel_task_request, worker_pool, task_status)
+            .await
+            .unwrap();
+        assert_eq!(result.status, TaskStatusEnum::CANCELLED);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_not_exist_task() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let cancel_task_request = CancelTaskRequest {
+            id: "random-UUID".to_string(),
+        };
+        let result = cancel_task(cancel_task_request, worker_pool, task_status).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_run_at() {
+        let workers_count = 1;
+
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        let python_code = "print('Hello, world!')".to_string();
+        let arguments = "".to_string();
+        let mut create_task_request =
+            CreateTaskRequest::new(TaskType::Python, python_code, arguments);
+        create_task_request.run_at = Some("tomorrow".to_string());
+
+        let result = create_task(create_task_request, worker_pool, task_status).await;
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_task_store_errors {
+    use crate::server::handlers::{
+        cancel_task, create_task, get_status, CancelTaskError, CreateTaskError,
+    };
+    use crate::server::models::requests::{
+        CancelTaskRequest, CreateTaskRequest, GetStatusRequest, TaskType,
+    };
+    use crate::server::models::responses::GetStatusResponse;
+    use crate::server::server::TaskStatus;
+    use crate::task_store::task_store::{
+        IdempotencyKeyRecord, TaskRecord, TaskStore, TaskStoreError,
+    };
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use std::sync::Arc;
+
+    /// Store whose database can't be reached
+    struct UnreachableTaskStore;
+
+    fn unreachable() -> TaskStoreError {
+        TaskStoreError("database is unreachable".to_string())
+    }
+
+    impl TaskStore for UnreachableTaskStore {
+        fn create(
+            &self,
+            _: &str,
+            _: &CreateTaskRequest,
+            _: &GetStatusResponse,
+        ) -> Result<(), TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn transition(
+            &self,
+            _: &str,
+            _: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+        ) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn get(&self, _: &str) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn get_record(&self, _: &str) -> Result<Option<TaskRecord>, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn list(&self) -> Result<Vec<TaskRecord>, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn delete(&self, _: &str) -> Result<bool, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn put_idempotency_key(&self, _: &IdempotencyKeyRecord) -> Result<(), TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn get_idempotency_key(
+            &self,
+            _: &str,
+            _: &str,
+        ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn purge_idempotency_keys(&self, _: i64) -> Result<(), TaskStoreError> {
+            Err(unreachable())
+        }
+
+        fn is_persistent(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_errors_are_returned() {
+        let worker_pool = Arc::new(WorkerPool::new(1));
+        let task_status = TaskStatus::with_store(Arc::new(UnreachableTaskStore));
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let result = create_task(request, worker_pool.clone(), task_status.clone()).await;
+        assert!(matches!(result, Err(CreateTaskError::Storage(_))));
+
+        let get_status_request = GetStatusRequest {
+            id: "task".to_string(),
+            include_events: false,
+        };
+        assert!(get_status(get_status_request, task_status.clone())
+            .await
+            .is_err());
+
+        let cancel_task_request = CancelTaskRequest {
+            id: "task".to_string(),
+        };
+        let result = cancel_task(cancel_task_request, worker_pool, task_status).await;
+        assert!(matches!(result, Err(CancelTaskError::Storage(_))));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod handlers;
 pub mod models;
 pub mod routes;
+#[allow(clippy::module_inception)]
 pub mod server;
@@ -1,8 +1,15 @@
 pub mod requests {
+    use super::responses::TaskStatusEnum;
+    use crate::export::export::ExportFormat;
+    use base64::prelude::*;
+    use chrono::prelude::*;
+    use clap::ValueEnum;
     use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use std::path::{Component, Path};
 
     /// Enum for task type
-    #[derive(Clone, Serialize, Deserialize, Debug)]
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, ValueEnum)]
     #[serde(rename_all = "lowercase")]
     pub enum TaskType {
         Python,
@@ -19,6 +26,133 @@ pub mod requests {
         pub file: String,
         // Arguments of executable
         pub args: String,
+        /// UTC time (RFC 3339) when task should be put into queue
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub run_at: Option<String>,
+        /// Delay in milliseconds before task is put into queue
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub delay_ms: Option<u64>,
+        /// Data written to stdin of executable
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub stdin: Option<String>,
+        /// Base64 encoded files put into working directory of executable by their relative paths
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub files: BTreeMap<String, String>,
+        /// Relative paths of files that are collected from working directory after execution
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub artifacts: Vec<String>,
+        /// Client that created task, taken from X-Client-Id header if it is set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_id: Option<String>,
+        /// Named queue of task, by default chosen by routing rules of server
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub queue: Option<String>,
+        /// Time in milliseconds after which task is killed, by default taken from queue
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout_ms: Option<u64>,
+        /// Limit of memory (address space) of executable in megabytes, by default taken from queue
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub memory_limit_mb: Option<u64>,
+        /// Key that makes retried requests return the first created task,
+        /// taken from Idempotency-Key header if it is set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub idempotency_key: Option<String>,
+        /// How task is retried if it fails
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub retry: Option<RetryPolicy>,
+        /// UTC time (RFC 3339) after which task is not started anymore
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<String>,
+        /// Time in milliseconds that task can wait in queue before it expires,
+        /// by default taken from server
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_wait_ms: Option<u64>,
+        /// Amount of worker slots (CPUs) that task takes while it is running, 1 by default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub slots: Option<u32>,
+        /// Labels of task, e.g. {"tool": "matlab"}
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+        /// Opaque json of client that is stored with task and returned with its status
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<serde_json::Value>,
+        /// HTTP(S) URL that final status of task is posted to, by default taken from server
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub callback_url: Option<String>,
+    }
+
+    /// Why execution of task failed
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum FailureKind {
+        /// Executable exited with non-zero code
+        Error,
+        /// Task exceeded its timeout
+        Timeout,
+        /// Executable was killed by signal
+        Signal,
+    }
+
+    fn default_retry_on() -> Vec<FailureKind> {
+        vec![
+            FailureKind::Error,
+            FailureKind::Timeout,
+            FailureKind::Signal,
+        ]
+    }
+
+    fn default_backoff_multiplier() -> f64 {
+        2.0
+    }
+
+    /// Policy of retrying failed task
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct RetryPolicy {
+        /// Maximum amount of attempts, including the first one
+        pub max_attempts: u32,
+        /// Delay in milliseconds before second attempt
+        #[serde(default)]
+        pub backoff_ms: u64,
+        /// Multiplier of delay for every next attempt
+        #[serde(default = "default_backoff_multiplier")]
+        pub backoff_multiplier: f64,
+        /// Upper bound of delay in milliseconds
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub max_backoff_ms: Option<u64>,
+        /// Failures after which task is retried (error/timeout/signal), all by default
+        #[serde(default = "default_retry_on")]
+        pub retry_on: Vec<FailureKind>,
+    }
+
+    impl RetryPolicy {
+        /// Checks that policy allows at least one attempt and delays don't decrease
+        pub fn validate(&self) -> Result<(), String> {
+            if self.max_attempts == 0 {
+                return Err("max_attempts must be positive".to_string());
+            }
+            if !self.backoff_multiplier.is_finite() || self.backoff_multiplier < 1.0 {
+                return Err("backoff_multiplier must be at least 1".to_string());
+            }
+
+            Ok(())
+        }
+
+        /// Returns true if task that failed on given attempt (starting from 1) is retried
+        pub fn should_retry(&self, attempt: u32, failure: FailureKind) -> bool {
+            attempt < self.max_attempts && self.retry_on.contains(&failure)
+        }
+
+        /// Returns delay before attempt that follows given one
+        pub fn backoff(&self, attempt: u32) -> std::time::Duration {
+            let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+            let backoff_ms = self.backoff_ms as f64 * self.backoff_multiplier.powi(exponent);
+            let backoff_ms = match self.max_backoff_ms {
+                Some(max_backoff_ms) => backoff_ms.min(max_backoff_ms as f64),
+                None => backoff_ms,
+            };
+
+            std::time::Duration::from_millis(backoff_ms.min(u64::MAX as f64) as u64)
+        }
     }
 
     impl CreateTaskRequest {
@@ -27,8 +161,140 @@ pub mod requests {
                 task_type,
                 file,
                 args,
+                run_at: None,
+                delay_ms: None,
+                stdin: None,
+                files: BTreeMap::new(),
+                artifacts: Vec::new(),
+                client_id: None,
+                queue: None,
+                timeout_ms: None,
+                memory_limit_mb: None,
+                idempotency_key: None,
+                retry: None,
+                expires_at: None,
+                max_wait_ms: None,
+                slots: None,
+                labels: BTreeMap::new(),
+                metadata: None,
+                callback_url: None,
             }
         }
+
+        /// Returns amount of worker slots that task takes
+        pub fn required_slots(&self) -> usize {
+            self.slots.unwrap_or(1) as usize
+        }
+
+        /// Checks that input files and artifacts stay inside working directory,
+        /// input files are base64 encoded, retry and expiration settings are valid,
+        /// labels and metadata don't exceed size limits and callback URL is HTTP(S) URL
+        pub fn validate(&self) -> Result<(), String> {
+            for (path, content) in &self.files {
+                validate_workspace_path(path)?;
+                if BASE64_STANDARD.decode(content).is_err() {
+                    return Err(format!("file {} is not base64 encoded", path));
+                }
+            }
+
+            for path in &self.artifacts {
+                validate_workspace_path(path)?;
+            }
+
+            if let Some(retry) = &self.retry {
+                retry.validate()?;
+            }
+
+            match (&self.expires_at, self.max_wait_ms) {
+                (Some(_), Some(_)) => {
+                    return Err("only one of expires_at and max_wait_ms can be set".to_string())
+                }
+                (Some(expires_at), None) if expires_at.parse::<DateTime<Utc>>().is_err() => {
+                    return Err(format!("expires_at is not a valid time: {}", expires_at))
+                }
+                _ => {}
+            }
+
+            if self.slots == Some(0) {
+                return Err("slots must be positive".to_string());
+            }
+
+            validate_labels(&self.labels)?;
+            if let Some(metadata) = &self.metadata {
+                let metadata_bytes = serde_json::to_string(metadata).unwrap().len();
+                if metadata_bytes > MAX_METADATA_BYTES {
+                    return Err(format!(
+                        "metadata must take at most {} bytes, but it takes {}",
+                        MAX_METADATA_BYTES, metadata_bytes
+                    ));
+                }
+            }
+
+            if let Some(callback_url) = &self.callback_url {
+                validate_callback_url(callback_url)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Maximum amount of labels of task
+    pub const MAX_LABELS: usize = 32;
+
+    /// Maximum length in bytes of label name or value
+    pub const MAX_LABEL_LENGTH: usize = 256;
+
+    /// Maximum size in bytes of metadata of task serialized to json
+    pub const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+    /// Labels are valid if there are not too many of them and their
+    /// names are not empty and names and values are not too long
+    pub fn validate_labels(labels: &BTreeMap<String, String>) -> Result<(), String> {
+        if labels.len() > MAX_LABELS {
+            return Err(format!("task can't have more than {} labels", MAX_LABELS));
+        }
+
+        for (name, value) in labels {
+            if name.is_empty() {
+                return Err("label name can't be empty".to_string());
+            }
+            if name.len() > MAX_LABEL_LENGTH || value.len() > MAX_LABEL_LENGTH {
+                return Err(format!(
+                    "label {} is longer than {} bytes",
+                    name, MAX_LABEL_LENGTH
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Callback URL is valid if it is HTTP or HTTPS URL
+    pub fn validate_callback_url(url: &str) -> Result<(), String> {
+        let is_http =
+            reqwest::Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        if !is_http {
+            return Err(format!("callback url is not a HTTP URL: {}", url));
+        }
+
+        Ok(())
+    }
+
+    /// Path is valid if it is relative and doesn't leave working directory
+    pub fn validate_workspace_path(path: &str) -> Result<(), String> {
+        let components: Vec<Component> = Path::new(path).components().collect();
+        if components.is_empty()
+            || !components
+                .iter()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(format!(
+                "path {} must be relative and stay inside working directory",
+                path
+            ));
+        }
+
+        Ok(())
     }
 
     /// Struct of get status request (GET)
@@ -36,6 +302,277 @@ pub mod requests {
     pub struct GetStatusRequest {
         /// UUID of task
         pub id: String,
+        /// If true, history of task status is returned with status
+        #[serde(default)]
+        pub include_events: bool,
+    }
+
+    /// Struct of wait task request (GET)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct WaitTaskRequest {
+        /// UUID of task
+        pub id: String,
+        /// Maximum time of waiting in milliseconds, server default if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout_ms: Option<u64>,
+    }
+
+    /// Query parameters of create task request
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct CreateTaskQuery {
+        /// If true, response is sent after task is finished and contains its status
+        #[serde(default)]
+        pub wait: bool,
+        /// Maximum time of waiting in milliseconds, server default if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timeout_ms: Option<u64>,
+    }
+
+    /// Query parameters of export tasks request
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct ExportQuery {
+        /// Format of exported tasks (ndjson/tar), ndjson by default
+        #[serde(default)]
+        pub format: ExportFormat,
+    }
+
+    /// Query parameters of task output request
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct TaskOutputQuery {
+        /// Number of attempt whose output is returned, final output if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub attempt: Option<u32>,
+    }
+
+    /// Struct of create task batch request (POST)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct CreateTaskBatchRequest {
+        /// Requests of tasks to create
+        pub tasks: Vec<CreateTaskRequest>,
+    }
+
+    /// Struct of get status batch request (GET)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct GetStatusBatchRequest {
+        /// UUIDs of tasks
+        pub ids: Vec<String>,
+    }
+
+    /// Field that listed tasks are sorted by
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TaskSortField {
+        #[default]
+        CreatedAt,
+        /// Tasks that are not finished go first in ascending order
+        FinishedAt,
+    }
+
+    /// Order of listed tasks
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum SortOrder {
+        Asc,
+        #[default]
+        Desc,
+    }
+
+    /// Struct of list tasks request (GET). All filters are optional,
+    /// task is listed if it matches all of them.
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct ListTasksRequest {
+        /// Statuses of listed tasks, tasks with any status are listed if empty
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub status: Vec<TaskStatusEnum>,
+        /// Type of listed tasks (python/bin)
+        #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+        pub task_type: Option<TaskType>,
+        /// UTC time (RFC 3339), tasks created at or after it are listed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub created_after: Option<String>,
+        /// UTC time (RFC 3339), tasks created before it are listed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub created_before: Option<String>,
+        /// UTC time (RFC 3339), tasks finished at or after it are listed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub finished_after: Option<String>,
+        /// UTC time (RFC 3339), tasks finished before it are listed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub finished_before: Option<String>,
+        /// Labels that listed tasks must have with same values
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+        /// Metadata that listed tasks must match: fields of json object must be in
+        /// metadata of task with same values, other json must be equal to it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<serde_json::Value>,
+        /// Client that created listed tasks
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_id: Option<String>,
+        /// Field that tasks are sorted by (created_at/finished_at)
+        #[serde(default)]
+        pub sort_by: TaskSortField,
+        /// Order of tasks (asc/desc), newest tasks go first by default
+        #[serde(default)]
+        pub order: SortOrder,
+        /// Maximum amount of tasks in response
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub limit: Option<usize>,
+        /// Cursor from previous response, tasks after it are listed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub cursor: Option<String>,
+        /// If true, stdout, stderr and artifacts of tasks are not returned
+        #[serde(default)]
+        pub omit_output: bool,
+    }
+
+    /// Struct of cancel task request (POST)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct CancelTaskRequest {
+        /// UUID of task
+        pub id: String,
+    }
+
+    /// What to do when job fires while its previous task is not finished
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum OverlapPolicy {
+        /// Don't create new task
+        #[default]
+        Skip,
+        /// Create new task as soon as previous task finishes
+        Queue,
+        /// Cancel or kill previous task and create new one
+        Replace,
+    }
+
+    fn default_timezone() -> String {
+        "UTC".to_string()
+    }
+
+    /// Definition of recurring job (body of create job request (POST))
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct JobDefinition {
+        /// Cron expression (with or without seconds field), e.g. "0 3 * * *"
+        pub schedule: String,
+        /// IANA timezone of cron expression, e.g. "Europe/Moscow"
+        #[serde(default = "default_timezone")]
+        pub timezone: String,
+        /// Request of task that is created on every firing
+        pub task: CreateTaskRequest,
+        /// What to do if previous task is not finished (skip/queue/replace)
+        #[serde(default)]
+        pub overlap_policy: OverlapPolicy,
+    }
+
+    /// Struct of update job request (POST)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct UpdateJobRequest {
+        /// UUID of job
+        pub id: String,
+        /// New definition of job
+        #[serde(flatten)]
+        pub definition: JobDefinition,
+    }
+
+    /// Struct of get job request (GET)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct GetJobRequest {
+        /// UUID of job
+        pub id: String,
+    }
+
+    /// Struct of delete job request (POST)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct DeleteJobRequest {
+        /// UUID of job
+        pub id: String,
+    }
+
+    /// Task of workflow with its dependencies
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct WorkflowTaskRequest {
+        /// Name of task, unique inside workflow
+        pub name: String,
+        /// Request of task
+        #[serde(flatten)]
+        pub task: CreateTaskRequest,
+        /// Names of tasks that must succeed before this task is put into queue
+        #[serde(default)]
+        pub depends_on: Vec<String>,
+        /// Outputs of dependencies that are passed to task when it is put into queue
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub inputs: Vec<TaskInput>,
+    }
+
+    /// Output of upstream task
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum OutputSource {
+        Stdout,
+        Stderr,
+        Artifact,
+    }
+
+    /// Where output of upstream task is passed to
+    #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum InputTarget {
+        /// Appended to stdin of task
+        Stdin,
+        /// Replaces arguments of task (trailing newlines are trimmed)
+        Args,
+        /// Written to file in working directory of task
+        File,
+    }
+
+    /// Output of upstream task passed to workflow task at dispatch time
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct TaskInput {
+        /// Name of upstream task, must be in depends_on
+        pub from: String,
+        /// Which output to take (stdout/stderr/artifact)
+        pub source: OutputSource,
+        /// Path of artifact if source is artifact
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub artifact: Option<String>,
+        /// Where to pass output (stdin/args/file)
+        pub target: InputTarget,
+        /// Path of file if target is file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub path: Option<String>,
+    }
+
+    /// Struct of create workflow request (POST)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct CreateWorkflowRequest {
+        /// Tasks of workflow
+        pub tasks: Vec<WorkflowTaskRequest>,
+    }
+
+    /// Struct of get workflow request (GET)
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct GetWorkflowRequest {
+        /// UUID of workflow
+        pub id: String,
+    }
+
+    /// Struct of get task metrics request (GET). Metrics are counted
+    /// over tasks that match all filters, over all tasks if filters are empty.
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    pub struct GetTaskMetricsRequest {
+        /// Type of counted tasks (python/bin)
+        #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+        pub task_type: Option<TaskType>,
+        /// Labels that counted tasks must have with same values
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+        /// Metadata that counted tasks must match, same as in list tasks request
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<serde_json::Value>,
+        /// Client that created counted tasks
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_id: Option<String>,
     }
 
     /// Struct of get task count request (GET)
@@ -44,8 +581,10 @@ pub mod requests {
 }
 
 pub mod responses {
+    use super::requests::{FailureKind, JobDefinition, TaskType};
     use chrono::prelude::*;
     use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
 
     /// Struct of create task response
     #[derive(Serialize, Deserialize)]
@@ -54,25 +593,211 @@ pub mod responses {
         pub id: String,
     }
 
+    /// Struct of create task response for request that waits for task
+    #[derive(Serialize, Deserialize)]
+    pub struct CreateTaskWaitResponse {
+        /// UUID of task
+        pub id: String,
+        /// Status of task, it is not finished if waiting timed out
+        #[serde(flatten)]
+        pub status: GetStatusResponse,
+    }
+
+    /// Result of creating one task of batch: id of created task or error
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct CreateTaskBatchItem {
+        /// UUID of task if it was created
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub id: Option<String>,
+        /// Error message if task wasn't created
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    /// Struct of create task batch response
+    #[derive(Serialize, Deserialize)]
+    pub struct CreateTaskBatchResponse {
+        /// Results in order of requests
+        pub tasks: Vec<CreateTaskBatchItem>,
+    }
+
+    /// Struct of get status batch response
+    #[derive(Serialize, Deserialize)]
+    pub struct GetStatusBatchResponse {
+        /// Statuses of tasks by their ids
+        pub tasks: BTreeMap<String, GetStatusResponse>,
+    }
+
+    /// Task in list of tasks: its id, type and status
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct TaskListItem {
+        /// UUID of task
+        pub id: String,
+        /// Type of task (python/bin)
+        #[serde(rename = "type")]
+        pub task_type: TaskType,
+        /// Status of task, same as in get status response
+        #[serde(flatten)]
+        pub status: GetStatusResponse,
+    }
+
+    /// Struct of task events response
+    #[derive(Serialize, Deserialize)]
+    pub struct TaskEventsResponse {
+        /// UUID of task
+        pub id: String,
+        /// History of task status, oldest first
+        pub events: Vec<TaskEvent>,
+    }
+
+    /// Struct of list tasks response
+    #[derive(Serialize, Deserialize)]
+    pub struct ListTasksResponse {
+        /// Tasks in requested order
+        pub tasks: Vec<TaskListItem>,
+        /// Cursor to get next page, absent on last page
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub next_cursor: Option<String>,
+    }
+
     /// Enum for task status
-    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
     pub enum TaskStatusEnum {
+        SCHEDULED,
+        BLOCKED,
         WAIT,
         RUNNING,
         SUCCESS,
         ERROR,
         NOTEXIST,
+        CANCELLED,
+        SKIPPED,
+        EXPIRED,
+        EVICTED,
+    }
+
+    impl TaskStatusEnum {
+        /// Returns true if task reached its final status
+        pub fn is_finished(&self) -> bool {
+            matches!(
+                self,
+                TaskStatusEnum::SUCCESS
+                    | TaskStatusEnum::ERROR
+                    | TaskStatusEnum::CANCELLED
+                    | TaskStatusEnum::SKIPPED
+                    | TaskStatusEnum::EXPIRED
+                    | TaskStatusEnum::EVICTED
+            )
+        }
     }
 
     /// Struct of get status response
     #[derive(Serialize, Deserialize, Clone)]
     pub struct GetStatusResponse {
-        /// Task status (WAIT/RUNNING/SUCCESS/ERROR/NOTEXIST)
+        /// Task status (SCHEDULED/BLOCKED/WAIT/RUNNING/SUCCESS/ERROR/NOTEXIST/CANCELLED/SKIPPED/EXPIRED/EVICTED)
         pub status: TaskStatusEnum,
         /// Meta information (created_at, started_at, finished_at)
         pub meta: MetaInformation,
         /// Execution result (stdout, stderr)
         pub result: GetStatusResult,
+        /// Finished attempts of task that has retry policy, oldest first
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub attempts: Vec<TaskAttempt>,
+        /// History of task status, oldest first
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub events: Vec<TaskEvent>,
+        /// Attempts to deliver final status of task to its callback URL, oldest first
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub callback_attempts: Vec<CallbackAttempt>,
+    }
+
+    /// One attempt to post final status of task to its callback URL
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct CallbackAttempt {
+        /// Number of attempt, starting from 1
+        pub attempt: u32,
+        /// UTC time of attempt
+        pub at: String,
+        /// True if callback URL replied with success status code
+        pub delivered: bool,
+        /// Status code of reply, absent if request failed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub status_code: Option<u16>,
+        /// Why request failed, absent if callback URL replied
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    /// Event in history of task
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct TaskEvent {
+        /// UTC time of event
+        pub at: String,
+        /// Status of task after event
+        pub status: TaskStatusEnum,
+        /// What happened to task
+        #[serde(flatten)]
+        pub kind: TaskEventKind,
+    }
+
+    /// Kind of task event with its details
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[serde(tag = "event", rename_all = "snake_case")]
+    pub enum TaskEventKind {
+        /// Task was created
+        Submitted,
+        /// Task became ready to run: its scheduled time has come
+        /// or all its dependencies succeeded
+        Ready,
+        /// Task was put into queue
+        Queued { queue: String },
+        /// Worker of queue took task (workers are numbered from 0 in every queue)
+        Dispatched {
+            queue: String,
+            worker: usize,
+            #[serde(default, skip_serializing_if = "Vec::is_empty")]
+            cpus: Vec<usize>,
+        },
+        /// Process of task was spawned
+        Started { pid: u32 },
+        /// Attempt failed and task is scheduled for next attempt
+        Retrying { attempt: u32, retry_at: String },
+        /// Task interrupted by server restart was put back into queue
+        Requeued,
+        /// Task reached final status
+        Finished {
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            failure: Option<FailureKind>,
+        },
+    }
+
+    impl TaskEvent {
+        /// Creates event that happened now to task with given status
+        pub fn new(status: TaskStatusEnum, kind: TaskEventKind) -> TaskEvent {
+            TaskEvent {
+                at: Utc::now().to_string(),
+                status,
+                kind,
+            }
+        }
+    }
+
+    /// One execution of task
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct TaskAttempt {
+        /// Number of attempt, starting from 1
+        pub attempt: u32,
+        /// UTC time of starting attempt
+        pub started_at: String,
+        /// UTC time of finishing attempt
+        pub finished_at: String,
+        /// Status of attempt (SUCCESS/ERROR)
+        pub status: TaskStatusEnum,
+        /// Output of attempt
+        pub result: GetStatusResult,
+        /// Why attempt failed (error/timeout/signal)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub failure: Option<FailureKind>,
     }
 
     impl GetStatusResponse {
@@ -80,40 +805,83 @@ pub mod responses {
         pub fn new_utc_status() -> GetStatusResponse {
             let meta = MetaInformation {
                 created_at: Utc::now().to_string(),
+                scheduled_for: None,
+                job_id: None,
+                workflow_id: None,
+                client_id: None,
+                labels: BTreeMap::new(),
+                metadata: None,
                 started_at: None,
+                cpus: Vec::new(),
                 finished_at: None,
             };
 
             let result = GetStatusResult {
                 stdout: "".to_string(),
                 stderr: None,
+                artifacts: BTreeMap::new(),
+                stdout_ref: None,
+                stderr_ref: None,
             };
 
             GetStatusResponse {
                 status: TaskStatusEnum::WAIT,
                 meta,
                 result,
+                attempts: Vec::new(),
+                events: Vec::new(),
+                callback_attempts: Vec::new(),
             }
         }
 
+        /// Creates new status response for task that will be put into queue at given time
+        pub fn new_scheduled_status(run_at: DateTime<Utc>) -> GetStatusResponse {
+            let mut status = GetStatusResponse::new_utc_status();
+            status.status = TaskStatusEnum::SCHEDULED;
+            status.meta.scheduled_for = Some(run_at.to_string());
+
+            status
+        }
+
         pub fn new_error_status() -> GetStatusResponse {
             let meta = MetaInformation {
                 created_at: Utc::now().to_string(),
+                scheduled_for: None,
+                job_id: None,
+                workflow_id: None,
+                client_id: None,
+                labels: BTreeMap::new(),
+                metadata: None,
                 started_at: None,
+                cpus: Vec::new(),
                 finished_at: None,
             };
 
             let result = GetStatusResult {
                 stdout: "".to_string(),
                 stderr: None,
+                artifacts: BTreeMap::new(),
+                stdout_ref: None,
+                stderr_ref: None,
             };
 
             GetStatusResponse {
                 status: TaskStatusEnum::NOTEXIST,
                 meta,
                 result,
+                attempts: Vec::new(),
+                events: Vec::new(),
+                callback_attempts: Vec::new(),
             }
         }
+
+        /// Creates status response for finished task that was removed by retention policy
+        pub fn new_evicted_status() -> GetStatusResponse {
+            let mut status = GetStatusResponse::new_error_status();
+            status.status = TaskStatusEnum::EVICTED;
+
+            status
+        }
     }
 
     /// Struct of meta information for task
@@ -121,9 +889,30 @@ pub mod responses {
     pub struct MetaInformation {
         /// UTC time of create task
         pub created_at: String,
+        /// UTC time when scheduled task is put into queue
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub scheduled_for: Option<String>,
+        /// UUID of recurring job that created task
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub job_id: Option<String>,
+        /// UUID of workflow that task belongs to
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub workflow_id: Option<String>,
+        /// Client that created task
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client_id: Option<String>,
+        /// Labels of task
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub labels: BTreeMap<String, String>,
+        /// Metadata of task
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<serde_json::Value>,
         /// UTC time of starting task
         #[serde(skip_serializing_if = "Option::is_none")]
         pub started_at: Option<String>,
+        /// CPU cores that task was pinned to
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub cpus: Vec<usize>,
         /// UTC time of finishing task
         #[serde(skip_serializing_if = "Option::is_none")]
         pub finished_at: Option<String>,
@@ -137,6 +926,39 @@ pub mod responses {
         /// Stderr of executable file
         #[serde(skip_serializing_if = "Option::is_none")]
         pub stderr: Option<String>,
+        /// Base64 encoded artifacts collected after execution by their paths
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub artifacts: BTreeMap<String, String>,
+        /// Full stdout if it was too large to keep in task, stdout is its preview then
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub stdout_ref: Option<OutputRef>,
+        /// Full stderr if it was too large to keep in task, stderr is its preview then
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub stderr_ref: Option<OutputRef>,
+    }
+
+    /// Reference to output that is stored in file of output directory
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct OutputRef {
+        /// Size of full output in bytes
+        pub size: u64,
+        /// Path of endpoint that returns full output
+        pub url: String,
+        /// Path of file with output relative to output directory
+        pub file: String,
+    }
+
+    /// Struct of get task metrics response
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct GetTaskMetricsResponse {
+        /// Amount of matching tasks
+        pub tasks: usize,
+        /// Amount of matching tasks with every status that they have
+        pub statuses: BTreeMap<TaskStatusEnum, usize>,
+        /// Average time in milliseconds from start to finish of matching tasks
+        /// that ran and finished, absent if there are no such tasks
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub average_duration_ms: Option<f64>,
     }
 
     /// Struct of get task count response
@@ -144,5 +966,146 @@ pub mod responses {
     pub struct GetTaskCountResponse {
         /// Amount of tasks in queue
         pub tasks: usize,
+        /// Amount of tasks in queue of every client that has them
+        #[serde(default)]
+        pub clients: BTreeMap<String, usize>,
+        /// Information about every named queue
+        #[serde(default)]
+        pub queues: BTreeMap<String, QueueInfo>,
+        /// Information about every concurrency limit by its key
+        #[serde(default)]
+        pub limits: BTreeMap<String, LimitInfo>,
+    }
+
+    /// Information about concurrency limit
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct LimitInfo {
+        /// Maximum amount of running tasks
+        pub limit: usize,
+        /// Amount of running tasks that limit applies to
+        pub running: usize,
+        /// Amount of tasks in queues that limit applies to
+        pub waiting: usize,
+    }
+
+    /// Information about named queue
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    pub struct QueueInfo {
+        /// Amount of tasks in queue
+        pub tasks: usize,
+        /// Amount of workers of queue
+        pub workers: usize,
+        /// Amount of workers that don't execute task now
+        pub idle_workers: usize,
+        /// Amount of slots that are not taken by running tasks
+        #[serde(default)]
+        pub free_slots: usize,
+    }
+
+    /// Information about task that waits for its scheduled time
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct ScheduledTaskInfo {
+        /// UUID of task
+        pub id: String,
+        /// UTC time when task will be put into queue
+        pub run_at: String,
+    }
+
+    /// Struct of get scheduled tasks response
+    #[derive(Serialize, Deserialize)]
+    pub struct GetScheduledTasksResponse {
+        /// Scheduled tasks ordered by time of run
+        pub tasks: Vec<ScheduledTaskInfo>,
+    }
+
+    /// Struct of create job response
+    #[derive(Serialize, Deserialize)]
+    pub struct CreateJobResponse {
+        /// UUID of job
+        pub id: String,
+    }
+
+    /// Task created by one firing of recurring job
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct JobRun {
+        /// UUID of created task
+        pub task_id: String,
+        /// UTC time of firing
+        pub fired_at: String,
+        /// Current status of created task
+        pub status: TaskStatusEnum,
+    }
+
+    /// Struct of get job response
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct GetJobResponse {
+        /// UUID of job
+        pub id: String,
+        /// Definition of job
+        #[serde(flatten)]
+        pub definition: JobDefinition,
+        /// UTC time of next firing
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub next_run_at: Option<String>,
+        /// Last runs of job, newest first
+        pub runs: Vec<JobRun>,
+    }
+
+    /// Struct of get jobs response
+    #[derive(Serialize, Deserialize)]
+    pub struct GetJobsResponse {
+        /// All recurring jobs
+        pub jobs: Vec<GetJobResponse>,
+    }
+
+    /// Struct of create workflow response
+    #[derive(Serialize, Deserialize)]
+    pub struct CreateWorkflowResponse {
+        /// UUID of workflow
+        pub id: String,
+        /// UUIDs of created tasks by their names
+        pub tasks: BTreeMap<String, String>,
+    }
+
+    /// Enum for aggregate workflow status
+    #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+    pub enum WorkflowStatusEnum {
+        /// Some tasks are not finished yet
+        RUNNING,
+        /// All tasks succeeded
+        SUCCESS,
+        /// All tasks are finished and some of them didn't succeed
+        ERROR,
+    }
+
+    /// Status of one task of workflow
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct WorkflowTaskStatus {
+        /// Name of task
+        pub name: String,
+        /// UUID of task
+        pub id: String,
+        /// Current status of task
+        pub status: TaskStatusEnum,
+        /// Names of tasks that task depends on
+        pub depends_on: Vec<String>,
+    }
+
+    /// Struct of get workflow response
+    #[derive(Serialize, Deserialize)]
+    pub struct GetWorkflowResponse {
+        /// UUID of workflow
+        pub id: String,
+        /// Aggregate status of workflow (RUNNING/SUCCESS/ERROR)
+        pub status: WorkflowStatusEnum,
+        /// Tasks of workflow in order of submission
+        pub tasks: Vec<WorkflowTaskStatus>,
+    }
+
+    /// Struct of error response
+    #[derive(Serialize, Deserialize)]
+    pub struct ErrorResponse {
+        /// Error message
+        pub error: String,
     }
 }
@@ -1,27 +1,236 @@
-use super::handlers;
+use super::handlers::{
+    self, BatchError, CancelTaskError, CreateTaskError, ListTasksError, TaskEventsError,
+    TaskOutputError,
+};
+use super::models::requests::{
+    CreateTaskBatchRequest, CreateTaskQuery, CreateTaskRequest, CreateWorkflowRequest, ExportQuery,
+    JobDefinition, TaskOutputQuery, UpdateJobRequest,
+};
+use super::models::responses::ErrorResponse;
 use super::server::{ServerInfo, TaskStatus};
+use crate::export::export::export_reply;
+use crate::idempotency::idempotency::IdempotencyKeys;
+use crate::jobs::jobs::{JobError, JobScheduler};
+use crate::outputs::outputs::{output_reply, OutputStream};
 use crate::worker_pool::worker_pool::WorkerPool;
+use crate::workflows::workflows::{WorkflowError, WorkflowScheduler};
+use serde::Serialize;
+use std::convert::Infallible;
 use std::sync::Arc;
-use warp::{self, Filter};
+use warp::http::StatusCode;
+use warp::{self, Filter, Reply};
 
-/// Warp /create_task endpoint that calls create_task handler
+/// Creates json reply with error message and given status code
+fn error_reply(
+    error: impl ToString,
+    status_code: StatusCode,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    let error_response = ErrorResponse {
+        error: error.to_string(),
+    };
+
+    warp::reply::with_status(warp::reply::json(&error_response), status_code)
+}
+
+/// Creates json reply from result of handler. Errors are replied
+/// with status code chosen by given function.
+fn result_reply<T: Serialize, E: ToString>(
+    result: Result<T, E>,
+    error_status_code: impl Fn(&E) -> StatusCode,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    let reply = match result {
+        Ok(response) => warp::reply::with_status(warp::reply::json(&response), StatusCode::OK),
+        Err(err) => {
+            let status_code = error_status_code(&err);
+            error_reply(err, status_code)
+        }
+    };
+
+    Ok(reply)
+}
+
+/// Header that identifies client for fair scheduling
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+
+/// Header with idempotency key of create task request
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Extracts client id from X-Client-Id header
+fn client_id() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(CLIENT_ID_HEADER)
+}
+
+/// Sets client of task to client from header, if header is set
+fn set_client_id(task_request: &mut CreateTaskRequest, client_id: &Option<String>) {
+    if client_id.is_some() {
+        task_request.client_id = client_id.clone();
+    }
+}
+
+/// Status code of reply for create task errors
+fn create_task_error_status_code(err: &CreateTaskError) -> StatusCode {
+    match err {
+        CreateTaskError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        CreateTaskError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+        CreateTaskError::IdempotencyKeyReused => StatusCode::CONFLICT,
+        CreateTaskError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Warp /create_task endpoint that calls create_task_once handler,
+/// or create_task_and_wait handler if query has wait=true.
+/// Replies with 400 if request is invalid, with 409 if idempotency key
+/// was used with different request and with 503 if server is shutting down.
 fn create_task_route(
+    idempotency_keys: Arc<IdempotencyKeys>,
     worker_pool: Arc<WorkerPool>,
     task_status: TaskStatus,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("create_task")
         .and(warp::post())
+        .and(warp::query::<CreateTaskQuery>())
         .and(warp::body::json())
+        .and(client_id())
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+        .and(warp::any().map(move || idempotency_keys.clone()))
         .and(warp::any().map(move || worker_pool.clone()))
         .and(warp::any().map(move || task_status.clone()))
-        .and_then(|task_request, worker_pool, task_status| async move {
-            handlers::create_task(task_request, worker_pool, task_status)
-                .await
-                .map(|create_task_response| warp::reply::json(&create_task_response))
+        .and_then(
+            |query: CreateTaskQuery,
+             mut task_request: CreateTaskRequest,
+             client_id,
+             idempotency_key: Option<String>,
+             idempotency_keys,
+             worker_pool,
+             task_status| async move {
+                set_client_id(&mut task_request, &client_id);
+                if idempotency_key.is_some() {
+                    task_request.idempotency_key = idempotency_key;
+                }
+                if query.wait {
+                    let result = handlers::create_task_and_wait(
+                        task_request,
+                        query.timeout_ms,
+                        idempotency_keys,
+                        worker_pool,
+                        task_status,
+                    )
+                    .await;
+                    return result_reply(result, create_task_error_status_code);
+                }
+
+                let result = handlers::create_task_once(
+                    task_request,
+                    idempotency_keys,
+                    worker_pool,
+                    task_status,
+                )
+                .await;
+                result_reply(result, create_task_error_status_code)
+            },
+        )
+}
+
+/// Status code of reply for batch errors
+fn batch_error_status_code(err: &BatchError) -> StatusCode {
+    match err {
+        BatchError::TooBig => StatusCode::PAYLOAD_TOO_LARGE,
+        BatchError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        BatchError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Warp /create_task_batch endpoint that calls create_task_batch handler.
+/// Replies with 413 if batch is too big and with 503 if server is shutting down.
+fn create_task_batch_route(
+    idempotency_keys: Arc<IdempotencyKeys>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("create_task_batch")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_id())
+        .and(warp::any().map(move || idempotency_keys.clone()))
+        .and(warp::any().map(move || worker_pool.clone()))
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(
+            |mut create_task_batch_request: CreateTaskBatchRequest,
+             client_id,
+             idempotency_keys,
+             worker_pool,
+             task_status| async move {
+                for task_request in &mut create_task_batch_request.tasks {
+                    set_client_id(task_request, &client_id);
+                }
+                let result = handlers::create_task_batch(
+                    create_task_batch_request,
+                    idempotency_keys,
+                    worker_pool,
+                    task_status,
+                )
+                .await;
+                result_reply(result, batch_error_status_code)
+            },
+        )
+}
+
+/// Warp /get_status_batch endpoint that calls get_status_batch handler.
+/// Replies with 413 if batch is too big.
+fn get_status_batch_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_status_batch")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|get_status_batch_request, task_status| async move {
+            let result = handlers::get_status_batch(get_status_batch_request, task_status).await;
+            result_reply(result, batch_error_status_code)
+        })
+}
+
+/// Warp /list_tasks endpoint that calls list_tasks handler.
+/// Replies with 400 if filters, limit or cursor are invalid.
+fn list_tasks_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("list_tasks")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|list_tasks_request, task_status| async move {
+            let result = handlers::list_tasks(list_tasks_request, task_status).await;
+            result_reply(result, |err| match err {
+                ListTasksError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+                ListTasksError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            })
         })
 }
 
-/// Warp /get_status endpoint that call get_status handler
+/// Warp /cancel_task endpoint that calls cancel_task handler.
+/// Replies with 404 if task doesn't exist and with 409 if it has already started.
+fn cancel_task_route(
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("cancel_task")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || worker_pool.clone()))
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|cancel_task_request, worker_pool, task_status| async move {
+            let result = handlers::cancel_task(cancel_task_request, worker_pool, task_status).await;
+            result_reply(result, |err| match err {
+                CancelTaskError::NotExist => StatusCode::NOT_FOUND,
+                CancelTaskError::AlreadyStarted => StatusCode::CONFLICT,
+                CancelTaskError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            })
+        })
+}
+
+/// Warp /get_status endpoint that call get_status handler.
+/// Replies with 500 if task store fails.
 fn get_status_route(
     task_status: TaskStatus,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -30,9 +239,104 @@ fn get_status_route(
         .and(warp::body::json())
         .and(warp::any().map(move || task_status.clone()))
         .and_then(|get_status_request, task_status| async move {
-            handlers::get_status(get_status_request, task_status)
-                .await
-                .map(|get_status_response| warp::reply::json(&get_status_response))
+            let result = handlers::get_status(get_status_request, task_status).await;
+            result_reply(result, |_| StatusCode::INTERNAL_SERVER_ERROR)
+        })
+}
+
+/// Warp /wait_task endpoint that calls wait_task handler.
+/// Replies with 500 if task store fails.
+fn wait_task_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("wait_task")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|wait_task_request, task_status| async move {
+            let result = handlers::wait_task(wait_task_request, task_status).await;
+            result_reply(result, |_| StatusCode::INTERNAL_SERVER_ERROR)
+        })
+}
+
+/// Warp /tasks/{id}/events endpoint that calls get_task_events handler.
+/// Replies with 404 if task doesn't exist.
+fn task_events_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tasks" / String / "events")
+        .and(warp::get())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|id, task_status| async move {
+            let result = handlers::get_task_events(id, task_status).await;
+            result_reply(result, |err| match err {
+                TaskEventsError::NotExist => StatusCode::NOT_FOUND,
+                TaskEventsError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            })
+        })
+}
+
+/// Warp /tasks/{id}/output/{stream} endpoint that calls get_task_output handler.
+/// Replies with part of output if request has Range header.
+/// Replies with 404 if task, its attempt or file of output doesn't exist.
+fn task_output_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tasks" / String / "output" / OutputStream)
+        .and(warp::get())
+        .and(warp::query::<TaskOutputQuery>())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(
+            |id, stream, query, range: Option<String>, task_status| async move {
+                let reply = match handlers::get_task_output(id, stream, query, task_status).await {
+                    Ok(content) => match output_reply(content, range.as_deref()).await {
+                        Ok(response) => response,
+                        Err(_) => error_reply(TaskOutputError::NotExist, StatusCode::NOT_FOUND)
+                            .into_response(),
+                    },
+                    Err(err) => {
+                        let status_code = match err {
+                            TaskOutputError::NotExist => StatusCode::NOT_FOUND,
+                            TaskOutputError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                        error_reply(err, status_code).into_response()
+                    }
+                };
+
+                Ok::<_, Infallible>(reply)
+            },
+        )
+}
+
+/// Warp /admin/export endpoint that calls export_tasks handler.
+/// Replies with 404 if endpoint is not enabled and with 500 if tasks can't be written.
+fn export_tasks_route(
+    task_status: TaskStatus,
+    enabled: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "export")
+        .and(warp::get())
+        .and(
+            warp::any()
+                .and_then(move || async move {
+                    match enabled {
+                        true => Ok(()),
+                        false => Err(warp::reject::not_found()),
+                    }
+                })
+                .untuple_one(),
+        )
+        .and(warp::query::<ExportQuery>())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|query: ExportQuery, task_status| async move {
+            let format = query.format;
+            let reply = match handlers::export_tasks(query, task_status).await {
+                Ok(file) => export_reply(format, file),
+                Err(err) => error_reply(err, StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+            };
+
+            Ok::<_, Infallible>(reply)
         })
 }
 
@@ -50,14 +354,215 @@ fn get_task_count_route(
         })
 }
 
-/// Handling all routes and users requests
+/// Warp /get_task_metrics endpoint that calls get_task_metrics handler.
+/// Replies with 500 if task store fails.
+fn get_task_metrics_route(
+    task_status: TaskStatus,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_task_metrics")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || task_status.clone()))
+        .and_then(|get_task_metrics_request, task_status| async move {
+            let result = handlers::get_task_metrics(get_task_metrics_request, task_status).await;
+            result_reply(result, |_| StatusCode::INTERNAL_SERVER_ERROR)
+        })
+}
+
+/// Warp /get_scheduled_tasks endpoint that calls get_scheduled_tasks handler
+fn get_scheduled_tasks_route(
+    worker_pool: Arc<WorkerPool>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_scheduled_tasks")
+        .and(warp::get())
+        .and(warp::any().map(move || worker_pool.clone()))
+        .and_then(|worker_pool| async move {
+            handlers::get_scheduled_tasks(worker_pool)
+                .await
+                .map(|get_scheduled_tasks_response| {
+                    warp::reply::json(&get_scheduled_tasks_response)
+                })
+        })
+}
+
+/// Status code of reply for job errors
+fn job_error_status_code(err: &JobError) -> StatusCode {
+    match err {
+        JobError::NotExist => StatusCode::NOT_FOUND,
+        JobError::InvalidJob(_) => StatusCode::BAD_REQUEST,
+        JobError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Warp /create_job endpoint that calls create_job handler.
+/// Replies with 400 if job definition is invalid.
+fn create_job_route(
+    job_scheduler: Arc<JobScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("create_job")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_id())
+        .and(warp::any().map(move || job_scheduler.clone()))
+        .and_then(
+            |mut job_definition: JobDefinition, client_id, job_scheduler| async move {
+                set_client_id(&mut job_definition.task, &client_id);
+                let result = handlers::create_job(job_definition, job_scheduler).await;
+                result_reply(result, job_error_status_code)
+            },
+        )
+}
+
+/// Warp /get_job endpoint that calls get_job handler.
+/// Replies with 404 if job doesn't exist.
+fn get_job_route(
+    job_scheduler: Arc<JobScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_job")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || job_scheduler.clone()))
+        .and_then(|get_job_request, job_scheduler| async move {
+            let result = handlers::get_job(get_job_request, job_scheduler).await;
+            result_reply(result, job_error_status_code)
+        })
+}
+
+/// Warp /get_jobs endpoint that calls get_jobs handler
+fn get_jobs_route(
+    job_scheduler: Arc<JobScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_jobs")
+        .and(warp::get())
+        .and(warp::any().map(move || job_scheduler.clone()))
+        .and_then(|job_scheduler| async move {
+            let result = handlers::get_jobs(job_scheduler).await;
+            result_reply(result, job_error_status_code)
+        })
+}
+
+/// Warp /update_job endpoint that calls update_job handler.
+/// Replies with 404 if job doesn't exist and with 400 if new definition is invalid.
+fn update_job_route(
+    job_scheduler: Arc<JobScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("update_job")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_id())
+        .and(warp::any().map(move || job_scheduler.clone()))
+        .and_then(
+            |mut update_job_request: UpdateJobRequest, client_id, job_scheduler| async move {
+                set_client_id(&mut update_job_request.definition.task, &client_id);
+                let result = handlers::update_job(update_job_request, job_scheduler).await;
+                result_reply(result, job_error_status_code)
+            },
+        )
+}
+
+/// Warp /delete_job endpoint that calls delete_job handler.
+/// Replies with 404 if job doesn't exist.
+fn delete_job_route(
+    job_scheduler: Arc<JobScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("delete_job")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::any().map(move || job_scheduler.clone()))
+        .and_then(|delete_job_request, job_scheduler| async move {
+            let result = handlers::delete_job(delete_job_request, job_scheduler).await;
+            result_reply(result, job_error_status_code)
+        })
+}
+
+/// Status code of reply for workflow errors
+fn workflow_error_status_code(err: &WorkflowError) -> StatusCode {
+    match err {
+        WorkflowError::NotExist => StatusCode::NOT_FOUND,
+        WorkflowError::InvalidWorkflow(_) => StatusCode::BAD_REQUEST,
+        WorkflowError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        WorkflowError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Warp /create_workflow endpoint that calls create_workflow handler.
+/// Replies with 400 if workflow is invalid (e.g. has cycle) and
+/// with 503 if server is shutting down.
+fn create_workflow_route(
+    workflow_scheduler: Arc<WorkflowScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("create_workflow")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_id())
+        .and(warp::any().map(move || workflow_scheduler.clone()))
+        .and_then(
+            |mut create_workflow_request: CreateWorkflowRequest,
+             client_id,
+             workflow_scheduler| async move {
+                for workflow_task in &mut create_workflow_request.tasks {
+                    set_client_id(&mut workflow_task.task, &client_id);
+                }
+                let result =
+                handlers::create_workflow(create_workflow_request, workflow_scheduler).await;
+            result_reply(result, workflow_error_status_code)
+        })
+}
+
+/// Warp /get_workflow endpoint that calls get_workflow handler.
+/// Replies with 404 if workflow doesn't exist.
+fn get_workflow_route(
+    workflow_scheduler: Arc<WorkflowScheduler>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("get_workflow")
+        .and(warp::get())
+        .and(warp::body::json())
+        .and(warp::any().map(move || workflow_scheduler.clone()))
+        .and_then(|get_workflow_request, workflow_scheduler| async move {
+            let result = handlers::get_workflow(get_workflow_request, workflow_scheduler).await;
+            result_reply(result, workflow_error_status_code)
+        })
+}
+
+/// Handling all routes and users requests, /admin/export is served only if admin_export is set
 pub fn routes_handler(
     server_info: ServerInfo,
+    admin_export: bool,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     create_task_route(
+        server_info.idempotency_keys.clone(),
         server_info.worker_pool.clone(),
         server_info.task_status.clone(),
     )
+    .or(cancel_task_route(
+        server_info.worker_pool.clone(),
+        server_info.task_status.clone(),
+    ))
+    .or(create_task_batch_route(
+        server_info.idempotency_keys.clone(),
+        server_info.worker_pool.clone(),
+        server_info.task_status.clone(),
+    ))
     .or(get_status_route(server_info.task_status.clone()))
+    .or(get_status_batch_route(server_info.task_status.clone()))
+    .or(list_tasks_route(server_info.task_status.clone()))
+    .or(wait_task_route(server_info.task_status.clone()))
+    .or(task_events_route(server_info.task_status.clone()))
+    .or(task_output_route(server_info.task_status.clone()))
+    .or(export_tasks_route(
+        server_info.task_status.clone(),
+        admin_export,
+    ))
     .or(get_task_count_route(server_info.worker_pool.clone()))
+    .or(get_task_metrics_route(server_info.task_status.clone()))
+    .or(get_scheduled_tasks_route(server_info.worker_pool.clone()))
+    .or(create_job_route(server_info.job_scheduler.clone()))
+    .or(get_job_route(server_info.job_scheduler.clone()))
+    .or(get_jobs_route(server_info.job_scheduler.clone()))
+    .or(update_job_route(server_info.job_scheduler.clone()))
+    .or(delete_job_route(server_info.job_scheduler.clone()))
+    .or(create_workflow_route(
+        server_info.workflow_scheduler.clone(),
+    ))
+    .or(get_workflow_route(server_info.workflow_scheduler.clone()))
 }
@@ -1,118 +1,1143 @@
-use chashmap::CHashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-use super::models::responses::GetStatusResponse;
+use super::models::requests::{CreateTaskRequest, FailureKind, TaskType};
+use super::models::responses::{
+    CallbackAttempt, GetStatusResponse, GetStatusResult, OutputRef, TaskAttempt, TaskEvent,
+    TaskEventKind,
+};
 use super::routes::routes_handler;
+use crate::callbacks::callbacks::{start_callbacks, CallbackConfig};
+use crate::idempotency::idempotency::IdempotencyKeys;
+use crate::jobs::jobs::JobScheduler;
+use crate::journal::journal::{
+    InterruptedTaskPolicy, Journal, DEFAULT_COMPACTION_INTERVAL_SECONDS,
+};
+use crate::outputs::outputs::OutputStore;
+use crate::retention::retention::{Retention, RetentionPolicy};
 use crate::server::models::responses::TaskStatusEnum;
-use crate::worker_pool::worker_pool::WorkerPool;
+use crate::task_store::task_store::{
+    IdempotencyKeyRecord, MemoryTaskStore, SharedTaskQueue, TaskRecord, TaskStore, TaskStoreError,
+};
+use crate::worker_pool::concurrency_limits::ConcurrencyLimit;
+use crate::worker_pool::worker_pool::{QueueConfig, TaskInfo, WorkerPool};
+use crate::workflows::workflows::WorkflowScheduler;
 
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task::{self, JoinHandle};
 use uuid::Uuid;
-use warp;
 
 use chrono::prelude::*;
 
-/// Task status hashmap for all tasks on server
+/// Default time in seconds that running tasks have to finish on shutdown
+pub const DEFAULT_GRACE_PERIOD_SECONDS: u64 = 30;
+
+/// Time between checks of awaited task in shared store, where other servers can finish it
+const SHARED_STORE_POLL_INTERVAL_MS: u64 = 100;
+
+/// Statuses of all tasks on server kept in task store
 #[derive(Clone)]
 pub struct TaskStatus {
-    task_status_chashmap: Arc<CHashMap<String, GetStatusResponse>>,
+    pub(crate) task_store: Arc<dyn TaskStore>,
+    /// Optional journal that records every submission and status transition
+    journal: Option<Arc<Journal>>,
+    /// Senders that are notified with id of every finished task
+    finished_task_listeners: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
+    /// Senders that are notified with request and final status of every finished task
+    finished_record_listeners: Arc<Mutex<Vec<mpsc::UnboundedSender<TaskRecord>>>>,
+    /// Senders of clients that wait for task to finish by task id
+    task_waiters: Arc<Mutex<HashMap<String, Vec<oneshot::Sender<()>>>>>,
+    /// Decides which finished tasks are evicted
+    retention: Arc<Retention>,
+    /// Optional store of outputs that are too large to keep in task
+    pub(crate) output_store: Option<Arc<OutputStore>>,
 }
 
 impl TaskStatus {
-    /// Create task status struct
+    /// Create task status struct that keeps tasks in memory
     pub fn new() -> TaskStatus {
+        TaskStatus::with_store(Arc::new(MemoryTaskStore::new()))
+    }
+
+    /// Create task status struct that keeps tasks in given store
+    pub fn with_store(task_store: Arc<dyn TaskStore>) -> TaskStatus {
+        TaskStatus {
+            task_store,
+            journal: None,
+            finished_task_listeners: Arc::new(Mutex::new(Vec::new())),
+            finished_record_listeners: Arc::new(Mutex::new(Vec::new())),
+            task_waiters: Arc::new(Mutex::new(HashMap::new())),
+            retention: Arc::new(Retention::new()),
+            output_store: None,
+        }
+    }
+
+    /// Create task status struct that writes all changes to journal
+    pub fn with_journal(journal: Arc<Journal>) -> TaskStatus {
         TaskStatus {
-            task_status_chashmap: Arc::new(CHashMap::new()),
+            journal: Some(journal),
+            ..TaskStatus::new()
+        }
+    }
+
+    /// Returns receiver that gets id of every task that reaches final status
+    pub fn subscribe_finished_tasks(&self) -> mpsc::UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.finished_task_listeners.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    /// Returns receiver that gets request and final status of every task that reaches
+    /// final status. Record is read when task finishes, so it is received even if
+    /// task is evicted right after that.
+    pub fn subscribe_finished_task_records(&self) -> mpsc::UnboundedReceiver<TaskRecord> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.finished_record_listeners.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    /// Waits until task reaches final status or timeout elapses and returns status of task.
+    /// Waiter is woken by transition of task. Tasks in shared store, that can be finished
+    /// by other servers, are also checked periodically.
+    pub async fn wait_for_finished_task(
+        &self,
+        id: &str,
+        timeout: Duration,
+    ) -> Result<GetStatusResponse, TaskStoreError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let poll_interval = Duration::from_millis(SHARED_STORE_POLL_INTERVAL_MS);
+
+        loop {
+            // Waiter is registered before status is checked, so transition can't be missed
+            let mut waiter = FinishedTaskWaiter::register(self, id);
+            let task_id = id.to_string();
+            let status = self
+                .blocking(move |task_status| task_status.get_status_by_id(&task_id))
+                .await?;
+            let now = tokio::time::Instant::now();
+            let is_waiting = !status.status.is_finished()
+                && status.status != TaskStatusEnum::NOTEXIST
+                && now < deadline;
+            if !is_waiting {
+                return Ok(status);
+            }
+
+            let wake_at = match self.task_store.is_shared() {
+                true => deadline.min(now + poll_interval),
+                false => deadline,
+            };
+            waiter.wait_until(wake_at).await;
+        }
+    }
+
+    /// Removes senders of waiters that stopped waiting for task
+    fn remove_closed_waiters(&self, id: &str) {
+        let mut task_waiters = self.task_waiters.lock().unwrap();
+        if let Some(waiters) = task_waiters.get_mut(id) {
+            waiters.retain(|waiter| !waiter.is_closed());
+            if waiters.is_empty() {
+                task_waiters.remove(id);
+            }
+        }
+    }
+
+    /// Runs function that uses task store. Persistent stores and journal block while
+    /// they wait for database or disk, so function is run on thread for blocking calls then.
+    pub async fn blocking<T: Send + 'static>(
+        &self,
+        function: impl FnOnce(&TaskStatus) -> T + Send + 'static,
+    ) -> T {
+        if !self.is_durable() {
+            return function(self);
+        }
+        let task_status = self.clone();
+        task::spawn_blocking(move || function(&task_status))
+            .await
+            .expect("task store call panicked")
+    }
+
+    /// Returns true if task changes are persisted to journal or persistent store
+    pub fn is_durable(&self) -> bool {
+        self.journal.is_some() || self.task_store.is_persistent()
+    }
+
+    /// Sets limits of finished tasks that are kept
+    pub fn set_retention_policy(&self, policy: RetentionPolicy) {
+        self.retention.set_policy(policy);
+    }
+
+    /// Returns limits of finished tasks that are kept
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        self.retention.policy()
+    }
+
+    /// Returns path of file with output that was too large to keep in task
+    pub fn output_path(&self, output_ref: &OutputRef) -> Option<PathBuf> {
+        self.output_store
+            .as_ref()
+            .map(|output_store| output_store.path(output_ref))
+    }
+
+    /// Returns store of outputs that are too large to keep in task, if it is set
+    pub fn output_store(&self) -> Option<Arc<OutputStore>> {
+        self.output_store.clone()
+    }
+
+    /// Moves large outputs of result of task or its attempt to output store
+    fn spill_output(&self, id: &str, attempt: Option<u32>, result: &mut GetStatusResult) {
+        if let Some(output_store) = &self.output_store {
+            output_store.spill(id, attempt, result);
         }
     }
 
-    pub fn get_status_by_id(&self, id: &str) -> GetStatusResponse {
-        if let Some(status) = self.task_status_chashmap.get(id) {
-            return status.clone();
+    /// Returns status of task. Finished task is marked as recently used.
+    /// Tasks that were evicted have status EVICTED, unknown tasks have status NOTEXIST.
+    pub fn get_status_by_id(&self, id: &str) -> Result<GetStatusResponse, TaskStoreError> {
+        if let Some(status) = self.task_store.get(id)? {
+            if status.status.is_finished() {
+                self.retention.touch(id);
+            }
+            return Ok(status);
         }
 
-        let error_status = GetStatusResponse::new_error_status();
-        error_status
+        if self.retention.is_evicted(id) {
+            return Ok(GetStatusResponse::new_evicted_status());
+        }
+        Ok(GetStatusResponse::new_error_status())
     }
 
-    pub fn add_new_task(&self) -> String {
-        let status = GetStatusResponse::new_utc_status();
+    /// Returns request and status of task, None if task doesn't exist
+    pub fn get_task(&self, id: &str) -> Result<Option<TaskRecord>, TaskStoreError> {
+        self.task_store.get_record(id)
+    }
+
+    /// Returns all tasks in order of creation, markers of evicted tasks are skipped
+    pub fn list_tasks(&self) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let mut records = self.task_store.list()?;
+        records.retain(|record| record.status.status != TaskStatusEnum::EVICTED);
+
+        Ok(records)
+    }
+
+    /// Calls function with every task in order of creation without keeping
+    /// all tasks in memory. Markers of evicted tasks are skipped.
+    pub fn for_each_task(&self, visit: &mut dyn FnMut(&TaskRecord)) -> Result<(), TaskStoreError> {
+        self.task_store.for_each(&mut |record| {
+            if record.status.status != TaskStatusEnum::EVICTED {
+                visit(record);
+            }
+        })
+    }
+
+    /// Adds new task with given initial status and returns its generated id.
+    /// Returns error if task can't be written to journal or store, task is not added then.
+    pub fn add_new_task(
+        &self,
+        request: &CreateTaskRequest,
+        mut status: GetStatusResponse,
+    ) -> Result<String, TaskStoreError> {
         let id = Uuid::new_v4().to_string();
-        self.task_status_chashmap.insert(id.clone(), status);
+        status.meta.client_id = request.client_id.clone();
+        status.meta.labels = request.labels.clone();
+        status.meta.metadata = request.metadata.clone();
+        status.events.push(TaskEvent::new(
+            status.status.clone(),
+            TaskEventKind::Submitted,
+        ));
+
+        if let Some(journal) = &self.journal {
+            journal.record_submission(&id, request, &status)?;
+        }
+        self.task_store.create(&id, request, &status)?;
+
+        Ok(id)
+    }
+
+    /// Records request of task that was changed after submission
+    /// (e.g. outputs of workflow dependencies were passed to it)
+    pub fn replace_task_request(&self, id: &str, request: &CreateTaskRequest) {
+        let status = match self.task_store.get(id) {
+            Ok(Some(status)) => status,
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("failed to read task {}: {}", id, err);
+                return;
+            }
+        };
+        if let Some(journal) = &self.journal {
+            if let Err(err) = journal.record_submission(id, request, &status) {
+                eprintln!("failed to write request of task {} to journal: {}", id, err);
+            }
+        }
+        if let Err(err) = self.task_store.create(id, request, &status) {
+            eprintln!("failed to store request of task {}: {}", id, err);
+        }
+    }
+
+    /// Remembers task created with idempotency key in journal and store
+    pub fn put_idempotency_key(&self, record: &IdempotencyKeyRecord) -> Result<(), TaskStoreError> {
+        if let Some(journal) = &self.journal {
+            journal.record_idempotency_key(record)?;
+        }
+        self.task_store.put_idempotency_key(record)
+    }
+
+    /// Returns task created with idempotency key of client
+    pub fn get_idempotency_key(
+        &self,
+        client_id: &str,
+        key: &str,
+    ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError> {
+        self.task_store.get_idempotency_key(client_id, key)
+    }
+
+    /// Forgets idempotency keys that were first used before given unix time in milliseconds
+    pub fn purge_idempotency_keys(&self, created_before: i64) {
+        if let Some(journal) = &self.journal {
+            if let Err(err) = journal.record_idempotency_key_purge(created_before) {
+                eprintln!(
+                    "failed to write purge of idempotency keys to journal: {}",
+                    err
+                );
+            }
+        }
+        if let Err(err) = self.task_store.purge_idempotency_keys(created_before) {
+            eprintln!("failed to purge idempotency keys: {}", err);
+        }
+    }
+
+    /// Stores task restored from journal or persistent store.
+    /// Evicted tasks are only remembered as evicted.
+    pub fn restore_task(&self, record: &TaskRecord) -> Result<(), TaskStoreError> {
+        if record.status.status == TaskStatusEnum::EVICTED {
+            self.retention.mark_evicted(&record.id);
+            self.remove_evicted_task(&record.id)?;
+            if let Some(output_store) = &self.output_store {
+                output_store.delete(&record.id);
+            }
+            return Ok(());
+        }
+
+        self.task_store
+            .create(&record.id, &record.request, &record.status)?;
+        if record.status.status.is_finished() {
+            let evicted = self.retention.track(&record.id, &record.status);
+            self.evict_tasks(&evicted);
+        }
+
+        Ok(())
+    }
+
+    /// Evicts finished tasks that are older than max age of retention policy
+    pub fn evict_expired_tasks(&self) {
+        let expired = self.retention.evict_expired();
+        self.evict_tasks(&expired);
+    }
+
+    fn evict_tasks(&self, ids: &[String]) {
+        for id in ids {
+            if let Err(err) = self.remove_evicted_task(id) {
+                eprintln!("failed to evict task {}: {}", id, err);
+            }
+            if let Some(output_store) = &self.output_store {
+                output_store.delete(id);
+            }
+            if let Some(journal) = &self.journal {
+                if let Err(err) = journal.record_eviction(id) {
+                    eprintln!(
+                        "failed to write eviction of task {} to journal: {}",
+                        id, err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Removes evicted task from store. Persistent stores keep marker of task without
+    /// its request and outputs instead, so that task is still reported as EVICTED
+    /// after restart and by other servers that share store.
+    fn remove_evicted_task(&self, id: &str) -> Result<(), TaskStoreError> {
+        if !self.task_store.is_persistent() {
+            self.task_store.delete(id)?;
+            return Ok(());
+        }
+
+        let Some(record) = self.task_store.get_record(id)? else {
+            return Ok(());
+        };
+        let request =
+            CreateTaskRequest::new(record.request.task_type, String::new(), String::new());
+        self.task_store
+            .create(id, &request, &GetStatusResponse::new_evicted_status())
+    }
+
+    /// Adds event to history of task that is not finished yet without changing its status
+    pub fn record_event(&self, id: &str, kind: TaskEventKind) {
+        let status = self.store_transition(id, &mut |status| {
+            if status.status.is_finished() {
+                return false;
+            }
+            status
+                .events
+                .push(TaskEvent::new(status.status.clone(), kind.clone()));
+            true
+        });
 
-        id
+        if let Some(status) = status {
+            self.journal_transition(id, &status);
+        }
     }
 
-    pub fn start_running_task(&mut self, id: &str) {
-        let mut status = self.task_status_chashmap.get_mut(id).unwrap();
-        status.status = TaskStatusEnum::RUNNING;
-        status.meta.started_at = Some(Utc::now().to_string());
+    /// Marks waiting task as running by given worker of queue on given CPU cores
+    /// (empty if task is not pinned). Returns false if task is not waiting anymore
+    /// (e.g. it was cancelled while in queue).
+    pub fn start_running_task(
+        &mut self,
+        id: &str,
+        queue: &str,
+        worker: usize,
+        cpus: Vec<usize>,
+    ) -> bool {
+        let event = TaskEventKind::Dispatched {
+            queue: queue.to_string(),
+            worker,
+            cpus: cpus.clone(),
+        };
+        self.transition(id, event, |status| {
+            if status.status != TaskStatusEnum::WAIT {
+                return false;
+            }
+            status.status = TaskStatusEnum::RUNNING;
+            status.meta.started_at = Some(Utc::now().to_string());
+            status.meta.cpus = cpus.clone();
+            true
+        })
     }
 
+    /// Marks scheduled task as waiting when its time has come.
+    /// Returns false if task is not scheduled anymore (e.g. it was cancelled).
+    pub fn enqueue_scheduled_task(&mut self, id: &str) -> bool {
+        self.transition(id, TaskEventKind::Ready, |status| {
+            if status.status != TaskStatusEnum::SCHEDULED {
+                return false;
+            }
+            status.status = TaskStatusEnum::WAIT;
+            true
+        })
+    }
+
+    /// Marks task as finished with given output, status and reason of failure
     pub fn finish_running_task(
         &mut self,
         id: &str,
         stdout: String,
         stderr: Option<String>,
         execution_result: TaskStatusEnum,
+        artifacts: BTreeMap<String, String>,
+        failure: Option<FailureKind>,
     ) {
-        let mut status = self.task_status_chashmap.get_mut(id).unwrap();
-        status.result.stdout = stdout;
-        status.result.stderr = stderr;
-        status.status = execution_result;
-        status.meta.finished_at = Some(Utc::now().to_string());
+        let mut result = GetStatusResult {
+            stdout,
+            stderr,
+            artifacts,
+            stdout_ref: None,
+            stderr_ref: None,
+        };
+        self.spill_output(id, None, &mut result);
+
+        self.transition(id, TaskEventKind::Finished { failure }, |status| {
+            status.result = result.clone();
+            status.status = execution_result.clone();
+            status.meta.finished_at = Some(Utc::now().to_string());
+            true
+        });
+    }
+
+    /// Adds finished attempt to history of running task
+    pub fn record_attempt(&mut self, id: &str, mut attempt: TaskAttempt) {
+        self.spill_output(id, Some(attempt.attempt), &mut attempt.result);
+        self.store_transition(id, &mut |status| {
+            status.attempts.push(attempt.clone());
+            true
+        });
+    }
+
+    /// Marks running task whose attempt failed as scheduled for next attempt.
+    /// Output of failed attempt stays in result until next attempt finishes.
+    pub fn retry_task(&mut self, id: &str, mut attempt: TaskAttempt, retry_at: DateTime<Utc>) {
+        self.spill_output(id, Some(attempt.attempt), &mut attempt.result);
+        let event = TaskEventKind::Retrying {
+            attempt: attempt.attempt,
+            retry_at: retry_at.to_string(),
+        };
+        self.transition(id, event, |status| {
+            status.result = attempt.result.clone();
+            status.attempts.push(attempt.clone());
+            status.status = TaskStatusEnum::SCHEDULED;
+            status.meta.scheduled_for = Some(retry_at.to_string());
+            status.meta.started_at = None;
+            true
+        });
+    }
+
+    /// Adds attempt to deliver final status of finished task to its callback URL
+    pub fn record_callback_attempt(&self, id: &str, attempt: CallbackAttempt) {
+        let status = self.store_transition(id, &mut |status| {
+            status.callback_attempts.push(attempt.clone());
+            true
+        });
+
+        if let Some(status) = status {
+            self.journal_transition(id, &status);
+        }
+    }
+
+    /// Marks task that was not started or was killed on shutdown as cancelled
+    pub fn cancel_task(&mut self, id: &str) {
+        self.transition(id, TaskEventKind::Finished { failure: None }, |status| {
+            status.status = TaskStatusEnum::CANCELLED;
+            status.meta.finished_at = Some(Utc::now().to_string());
+            true
+        });
+    }
+
+    /// Marks waiting task that was taken from queue after its deadline as expired.
+    /// Returns false if task is not waiting anymore.
+    pub fn expire_task(&mut self, id: &str) -> bool {
+        self.transition(id, TaskEventKind::Finished { failure: None }, |status| {
+            if status.status != TaskStatusEnum::WAIT {
+                return false;
+            }
+            status.status = TaskStatusEnum::EXPIRED;
+            status.meta.finished_at = Some(Utc::now().to_string());
+            true
+        })
+    }
+
+    /// Marks blocked task as waiting when all its dependencies succeeded.
+    /// Returns false if task is not blocked anymore (e.g. it was cancelled).
+    pub fn unblock_task(&mut self, id: &str) -> bool {
+        self.transition(id, TaskEventKind::Ready, |status| {
+            if status.status != TaskStatusEnum::BLOCKED {
+                return false;
+            }
+            status.status = TaskStatusEnum::WAIT;
+            true
+        })
+    }
+
+    /// Marks blocked task as skipped because one of its dependencies failed.
+    /// Returns false if task is not blocked anymore.
+    pub fn skip_task(&mut self, id: &str) -> bool {
+        self.transition(id, TaskEventKind::Finished { failure: None }, |status| {
+            if status.status != TaskStatusEnum::BLOCKED {
+                return false;
+            }
+            status.status = TaskStatusEnum::SKIPPED;
+            status.meta.finished_at = Some(Utc::now().to_string());
+            true
+        })
+    }
+
+    /// Cancels task if it is scheduled, blocked or waiting in queue.
+    /// Returns false if task has already started.
+    pub fn cancel_waiting_task(&mut self, id: &str) -> bool {
+        self.transition(id, TaskEventKind::Finished { failure: None }, |status| {
+            if !matches!(
+                status.status,
+                TaskStatusEnum::SCHEDULED | TaskStatusEnum::BLOCKED | TaskStatusEnum::WAIT
+            ) {
+                return false;
+            }
+            status.status = TaskStatusEnum::CANCELLED;
+            status.meta.finished_at = Some(Utc::now().to_string());
+            true
+        })
+    }
+
+    /// Puts task back into waiting state (e.g. interrupted task that is requeued)
+    pub fn reset_task(&mut self, id: &str) {
+        self.transition(id, TaskEventKind::Requeued, |status| {
+            status.status = TaskStatusEnum::WAIT;
+            status.meta.started_at = None;
+            status.meta.finished_at = None;
+            true
+        });
+    }
+
+    /// Changes status of task in store with given function, adds given event
+    /// to its history and records transition. Returns false if task doesn't exist
+    /// or function refused to change its status.
+    fn transition(
+        &self,
+        id: &str,
+        event: TaskEventKind,
+        mut update: impl FnMut(&mut GetStatusResponse) -> bool,
+    ) -> bool {
+        let mut update_with_event = |status: &mut GetStatusResponse| {
+            if !update(status) {
+                return false;
+            }
+            status
+                .events
+                .push(TaskEvent::new(status.status.clone(), event.clone()));
+            true
+        };
+
+        match self.store_transition(id, &mut update_with_event) {
+            Some(status) => {
+                self.record_transition(id, &status);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes status of task in store, see [`TaskStore::transition`].
+    /// Status stays unchanged if store fails, so failure is only reported.
+    fn store_transition(
+        &self,
+        id: &str,
+        update: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+    ) -> Option<GetStatusResponse> {
+        self.task_store
+            .transition(id, update)
+            .unwrap_or_else(|err| {
+                eprintln!("failed to change status of task {}: {}", id, err);
+                None
+            })
+    }
+
+    /// Writes new status of task to journal. Status stays changed in memory
+    /// if it can't be written, so failure is only reported.
+    fn journal_transition(&self, id: &str, status: &GetStatusResponse) {
+        if let Some(journal) = &self.journal {
+            if let Err(err) = journal.record_transition(id, status) {
+                eprintln!("failed to write status of task {} to journal: {}", id, err);
+            }
+        }
+    }
+
+    /// Sends request and final status of task to record listeners.
+    /// Store is read without lock of listeners.
+    fn send_finished_record(&self, id: &str, status: &GetStatusResponse) {
+        let listeners = self.finished_record_listeners.lock().unwrap().clone();
+        if listeners.is_empty() {
+            return;
+        }
+        let mut record = match self.task_store.get_record(id) {
+            Ok(Some(record)) => record,
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("failed to read finished task {}: {}", id, err);
+                return;
+            }
+        };
+        record.status = status.clone();
+
+        for listener in &listeners {
+            let _ = listener.send(record.clone());
+        }
+        self.finished_record_listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| !listener.is_closed());
+    }
+
+    /// Writes new status of task to journal and notifies
+    /// listeners if task is finished
+    fn record_transition(&self, id: &str, status: &GetStatusResponse) {
+        self.journal_transition(id, status);
+
+        if status.status.is_finished() {
+            self.finished_task_listeners
+                .lock()
+                .unwrap()
+                .retain(|listener| listener.send(id.to_string()).is_ok());
+            self.send_finished_record(id, status);
+
+            let waiters = self.task_waiters.lock().unwrap().remove(id);
+            for waiter in waiters.into_iter().flatten() {
+                let _ = waiter.send(());
+            }
+
+            let evicted = self.retention.track(id, status);
+            self.evict_tasks(&evicted);
+        }
+    }
+}
+
+/// Client waiting for task to finish, it is unregistered when dropped
+/// (e.g. when request of client is dropped)
+struct FinishedTaskWaiter<'a> {
+    task_status: &'a TaskStatus,
+    id: &'a str,
+    receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl<'a> FinishedTaskWaiter<'a> {
+    /// Registers waiter that is notified when task reaches final status
+    fn register(task_status: &'a TaskStatus, id: &'a str) -> FinishedTaskWaiter<'a> {
+        let (sender, receiver) = oneshot::channel();
+        task_status
+            .task_waiters
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .push(sender);
+
+        FinishedTaskWaiter {
+            task_status,
+            id,
+            receiver: Some(receiver),
+        }
+    }
+
+    /// Waits until task is finished or given time comes
+    async fn wait_until(&mut self, wake_at: tokio::time::Instant) {
+        if let Some(receiver) = self.receiver.as_mut() {
+            let _ = tokio::time::timeout_at(wake_at, receiver).await;
+        }
+    }
+}
+
+impl Drop for FinishedTaskWaiter<'_> {
+    fn drop(&mut self) {
+        self.receiver.take();
+        self.task_status.remove_closed_waiters(self.id);
+    }
+}
+
+impl Default for TaskStatus {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 /// Struct of server info that contains
-/// thread pool with workers, server queue of tasks
-/// and status of all tasks.
+/// thread pool with workers, server queue of tasks,
+/// status of all tasks, recurring jobs and workflows.
 #[derive(Clone)]
 pub struct ServerInfo {
     pub worker_pool: Arc<WorkerPool>,
     pub task_status: TaskStatus,
+    pub job_scheduler: Arc<JobScheduler>,
+    pub workflow_scheduler: Arc<WorkflowScheduler>,
+    pub idempotency_keys: Arc<IdempotencyKeys>,
 }
 
 impl ServerInfo {
     /// Creates new server info struct
     pub fn new(worker_pool: Arc<WorkerPool>, task_status: TaskStatus) -> ServerInfo {
+        let job_scheduler = Arc::new(JobScheduler::new(worker_pool.clone(), task_status.clone()));
+        let workflow_scheduler = Arc::new(WorkflowScheduler::new(
+            worker_pool.clone(),
+            task_status.clone(),
+        ));
+
         ServerInfo {
             worker_pool,
             task_status,
+            job_scheduler,
+            workflow_scheduler,
+            idempotency_keys: Arc::new(IdempotencyKeys::new()),
         }
     }
 }
 
+/// Resumes tasks restored from journal or persistent store. Waiting tasks are put back
+/// into queue, scheduled tasks are scheduled again, blocked tasks of workflows are cancelled,
+/// tasks that were running are marked as error or requeued according to policy,
+/// finished tasks are left as they are. In store shared with other servers running
+/// and blocked tasks may belong to them, and waiting tasks are already in shared queue,
+/// where other servers may have claimed them, so only scheduled tasks are resumed.
+/// Timers of scheduled tasks may then run on several servers, but only one of them
+/// makes task waiting.
+async fn resume_tasks(
+    records: Vec<TaskRecord>,
+    interrupted_task_policy: InterruptedTaskPolicy,
+    server_info: &ServerInfo,
+) {
+    let is_shared = server_info.task_status.task_store.is_shared();
+    for record in records {
+        let mut task_status = server_info.task_status.clone();
+        let status = record.status.status.clone();
+        if is_shared && status != TaskStatusEnum::SCHEDULED {
+            continue;
+        }
+        let stdout = record.status.result.stdout.clone();
+        let scheduled_for = record.status.meta.scheduled_for.clone();
+
+        let task_info = TaskInfo::new(record.id.clone(), record.request, task_status.clone());
+        match status {
+            TaskStatusEnum::WAIT => server_info.worker_pool.do_task(task_info).await,
+            TaskStatusEnum::SCHEDULED => {
+                let run_at = scheduled_for
+                    .and_then(|scheduled_for| scheduled_for.parse::<DateTime<Utc>>().ok())
+                    .unwrap_or_else(Utc::now);
+                server_info.worker_pool.schedule_task(task_info, run_at);
+            }
+            TaskStatusEnum::RUNNING => match interrupted_task_policy {
+                InterruptedTaskPolicy::Error => task_status.finish_running_task(
+                    &record.id,
+                    stdout,
+                    Some("task was interrupted by server restart".to_string()),
+                    TaskStatusEnum::ERROR,
+                    BTreeMap::new(),
+                    None,
+                ),
+                InterruptedTaskPolicy::Requeue => {
+                    task_status.reset_task(&record.id);
+                    server_info.worker_pool.do_task(task_info).await;
+                }
+            },
+            // Workflow graph is not persisted, so dependencies of blocked task can't be tracked
+            TaskStatusEnum::BLOCKED => {
+                task_status.cancel_waiting_task(&record.id);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Handle that triggers graceful shutdown of running task solver server
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown_sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// Starts graceful shutdown: server stops accepting new tasks,
+    /// cancels queued tasks and waits for running tasks during grace period
+    pub fn shutdown(&self) {
+        let _ = self.shutdown_sender.send(true);
+    }
+}
+
 /// TaskSolver server struct
 pub struct TaskSolverServer {
     socket: SocketAddr,
     server_info: ServerInfo,
+    /// Time that running tasks have to finish after shutdown was requested
+    grace_period: Duration,
+    shutdown_sender: Arc<watch::Sender<bool>>,
+    /// Journal that persists tasks between restarts
+    journal: Option<Arc<Journal>>,
+    /// What to do with tasks that were running when server stopped
+    interrupted_task_policy: InterruptedTaskPolicy,
+    /// Time between journal compactions
+    compaction_interval: Duration,
+    /// Queue of waiting tasks shared with other servers
+    shared_queue: Option<Arc<dyn SharedTaskQueue>>,
+    /// Settings of callbacks that post final status of tasks
+    callback_config: CallbackConfig,
+    /// Whether /admin/export endpoint is served
+    admin_export: bool,
 }
 
 impl TaskSolverServer {
     /// Creates new task solver server with given workers count, ip and port
-    pub fn new(workers_count: usize, ip: String, port: u16) -> TaskSolverServer { 
+    pub fn new(workers_count: usize, ip: String, port: u16) -> TaskSolverServer {
         let socket = SocketAddr::new(ip.parse().unwrap(), port);
 
-        let (task_sender, task_receiver) = async_channel::unbounded();
-        let worker_pool = Arc::new(WorkerPool::new(workers_count, task_sender, task_receiver));
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
 
         let task_status = TaskStatus::new();
         let server_info = ServerInfo::new(worker_pool.clone(), task_status);
 
+        let (shutdown_sender, _) = watch::channel(false);
+
         TaskSolverServer {
             socket,
             server_info,
+            grace_period: Duration::from_secs(DEFAULT_GRACE_PERIOD_SECONDS),
+            shutdown_sender: Arc::new(shutdown_sender),
+            journal: None,
+            interrupted_task_policy: InterruptedTaskPolicy::Error,
+            compaction_interval: Duration::from_secs(DEFAULT_COMPACTION_INTERVAL_SECONDS),
+            shared_queue: None,
+            callback_config: CallbackConfig::default(),
+            admin_export: false,
+        }
+    }
+
+    /// Makes server durable: every task change is written to journal and
+    /// tasks from journal are restored on start. Tasks that were running
+    /// when server stopped are handled according to given policy.
+    pub fn with_journal(
+        mut self,
+        journal: Journal,
+        interrupted_task_policy: InterruptedTaskPolicy,
+    ) -> TaskSolverServer {
+        let journal = Arc::new(journal);
+        let task_status = TaskStatus {
+            journal: Some(journal.clone()),
+            retention: self.server_info.task_status.retention.clone(),
+            output_store: self.server_info.task_status.output_store.clone(),
+            ..TaskStatus::with_store(self.server_info.task_status.task_store.clone())
+        };
+        self.server_info = ServerInfo {
+            idempotency_keys: self.server_info.idempotency_keys.clone(),
+            ..ServerInfo::new(self.server_info.worker_pool.clone(), task_status)
+        };
+        self.journal = Some(journal);
+        self.interrupted_task_policy = interrupted_task_policy;
+        self
+    }
+
+    /// Keeps tasks in given store instead of memory. Tasks that are in persistent
+    /// store when server starts are restored like tasks from journal.
+    pub fn with_task_store(
+        mut self,
+        task_store: Arc<dyn TaskStore>,
+        interrupted_task_policy: InterruptedTaskPolicy,
+    ) -> TaskSolverServer {
+        let task_status = TaskStatus {
+            journal: self.server_info.task_status.journal.clone(),
+            retention: self.server_info.task_status.retention.clone(),
+            output_store: self.server_info.task_status.output_store.clone(),
+            ..TaskStatus::with_store(task_store)
+        };
+        self.server_info = ServerInfo {
+            idempotency_keys: self.server_info.idempotency_keys.clone(),
+            ..ServerInfo::new(self.server_info.worker_pool.clone(), task_status)
+        };
+        self.interrupted_task_policy = interrupted_task_policy;
+        self
+    }
+
+    /// Shares waiting tasks with other servers through given queue: new tasks are put
+    /// into it and every server takes tasks from it while it has free workers.
+    /// Servers must also share task store.
+    pub fn with_shared_queue(mut self, shared_queue: Arc<dyn SharedTaskQueue>) -> TaskSolverServer {
+        self.shared_queue = Some(shared_queue);
+        self
+    }
+
+    /// Sets time between journal compactions
+    pub fn with_compaction_interval(mut self, compaction_interval: Duration) -> TaskSolverServer {
+        self.compaction_interval = compaction_interval;
+        self
+    }
+
+    /// Sets grace period that running tasks have to finish on shutdown
+    pub fn with_grace_period(mut self, grace_period: Duration) -> TaskSolverServer {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Sets weights of clients: client with weight 2 gets twice as many
+    /// workers as client with weight 1 when both have tasks in queue
+    pub fn with_client_weights(self, client_weights: HashMap<String, u32>) -> TaskSolverServer {
+        self.server_info
+            .worker_pool
+            .set_client_weights(client_weights);
+        self
+    }
+
+    /// Adds named queue with its own workers and task defaults.
+    /// Queue named "default" replaces default queue.
+    pub fn with_queue(self, queue_config: QueueConfig) -> TaskSolverServer {
+        self.server_info.worker_pool.add_queue(queue_config);
+        self
+    }
+
+    /// Routes tasks of given type that don't set queue to given queue
+    pub fn with_route(self, task_type: TaskType, queue: &str) -> TaskSolverServer {
+        self.server_info.worker_pool.set_route(task_type, queue);
+        self
+    }
+
+    /// Adds limit of running tasks of given type, label or queue
+    pub fn with_concurrency_limit(self, limit: ConcurrencyLimit) -> TaskSolverServer {
+        self.server_info.worker_pool.add_concurrency_limit(limit);
+        self
+    }
+
+    /// Pins workers and child processes of their tasks to given CPU cores
+    pub fn with_cpus(self, cpus: Vec<usize>) -> TaskSolverServer {
+        self.server_info.worker_pool.set_cpus(cpus);
+        self
+    }
+
+    /// Sets time that smaller tasks can overtake task that waits for free slots
+    pub fn with_backfill_window(self, backfill_window: Duration) -> TaskSolverServer {
+        self.server_info
+            .worker_pool
+            .set_backfill_window(backfill_window);
+        self
+    }
+
+    /// Sets time that tasks without expires_at or max_wait_ms can wait
+    /// in queue before they expire
+    pub fn with_default_max_wait(self, max_wait: Duration) -> TaskSolverServer {
+        self.server_info.worker_pool.set_default_max_wait(max_wait);
+        self
+    }
+
+    /// Sets time that idempotency key is remembered after first use
+    pub fn with_idempotency_window(self, window: Duration) -> TaskSolverServer {
+        self.server_info.idempotency_keys.set_window(window);
+        self
+    }
+
+    /// Sets limits of finished tasks that are kept: their age, amount and total size
+    /// of outputs. Least recently used tasks are evicted first.
+    pub fn with_retention(self, policy: RetentionPolicy) -> TaskSolverServer {
+        self.server_info.task_status.set_retention_policy(policy);
+        self
+    }
+
+    /// Stores outputs of tasks that are larger than threshold of given store in its
+    /// files instead of task store. Tasks keep only preview of such outputs.
+    pub fn with_output_store(mut self, output_store: OutputStore) -> TaskSolverServer {
+        let task_status = TaskStatus {
+            output_store: Some(Arc::new(output_store)),
+            ..self.server_info.task_status.clone()
+        };
+        self.server_info = ServerInfo {
+            idempotency_keys: self.server_info.idempotency_keys.clone(),
+            ..ServerInfo::new(self.server_info.worker_pool.clone(), task_status)
+        };
+        self
+    }
+
+    /// Sets default callback URL, signature secret and retries of callbacks
+    /// that post final status of tasks
+    pub fn with_callbacks(mut self, callback_config: CallbackConfig) -> TaskSolverServer {
+        self.callback_config = callback_config;
+        self
+    }
+
+    /// Serves /admin/export endpoint that returns all tasks with their code and outputs.
+    /// It is disabled by default, as it has no authentication.
+    pub fn with_admin_export(mut self, admin_export: bool) -> TaskSolverServer {
+        self.admin_export = admin_export;
+        self
+    }
+
+    /// Returns handle that can be used to gracefully shut server down
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown_sender: self.shutdown_sender.clone(),
         }
     }
+
     /// Runs server on given ip and port. Creates worker pool with given
     /// amount of workers. Creates tokio threads to manage the server and task queue in parallel.
-    pub async fn start_tasksolver_server(self) -> JoinHandle<()> {
-        let server = task::spawn(async move {
-            warp::serve(routes_handler(self.server_info)).run(self.socket).await;
+    /// Returned handle completes after shutdown was requested and worker pool was drained.
+    /// Returns error if journal or task store can't be read.
+    pub async fn start_tasksolver_server(self) -> io::Result<JoinHandle<()>> {
+        let journal_records = match &self.journal {
+            Some(journal) => Some(journal.replay()?),
+            None => None,
+        };
+
+        start_callbacks(
+            self.callback_config.clone(),
+            self.server_info.task_status.clone(),
+        );
+
+        if let Some(shared_queue) = self.shared_queue.clone() {
+            self.server_info
+                .worker_pool
+                .read_shared_queue(shared_queue, self.server_info.task_status.clone());
+        }
+
+        if let (Some(journal), Some(records)) = (self.journal.clone(), journal_records) {
+            for record in &records {
+                self.server_info
+                    .task_status
+                    .restore_task(record)
+                    .map_err(io::Error::other)?;
+            }
+            for record in journal.replay_idempotency_keys()? {
+                self.server_info
+                    .task_status
+                    .task_store
+                    .put_idempotency_key(&record)
+                    .map_err(io::Error::other)?;
+            }
+            resume_tasks(records, self.interrupted_task_policy, &self.server_info).await;
+            let _ = journal.compact();
+
+            let compaction_interval = self.compaction_interval;
+            task::spawn(async move {
+                let mut interval = tokio::time::interval(compaction_interval);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    let journal = journal.clone();
+                    let _ = task::spawn_blocking(move || journal.compact()).await;
+                }
+            });
+        } else if self.server_info.task_status.task_store.is_persistent() {
+            let task_store = self.server_info.task_status.task_store.clone();
+            let records = task_store.list().map_err(io::Error::other)?;
+            // Tasks of shared store can be changed by other servers meanwhile,
+            // evicted tasks are already kept as markers
+            if !task_store.is_shared() {
+                let not_evicted = records
+                    .iter()
+                    .filter(|record| record.status.status != TaskStatusEnum::EVICTED);
+                for record in not_evicted {
+                    self.server_info
+                        .task_status
+                        .restore_task(record)
+                        .map_err(io::Error::other)?;
+                }
+            }
+            resume_tasks(records, self.interrupted_task_policy, &self.server_info).await;
+        }
+
+        if let Some(max_age) = self.server_info.task_status.retention_policy().max_age {
+            let task_status = self.server_info.task_status.clone();
+            let sweep_interval =
+                (max_age / 2).clamp(Duration::from_millis(100), Duration::from_secs(30));
+            task::spawn(async move {
+                let mut interval = tokio::time::interval(sweep_interval);
+                loop {
+                    interval.tick().await;
+                    let task_status = task_status.clone();
+                    let _ = task::spawn_blocking(move || task_status.evict_expired_tasks()).await;
+                }
+            });
+        }
+
+        let idempotency_keys = self.server_info.idempotency_keys.clone();
+        let task_status = self.server_info.task_status.clone();
+        let purge_interval =
+            (idempotency_keys.window() / 2).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(purge_interval);
+            loop {
+                interval.tick().await;
+                let idempotency_keys = idempotency_keys.clone();
+                let task_status = task_status.clone();
+                let _ = task::spawn_blocking(move || idempotency_keys.purge_expired(&task_status))
+                    .await;
+            }
         });
 
-        server 
+        let worker_pool = self.server_info.worker_pool.clone();
+        let grace_period = self.grace_period;
+        let mut shutdown_receiver = self.shutdown_sender.subscribe();
+
+        let shutdown_signal = async move {
+            let _ = shutdown_receiver.wait_for(|&shutdown| shutdown).await;
+            worker_pool.shutdown(grace_period).await;
+        };
+
+        let (_, server) = warp::serve(routes_handler(self.server_info, self.admin_export))
+            .bind_with_graceful_shutdown(self.socket, shutdown_signal);
+
+        Ok(task::spawn(server))
     }
 }
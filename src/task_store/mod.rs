@@ -0,0 +1,4 @@
+pub mod postgres_task_store;
+pub mod sqlite_task_store;
+#[allow(clippy::module_inception)]
+pub mod task_store;
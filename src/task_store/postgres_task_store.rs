@@ -0,0 +1,569 @@
+use crate::server::models::requests::CreateTaskRequest;
+use crate::server::models::responses::GetStatusResponse;
+use crate::task_store::task_store::{
+    IdempotencyKeyRecord, SharedTaskQueue, TaskRecord, TaskStore, TaskStoreError,
+    FOR_EACH_BATCH_SIZE,
+};
+
+use postgres::{Client, NoTls};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Default time that server has to start task it claimed, after that
+/// task can be claimed by other servers (e.g. if server crashed)
+pub const DEFAULT_CLAIM_LEASE_SECONDS: u64 = 60;
+
+/// Job that is executed with connection on thread of store
+type Job = Box<dyn FnOnce(&mut Client) + Send>;
+
+/// Task store and shared queue in PostgreSQL database. Several servers that use
+/// same database see same tasks, and every waiting task is taken by one of them.
+/// Request and status of every task are stored as json.
+pub struct PostgresTaskStore {
+    /// Sender of jobs to thread that owns connection. Blocking client can't be used
+    /// from threads of tokio runtime, so it lives on its own thread.
+    jobs: Mutex<mpsc::Sender<Job>>,
+    /// Id of this store in claims of queued tasks
+    server_id: String,
+    /// Time that claim of queued task is kept
+    claim_lease: Duration,
+}
+
+impl PostgresTaskStore {
+    /// Connects to database with given url (e.g. "postgres://user@localhost/tasksolver")
+    /// and creates tables if they don't exist
+    pub fn connect(url: &str) -> Result<PostgresTaskStore, postgres::Error> {
+        let (jobs, jobs_receiver) = mpsc::channel::<Job>();
+        let (connected_sender, connected_receiver) = mpsc::channel();
+
+        let url = url.to_string();
+        thread::spawn(move || {
+            let mut client = match Client::connect(&url, NoTls).and_then(|mut client| {
+                create_tables(&mut client)?;
+                Ok(client)
+            }) {
+                Ok(client) => client,
+                Err(err) => {
+                    let _ = connected_sender.send(Err(err));
+                    return;
+                }
+            };
+            let _ = connected_sender.send(Ok(()));
+
+            // Thread stops when store is dropped
+            for job in jobs_receiver {
+                // Connection that was closed (e.g. database restarted) is opened again.
+                // If it can't be opened, job fails with closed connection and
+                // next job tries again.
+                if client.is_closed() {
+                    match Client::connect(&url, NoTls) {
+                        Ok(reconnected_client) => client = reconnected_client,
+                        Err(err) => eprintln!("failed to reconnect to postgres: {}", err),
+                    }
+                }
+                job(&mut client);
+            }
+        });
+
+        connected_receiver
+            .recv()
+            .expect("postgres thread stopped while connecting")?;
+
+        Ok(PostgresTaskStore {
+            jobs: Mutex::new(jobs),
+            server_id: Uuid::new_v4().to_string(),
+            claim_lease: Duration::from_secs(DEFAULT_CLAIM_LEASE_SECONDS),
+        })
+    }
+
+    /// Sets time that server has to start task it claimed
+    pub fn with_claim_lease(mut self, claim_lease: Duration) -> PostgresTaskStore {
+        self.claim_lease = claim_lease;
+        self
+    }
+
+    /// Runs function with connection on thread of store and returns its result
+    fn call<T: Send + 'static>(
+        &self,
+        function: impl FnOnce(&mut Client) -> Result<T, postgres::Error> + Send + 'static,
+    ) -> Result<T, TaskStoreError> {
+        let stopped = || TaskStoreError("postgres thread stopped".to_string());
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job: Job = Box::new(move |client| {
+            let _ = result_sender.send(function(client));
+        });
+        self.jobs.lock().unwrap().send(job).map_err(|_| stopped())?;
+
+        Ok(result_receiver.recv().map_err(|_| stopped())??)
+    }
+
+    /// Returns status of task with its version, that is incremented on every change
+    fn get_with_version(
+        &self,
+        id: &str,
+    ) -> Result<Option<(GetStatusResponse, i64)>, TaskStoreError> {
+        let id = id.to_string();
+        let row = self.call(move |client| {
+            client.query_opt("SELECT status, version FROM tasks WHERE id = $1", &[&id])
+        })?;
+
+        Ok(row.and_then(|row| {
+            let status = serde_json::from_str(row.get::<_, &str>(0)).ok()?;
+            Some((status, row.get(1)))
+        }))
+    }
+}
+
+fn create_tables(client: &mut Client) -> Result<(), postgres::Error> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS tasks (
+             sequence BIGSERIAL PRIMARY KEY,
+             id TEXT NOT NULL UNIQUE,
+             request TEXT NOT NULL,
+             status TEXT NOT NULL,
+             version BIGINT NOT NULL DEFAULT 0
+         );
+         CREATE TABLE IF NOT EXISTS task_queue (
+             sequence BIGSERIAL PRIMARY KEY,
+             id TEXT NOT NULL UNIQUE
+         );
+         ALTER TABLE task_queue ADD COLUMN IF NOT EXISTS claimed_by TEXT;
+         ALTER TABLE task_queue ADD COLUMN IF NOT EXISTS claimed_until TIMESTAMPTZ;
+         CREATE TABLE IF NOT EXISTS idempotency_keys (
+             client_id TEXT NOT NULL,
+             key TEXT NOT NULL,
+             fingerprint TEXT NOT NULL,
+             task_id TEXT NOT NULL,
+             created_at BIGINT NOT NULL,
+             PRIMARY KEY (client_id, key)
+         );
+         CREATE INDEX IF NOT EXISTS idempotency_keys_created_at
+             ON idempotency_keys (created_at);",
+    )
+}
+
+fn parse_record(id: String, request: &str, status: &str) -> Option<TaskRecord> {
+    Some(TaskRecord {
+        id,
+        request: serde_json::from_str(request).ok()?,
+        status: serde_json::from_str(status).ok()?,
+    })
+}
+
+impl From<postgres::Error> for TaskStoreError {
+    fn from(err: postgres::Error) -> Self {
+        TaskStoreError(err.to_string())
+    }
+}
+
+impl TaskStore for PostgresTaskStore {
+    fn create(
+        &self,
+        id: &str,
+        request: &CreateTaskRequest,
+        status: &GetStatusResponse,
+    ) -> Result<(), TaskStoreError> {
+        let id = id.to_string();
+        let request = serde_json::to_string(request).unwrap();
+        let status = serde_json::to_string(status).unwrap();
+        self.call(move |client| {
+            client.execute(
+                "INSERT INTO tasks (id, request, status) VALUES ($1, $2, $3)
+                 ON CONFLICT (id) DO UPDATE SET request = excluded.request,
+                     status = excluded.status, version = tasks.version + 1",
+                &[&id, &request, &status],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Status is changed optimistically: update is applied to latest read status and
+    /// written only if nobody changed it meanwhile, otherwise it is applied again
+    fn transition(
+        &self,
+        id: &str,
+        update: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+    ) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        loop {
+            let Some((mut status, version)) = self.get_with_version(id)? else {
+                return Ok(None);
+            };
+            if !update(&mut status) {
+                return Ok(None);
+            }
+
+            let id = id.to_string();
+            let serialized_status = serde_json::to_string(&status).unwrap();
+            let updated = self.call(move |client| {
+                client.execute(
+                    "UPDATE tasks SET status = $2, version = version + 1
+                     WHERE id = $1 AND version = $3",
+                    &[&id, &serialized_status, &version],
+                )
+            })?;
+            if updated > 0 {
+                return Ok(Some(status));
+            }
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        Ok(self.get_with_version(id)?.map(|(status, _)| status))
+    }
+
+    fn get_record(&self, id: &str) -> Result<Option<TaskRecord>, TaskStoreError> {
+        let id = id.to_string();
+        let select_id = id.clone();
+        let row = self.call(move |client| {
+            client.query_opt(
+                "SELECT request, status FROM tasks WHERE id = $1",
+                &[&select_id],
+            )
+        })?;
+
+        Ok(row.and_then(|row| parse_record(id, row.get(0), row.get(1))))
+    }
+
+    fn list(&self) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let rows = self.call(|client| {
+            client.query(
+                "SELECT id, request, status FROM tasks ORDER BY sequence",
+                &[],
+            )
+        })?;
+
+        // Rows that can't be parsed (e.g. written by incompatible version) are skipped
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| parse_record(row.get(0), row.get(1), row.get(2)))
+            .collect())
+    }
+
+    /// Tasks are read in batches, so only one batch is kept in memory
+    fn for_each(&self, visit: &mut dyn FnMut(&TaskRecord)) -> Result<(), TaskStoreError> {
+        let mut last_sequence = 0i64;
+        loop {
+            let rows = self.call(move |client| {
+                client.query(
+                    "SELECT sequence, id, request, status FROM tasks
+                     WHERE sequence > $1 ORDER BY sequence LIMIT $2",
+                    &[&last_sequence, &FOR_EACH_BATCH_SIZE],
+                )
+            })?;
+            let Some(last_row) = rows.last() else {
+                return Ok(());
+            };
+            last_sequence = last_row.get(0);
+
+            for row in &rows {
+                if let Some(record) = parse_record(row.get(1), row.get(2), row.get(3)) {
+                    visit(&record);
+                }
+            }
+        }
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, TaskStoreError> {
+        let id = id.to_string();
+        let deleted = self.call(move |client| {
+            let mut transaction = client.transaction()?;
+            transaction.execute("DELETE FROM task_queue WHERE id = $1", &[&id])?;
+            let deleted = transaction.execute("DELETE FROM tasks WHERE id = $1", &[&id])?;
+            transaction.commit()?;
+            Ok(deleted)
+        })?;
+
+        Ok(deleted > 0)
+    }
+
+    fn put_idempotency_key(&self, record: &IdempotencyKeyRecord) -> Result<(), TaskStoreError> {
+        let record = record.clone();
+        self.call(move |client| {
+            client.execute(
+                "INSERT INTO idempotency_keys (client_id, key, fingerprint, task_id, created_at)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (client_id, key) DO UPDATE SET fingerprint = excluded.fingerprint,
+                     task_id = excluded.task_id, created_at = excluded.created_at",
+                &[
+                    &record.client_id,
+                    &record.key,
+                    &record.fingerprint,
+                    &record.task_id,
+                    &record.created_at,
+                ],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn get_idempotency_key(
+        &self,
+        client_id: &str,
+        key: &str,
+    ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError> {
+        let (client_id, key) = (client_id.to_string(), key.to_string());
+        let (select_client_id, select_key) = (client_id.clone(), key.clone());
+        let row = self.call(move |client| {
+            client.query_opt(
+                "SELECT fingerprint, task_id, created_at FROM idempotency_keys
+                 WHERE client_id = $1 AND key = $2",
+                &[&select_client_id, &select_key],
+            )
+        })?;
+
+        Ok(row.map(|row| IdempotencyKeyRecord {
+            client_id,
+            key,
+            fingerprint: row.get(0),
+            task_id: row.get(1),
+            created_at: row.get(2),
+        }))
+    }
+
+    fn purge_idempotency_keys(&self, created_before: i64) -> Result<(), TaskStoreError> {
+        self.call(move |client| {
+            client.execute(
+                "DELETE FROM idempotency_keys WHERE created_at < $1",
+                &[&created_before],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
+
+impl SharedTaskQueue for PostgresTaskStore {
+    fn push(&self, id: &str) -> Result<(), TaskStoreError> {
+        let id = id.to_string();
+        self.call(move |client| {
+            client.execute(
+                "INSERT INTO task_queue (id) VALUES ($1) ON CONFLICT (id) DO NOTHING",
+                &[&id],
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Rows locked by other servers that are taking their tasks right now are skipped,
+    /// so servers don't wait for each other and never take same task. Claim expires
+    /// after lease, then task is taken again; task that was already started by then
+    /// is not started twice, since only waiting task can become running.
+    fn pop(&self) -> Result<Option<TaskRecord>, TaskStoreError> {
+        let server_id = self.server_id.clone();
+        let claim_lease = self.claim_lease.as_secs_f64();
+        let row = self.call(move |client| {
+            client.query_opt(
+                "WITH claimed AS (
+                         UPDATE task_queue
+                         SET claimed_by = $1, claimed_until = now() + $2 * interval '1 second'
+                         WHERE sequence = (
+                             SELECT sequence FROM task_queue
+                             WHERE claimed_until IS NULL OR claimed_until < now()
+                             ORDER BY sequence
+                             FOR UPDATE SKIP LOCKED LIMIT 1
+                         ) RETURNING id
+                     )
+                     SELECT tasks.id, tasks.request, tasks.status
+                     FROM claimed JOIN tasks ON tasks.id = claimed.id",
+                &[&server_id, &claim_lease],
+            )
+        })?;
+
+        Ok(row.and_then(|row| parse_record(row.get(0), row.get(1), row.get(2))))
+    }
+
+    fn complete(&self, id: &str) -> Result<(), TaskStoreError> {
+        let id = id.to_string();
+        self.call(move |client| client.execute("DELETE FROM task_queue WHERE id = $1", &[&id]))?;
+
+        Ok(())
+    }
+
+    fn release(&self, id: &str) -> Result<(), TaskStoreError> {
+        let id = id.to_string();
+        self.call(move |client| {
+            client.execute(
+                "INSERT INTO task_queue (id) VALUES ($1)
+                 ON CONFLICT (id) DO UPDATE SET claimed_by = NULL, claimed_until = NULL",
+                &[&id],
+            )
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_postgres_task_store {
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::GetStatusResponse;
+    use crate::task_store::postgres_task_store::PostgresTaskStore;
+    use crate::task_store::task_store::test_task_store::check_task_store;
+    use crate::task_store::task_store::{SharedTaskQueue, TaskStore};
+    use postgres::{Client, NoTls};
+    use std::collections::HashSet;
+    use std::thread;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    /// Empty database on server from TASKSOLVER_TEST_POSTGRES_URL that is dropped
+    /// with all its connections when test finishes. Tests that need it are ignored
+    /// by default and are run with
+    /// `TASKSOLVER_TEST_POSTGRES_URL=postgres://postgres@localhost:5432 cargo test -- --ignored`
+    struct TestDatabase {
+        server_url: String,
+        name: String,
+        url: String,
+    }
+
+    impl TestDatabase {
+        fn create() -> TestDatabase {
+            let server_url = std::env::var("TASKSOLVER_TEST_POSTGRES_URL")
+                .expect("TASKSOLVER_TEST_POSTGRES_URL must be set to run postgres tests");
+            let name = format!("tasksolver_{}", Uuid::new_v4().simple());
+
+            let mut client = Client::connect(&format!("{}/postgres", server_url), NoTls).unwrap();
+            client
+                .batch_execute(&format!("CREATE DATABASE {}", name))
+                .unwrap();
+
+            let url = format!("{}/{}", server_url, name);
+            TestDatabase {
+                server_url,
+                name,
+                url,
+            }
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            let mut client =
+                Client::connect(&format!("{}/postgres", self.server_url), NoTls).unwrap();
+            client
+                .batch_execute(&format!("DROP DATABASE {} WITH (FORCE)", self.name))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs PostgreSQL server from TASKSOLVER_TEST_POSTGRES_URL"]
+    fn test_postgres_task_store() {
+        let database = TestDatabase::create();
+        let url = database.url.clone();
+
+        check_task_store(&PostgresTaskStore::connect(&url).unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs PostgreSQL server from TASKSOLVER_TEST_POSTGRES_URL"]
+    fn test_claimed_task_stays_in_queue() {
+        let database = TestDatabase::create();
+        let url = database.url.clone();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let status = GetStatusResponse::new_utc_status();
+        let first_server = PostgresTaskStore::connect(&url)
+            .unwrap()
+            .with_claim_lease(Duration::from_millis(200));
+        let second_server = PostgresTaskStore::connect(&url).unwrap();
+        first_server.create("task", &request, &status).unwrap();
+        first_server.push("task").unwrap();
+
+        assert_eq!(first_server.pop().unwrap().unwrap().id, "task");
+        assert!(second_server.pop().unwrap().is_none());
+
+        // Claim of server that stopped without starting task expires
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(second_server.pop().unwrap().unwrap().id, "task");
+        // Pushing task again doesn't take it from server that claimed it
+        first_server.push("task").unwrap();
+        assert!(first_server.pop().unwrap().is_none());
+
+        second_server.release("task").unwrap();
+        assert_eq!(first_server.pop().unwrap().unwrap().id, "task");
+        first_server.complete("task").unwrap();
+        thread::sleep(Duration::from_millis(300));
+        assert!(second_server.pop().unwrap().is_none());
+    }
+
+    #[test]
+    #[ignore = "needs PostgreSQL server from TASKSOLVER_TEST_POSTGRES_URL"]
+    fn test_every_task_is_taken_once() {
+        let database = TestDatabase::create();
+        let url = database.url.clone();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let status = GetStatusResponse::new_utc_status();
+        let store = PostgresTaskStore::connect(&url).unwrap();
+        let ids: HashSet<String> = (0..20).map(|index| index.to_string()).collect();
+        for id in &ids {
+            store.create(id, &request, &status).unwrap();
+            store.push(id).unwrap();
+            // Task that is already in queue is not added again
+            store.push(id).unwrap();
+        }
+
+        // Every server has its own connection
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = PostgresTaskStore::connect(&url).unwrap();
+                thread::spawn(move || {
+                    let mut taken = Vec::new();
+                    while let Some(record) = store.pop().unwrap() {
+                        taken.push(record.id);
+                    }
+                    taken
+                })
+            })
+            .collect();
+
+        let mut taken = Vec::new();
+        for reader in readers {
+            taken.extend(reader.join().unwrap());
+        }
+        assert_eq!(taken.len(), ids.len());
+        assert_eq!(taken.into_iter().collect::<HashSet<String>>(), ids);
+    }
+
+    #[test]
+    #[ignore = "needs PostgreSQL server from TASKSOLVER_TEST_POSTGRES_URL"]
+    fn test_reconnect_after_connection_is_closed() {
+        let database = TestDatabase::create();
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let status = GetStatusResponse::new_utc_status();
+        let store = PostgresTaskStore::connect(&database.url).unwrap();
+        store.create("task", &request, &status).unwrap();
+
+        let mut client = Client::connect(&database.url, NoTls).unwrap();
+        client
+            .execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity
+                 WHERE datname = current_database() AND pid <> pg_backend_pid()",
+                &[],
+            )
+            .unwrap();
+
+        // Call that finds connection closed fails, next one reconnects
+        let _ = store.get("task");
+        assert!(store.get("task").unwrap().is_some());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::server::models::requests::CreateTaskRequest;
+use crate::server::models::responses::GetStatusResponse;
+use crate::task_store::task_store::{
+    IdempotencyKeyRecord, TaskRecord, TaskStore, TaskStoreError, FOR_EACH_BATCH_SIZE,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Task store that keeps tasks in embedded SQLite database file.
+/// Request and status of every task are stored as json.
+pub struct SqliteTaskStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteTaskStore {
+    /// Opens database file, creates it and its tables if they don't exist
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<SqliteTaskStore> {
+        SqliteTaskStore::from_connection(Connection::open(path)?)
+    }
+
+    /// Creates store in memory, tasks are lost when it is dropped
+    pub fn open_in_memory() -> rusqlite::Result<SqliteTaskStore> {
+        SqliteTaskStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> rusqlite::Result<SqliteTaskStore> {
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS tasks (
+                 sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+                 id TEXT NOT NULL UNIQUE,
+                 request TEXT NOT NULL,
+                 status TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS idempotency_keys (
+                 client_id TEXT NOT NULL,
+                 key TEXT NOT NULL,
+                 fingerprint TEXT NOT NULL,
+                 task_id TEXT NOT NULL,
+                 created_at INTEGER NOT NULL,
+                 PRIMARY KEY (client_id, key)
+             );
+             CREATE INDEX IF NOT EXISTS idempotency_keys_created_at
+                 ON idempotency_keys (created_at);",
+        )?;
+
+        Ok(SqliteTaskStore {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl From<rusqlite::Error> for TaskStoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        TaskStoreError(err.to_string())
+    }
+}
+
+impl TaskStore for SqliteTaskStore {
+    fn create(
+        &self,
+        id: &str,
+        request: &CreateTaskRequest,
+        status: &GetStatusResponse,
+    ) -> Result<(), TaskStoreError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO tasks (id, request, status) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET request = excluded.request, status = excluded.status",
+            params![
+                id,
+                serde_json::to_string(request).unwrap(),
+                serde_json::to_string(status).unwrap()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn transition(
+        &self,
+        id: &str,
+        update: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+    ) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        // Connection is locked for whole read-modify-write, so transitions don't interleave
+        let connection = self.connection.lock().unwrap();
+        let Some(mut status) = select_status(&connection, id)? else {
+            return Ok(None);
+        };
+        if !update(&mut status) {
+            return Ok(None);
+        }
+
+        connection.execute(
+            "UPDATE tasks SET status = ?2 WHERE id = ?1",
+            params![id, serde_json::to_string(&status).unwrap()],
+        )?;
+
+        Ok(Some(status))
+    }
+
+    fn get(&self, id: &str) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        select_status(&self.connection.lock().unwrap(), id)
+    }
+
+    fn get_record(&self, id: &str) -> Result<Option<TaskRecord>, TaskStoreError> {
+        let row = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT request, status FROM tasks WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+
+        Ok(row.and_then(|(request, status)| {
+            Some(TaskRecord {
+                id: id.to_string(),
+                request: serde_json::from_str(&request).ok()?,
+                status: serde_json::from_str(&status).ok()?,
+            })
+        }))
+    }
+
+    fn list(&self) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let mut records = Vec::new();
+        self.for_each(&mut |record| records.push(record.clone()))?;
+
+        Ok(records)
+    }
+
+    /// Tasks are read in batches and connection is unlocked while batch is visited,
+    /// so other calls of store don't wait for whole search
+    fn for_each(&self, visit: &mut dyn FnMut(&TaskRecord)) -> Result<(), TaskStoreError> {
+        let mut last_sequence = 0i64;
+        loop {
+            let rows = {
+                let connection = self.connection.lock().unwrap();
+                let mut statement = connection.prepare(
+                    "SELECT sequence, id, request, status FROM tasks
+                     WHERE sequence > ?1 ORDER BY sequence LIMIT ?2",
+                )?;
+                let rows =
+                    statement.query_map(params![last_sequence, FOR_EACH_BATCH_SIZE], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, String>(3)?,
+                        ))
+                    })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            let Some(last_row) = rows.last() else {
+                return Ok(());
+            };
+            last_sequence = last_row.0;
+
+            // Rows that can't be parsed (e.g. written by incompatible version) are skipped
+            for (_, id, request, status) in rows {
+                let (Ok(request), Ok(status)) = (
+                    serde_json::from_str(&request),
+                    serde_json::from_str(&status),
+                ) else {
+                    continue;
+                };
+                visit(&TaskRecord {
+                    id,
+                    request,
+                    status,
+                });
+            }
+        }
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, TaskStoreError> {
+        let connection = self.connection.lock().unwrap();
+        let deleted = connection.execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn put_idempotency_key(&self, record: &IdempotencyKeyRecord) -> Result<(), TaskStoreError> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO idempotency_keys (client_id, key, fingerprint, task_id, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (client_id, key) DO UPDATE SET fingerprint = excluded.fingerprint,
+                 task_id = excluded.task_id, created_at = excluded.created_at",
+            params![
+                record.client_id,
+                record.key,
+                record.fingerprint,
+                record.task_id,
+                record.created_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_idempotency_key(
+        &self,
+        client_id: &str,
+        key: &str,
+    ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT fingerprint, task_id, created_at FROM idempotency_keys
+                 WHERE client_id = ?1 AND key = ?2",
+                params![client_id, key],
+                |row| {
+                    Ok(IdempotencyKeyRecord {
+                        client_id: client_id.to_string(),
+                        key: key.to_string(),
+                        fingerprint: row.get(0)?,
+                        task_id: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn purge_idempotency_keys(&self, created_before: i64) -> Result<(), TaskStoreError> {
+        self.connection.lock().unwrap().execute(
+            "DELETE FROM idempotency_keys WHERE created_at < ?1",
+            params![created_before],
+        )?;
+
+        Ok(())
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+}
+
+fn select_status(
+    connection: &Connection,
+    id: &str,
+) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+    let status = connection
+        .query_row(
+            "SELECT status FROM tasks WHERE id = ?1",
+            params![id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    Ok(status.and_then(|status| serde_json::from_str(&status).ok()))
+}
+
+#[cfg(test)]
+mod test_sqlite_task_store {
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::task_store::sqlite_task_store::SqliteTaskStore;
+    use crate::task_store::task_store::test_task_store::check_task_store;
+    use crate::task_store::task_store::TaskStore;
+
+    #[test]
+    fn test_sqlite_task_store() {
+        check_task_store(&SqliteTaskStore::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_tasks_outlive_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("tasks.sqlite");
+
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let mut status = GetStatusResponse::new_utc_status();
+        status.status = TaskStatusEnum::SUCCESS;
+        SqliteTaskStore::open(&path)
+            .unwrap()
+            .create("first", &request, &status)
+            .unwrap();
+
+        let store = SqliteTaskStore::open(&path).unwrap();
+        let records = store.list().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].request.file, "print(1)");
+        assert_eq!(records[0].status.status, TaskStatusEnum::SUCCESS);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::server::models::requests::CreateTaskRequest;
+use crate::server::models::responses::GetStatusResponse;
+
+use chashmap::CHashMap;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Default path of SQLite database of task store
+pub const DEFAULT_SQLITE_PATH: &str = "tasksolver.sqlite";
+
+/// Amount of tasks that are read from database at once when all tasks are visited
+pub(crate) const FOR_EACH_BATCH_SIZE: i64 = 500;
+
+/// Where server keeps tasks
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TaskStoreKind {
+    /// In memory of server process, tasks are lost on restart
+    Memory,
+    /// In embedded SQLite database file
+    Sqlite,
+    /// In PostgreSQL database that can be shared by several servers
+    Postgres,
+}
+
+/// Error of task store or journal, e.g. database can't be reached
+#[derive(Debug, Clone)]
+pub struct TaskStoreError(pub String);
+
+impl std::fmt::Display for TaskStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "task store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for TaskStoreError {}
+
+impl From<io::Error> for TaskStoreError {
+    fn from(err: io::Error) -> Self {
+        TaskStoreError(err.to_string())
+    }
+}
+
+/// Stored task: its request and latest status
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub id: String,
+    pub request: CreateTaskRequest,
+    pub status: GetStatusResponse,
+}
+
+/// Task created with idempotency key of client
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IdempotencyKeyRecord {
+    pub client_id: String,
+    pub key: String,
+    /// Hash of request that created task
+    pub fingerprint: String,
+    pub task_id: String,
+    /// Unix time in milliseconds when key was first used
+    pub created_at: i64,
+}
+
+/// Storage of tasks and their statuses. Persistent stores block while they
+/// wait for database, so async code calls them on threads for blocking calls.
+pub trait TaskStore: Send + Sync {
+    /// Stores new task. If task with given id exists, its request and status are replaced.
+    fn create(
+        &self,
+        id: &str,
+        request: &CreateTaskRequest,
+        status: &GetStatusResponse,
+    ) -> Result<(), TaskStoreError>;
+
+    /// Atomically changes status of task with given function. Function returns false
+    /// if status must not be changed (e.g. task is not in expected status).
+    /// Returns new status, or None if task doesn't exist or wasn't changed.
+    fn transition(
+        &self,
+        id: &str,
+        update: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+    ) -> Result<Option<GetStatusResponse>, TaskStoreError>;
+
+    /// Returns status of task
+    fn get(&self, id: &str) -> Result<Option<GetStatusResponse>, TaskStoreError>;
+
+    /// Returns request and status of task
+    fn get_record(&self, id: &str) -> Result<Option<TaskRecord>, TaskStoreError>;
+
+    /// Returns all tasks in order of creation
+    fn list(&self) -> Result<Vec<TaskRecord>, TaskStoreError>;
+
+    /// Calls function with every task in order of creation. Unlike list, stores
+    /// don't keep all tasks in memory at once, so it is used to search through them.
+    fn for_each(&self, visit: &mut dyn FnMut(&TaskRecord)) -> Result<(), TaskStoreError> {
+        for record in self.list()? {
+            visit(&record);
+        }
+
+        Ok(())
+    }
+
+    /// Deletes task, returns false if it doesn't exist
+    fn delete(&self, id: &str) -> Result<bool, TaskStoreError>;
+
+    /// Stores task created with idempotency key, replaces record of same key of client
+    fn put_idempotency_key(&self, record: &IdempotencyKeyRecord) -> Result<(), TaskStoreError>;
+
+    /// Returns task created with idempotency key of client
+    fn get_idempotency_key(
+        &self,
+        client_id: &str,
+        key: &str,
+    ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError>;
+
+    /// Deletes idempotency keys that were first used before given unix time in milliseconds
+    fn purge_idempotency_keys(&self, created_before: i64) -> Result<(), TaskStoreError>;
+
+    /// Returns true if tasks outlive server process
+    fn is_persistent(&self) -> bool {
+        false
+    }
+
+    /// Returns true if several servers can use store at the same time
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+/// Queue of waiting tasks shared by several servers, every task is taken by one of them
+pub trait SharedTaskQueue: Send + Sync {
+    /// Adds task to the end of queue, task that is already in queue is not added again
+    fn push(&self, id: &str) -> Result<(), TaskStoreError>;
+
+    /// Claims first task of queue that is not claimed by any server, returns None
+    /// if there is no such task. Claimed task stays in queue until it is completed
+    /// or released, so it is not lost if server stops before starting it.
+    fn pop(&self) -> Result<Option<TaskRecord>, TaskStoreError>;
+
+    /// Removes claimed task from queue once it was started or can't be started anymore
+    fn complete(&self, id: &str) -> Result<(), TaskStoreError>;
+
+    /// Returns claimed task to queue for other servers, adds task if it is not in queue
+    fn release(&self, id: &str) -> Result<(), TaskStoreError>;
+}
+
+/// Task with number that orders tasks by creation
+struct MemoryTask {
+    sequence: u64,
+    record: TaskRecord,
+}
+
+/// Idempotency keys by client id and key, with index ordered by time of first use
+#[derive(Default)]
+struct MemoryIdempotencyKeys {
+    records: HashMap<(String, String), IdempotencyKeyRecord>,
+    by_creation: BTreeSet<(i64, String, String)>,
+}
+
+/// Task store that keeps tasks in memory of server process
+#[derive(Default)]
+pub struct MemoryTaskStore {
+    tasks: CHashMap<String, MemoryTask>,
+    /// Ids of tasks by their sequence numbers
+    order: Mutex<BTreeMap<u64, String>>,
+    next_sequence: AtomicU64,
+    idempotency_keys: Mutex<MemoryIdempotencyKeys>,
+}
+
+impl MemoryTaskStore {
+    /// Creates empty store
+    pub fn new() -> MemoryTaskStore {
+        MemoryTaskStore::default()
+    }
+}
+
+impl TaskStore for MemoryTaskStore {
+    fn create(
+        &self,
+        id: &str,
+        request: &CreateTaskRequest,
+        status: &GetStatusResponse,
+    ) -> Result<(), TaskStoreError> {
+        let record = TaskRecord {
+            id: id.to_string(),
+            request: request.clone(),
+            status: status.clone(),
+        };
+        if let Some(mut task) = self.tasks.get_mut(id) {
+            task.record = record;
+            return Ok(());
+        }
+
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        self.order.lock().unwrap().insert(sequence, id.to_string());
+        self.tasks
+            .insert(id.to_string(), MemoryTask { sequence, record });
+
+        Ok(())
+    }
+
+    fn transition(
+        &self,
+        id: &str,
+        update: &mut dyn FnMut(&mut GetStatusResponse) -> bool,
+    ) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        let Some(mut task) = self.tasks.get_mut(id) else {
+            return Ok(None);
+        };
+        Ok(update(&mut task.record.status).then(|| task.record.status.clone()))
+    }
+
+    fn get(&self, id: &str) -> Result<Option<GetStatusResponse>, TaskStoreError> {
+        Ok(self.tasks.get(id).map(|task| task.record.status.clone()))
+    }
+
+    fn get_record(&self, id: &str) -> Result<Option<TaskRecord>, TaskStoreError> {
+        Ok(self.tasks.get(id).map(|task| task.record.clone()))
+    }
+
+    fn list(&self) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let ids: Vec<String> = self.order.lock().unwrap().values().cloned().collect();
+        Ok(ids
+            .iter()
+            .filter_map(|id| self.tasks.get(id).map(|task| task.record.clone()))
+            .collect())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(&TaskRecord)) -> Result<(), TaskStoreError> {
+        let ids: Vec<String> = self.order.lock().unwrap().values().cloned().collect();
+        for id in ids {
+            if let Some(task) = self.tasks.get(&id) {
+                visit(&task.record);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> Result<bool, TaskStoreError> {
+        let Some(task) = self.tasks.remove(id) else {
+            return Ok(false);
+        };
+        self.order.lock().unwrap().remove(&task.sequence);
+
+        Ok(true)
+    }
+
+    fn put_idempotency_key(&self, record: &IdempotencyKeyRecord) -> Result<(), TaskStoreError> {
+        let mut idempotency_keys = self.idempotency_keys.lock().unwrap();
+        let entry_key = (record.client_id.clone(), record.key.clone());
+        if let Some(previous) = idempotency_keys.records.insert(entry_key, record.clone()) {
+            idempotency_keys.by_creation.remove(&(
+                previous.created_at,
+                previous.client_id,
+                previous.key,
+            ));
+        }
+        idempotency_keys.by_creation.insert((
+            record.created_at,
+            record.client_id.clone(),
+            record.key.clone(),
+        ));
+
+        Ok(())
+    }
+
+    fn get_idempotency_key(
+        &self,
+        client_id: &str,
+        key: &str,
+    ) -> Result<Option<IdempotencyKeyRecord>, TaskStoreError> {
+        let idempotency_keys = self.idempotency_keys.lock().unwrap();
+        Ok(idempotency_keys
+            .records
+            .get(&(client_id.to_string(), key.to_string()))
+            .cloned())
+    }
+
+    fn purge_idempotency_keys(&self, created_before: i64) -> Result<(), TaskStoreError> {
+        let mut idempotency_keys = self.idempotency_keys.lock().unwrap();
+        while let Some(first) = idempotency_keys.by_creation.first() {
+            if first.0 >= created_before {
+                break;
+            }
+            let (_, client_id, key) = idempotency_keys.by_creation.pop_first().unwrap();
+            idempotency_keys.records.remove(&(client_id, key));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_task_store {
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::models::responses::{GetStatusResponse, TaskStatusEnum};
+    use crate::task_store::task_store::{IdempotencyKeyRecord, MemoryTaskStore, TaskStore};
+
+    /// Checks behaviour that every task store must have
+    pub(crate) fn check_task_store(store: &dyn TaskStore) {
+        let request =
+            CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+        let status = GetStatusResponse::new_utc_status();
+        store.create("first", &request, &status).unwrap();
+        store.create("second", &request, &status).unwrap();
+
+        let running = store.transition("first", &mut |status| {
+            if status.status != TaskStatusEnum::WAIT {
+                return false;
+            }
+            status.status = TaskStatusEnum::RUNNING;
+            true
+        });
+        assert_eq!(running.unwrap().unwrap().status, TaskStatusEnum::RUNNING);
+        assert!(store
+            .transition("first", &mut |status| status.status == TaskStatusEnum::WAIT)
+            .unwrap()
+            .is_none());
+        assert!(store.transition("third", &mut |_| true).unwrap().is_none());
+
+        assert_eq!(
+            store.get("first").unwrap().unwrap().status,
+            TaskStatusEnum::RUNNING
+        );
+        assert!(store.get("third").unwrap().is_none());
+        let record = store.get_record("first").unwrap().unwrap();
+        assert_eq!(record.request.file, "print(1)");
+        assert_eq!(record.status.status, TaskStatusEnum::RUNNING);
+        assert!(store.get_record("third").unwrap().is_none());
+
+        let ids: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.id)
+            .collect();
+        assert_eq!(ids, vec!["first", "second"]);
+        let mut visited_ids = Vec::new();
+        store
+            .for_each(&mut |record| visited_ids.push(record.id.clone()))
+            .unwrap();
+        assert_eq!(visited_ids, ids);
+
+        assert!(store.delete("first").unwrap());
+        assert!(!store.delete("first").unwrap());
+        assert!(store.get("first").unwrap().is_none());
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        check_idempotency_keys(store);
+    }
+
+    fn check_idempotency_keys(store: &dyn TaskStore) {
+        let record = |key: &str, task_id: &str, created_at| IdempotencyKeyRecord {
+            client_id: "client".to_string(),
+            key: key.to_string(),
+            fingerprint: "fingerprint".to_string(),
+            task_id: task_id.to_string(),
+            created_at,
+        };
+        store
+            .put_idempotency_key(&record("old", "first", 10))
+            .unwrap();
+        store
+            .put_idempotency_key(&record("new", "second", 30))
+            .unwrap();
+        assert_eq!(
+            store.get_idempotency_key("client", "old").unwrap(),
+            Some(record("old", "first", 10))
+        );
+        assert!(store.get_idempotency_key("other", "old").unwrap().is_none());
+
+        store
+            .put_idempotency_key(&record("old", "third", 20))
+            .unwrap();
+        assert_eq!(
+            store.get_idempotency_key("client", "old").unwrap(),
+            Some(record("old", "third", 20))
+        );
+
+        store.purge_idempotency_keys(25).unwrap();
+        assert!(store
+            .get_idempotency_key("client", "old")
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_idempotency_key("client", "new")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_memory_task_store() {
+        check_task_store(&MemoryTaskStore::new());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::server::models::requests::{CreateTaskRequest, TaskType};
+
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+/// Tasks that concurrency limit applies to
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimitKey {
+    /// Tasks of given type
+    TaskType(TaskType),
+    /// Tasks of given queue
+    Queue(String),
+    /// Tasks that have label with given name and value
+    Label(String, String),
+}
+
+impl LimitKey {
+    /// Returns true if task from given queue is limited by key
+    pub fn matches(&self, request: &CreateTaskRequest, queue: &str) -> bool {
+        match self {
+            LimitKey::TaskType(task_type) => request.task_type == *task_type,
+            LimitKey::Queue(name) => name == queue,
+            LimitKey::Label(name, value) => request.labels.get(name) == Some(value),
+        }
+    }
+}
+
+impl fmt::Display for LimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitKey::TaskType(task_type) => {
+                let task_type = task_type.to_possible_value().unwrap();
+                write!(f, "type:{}", task_type.get_name())
+            }
+            LimitKey::Queue(name) => write!(f, "queue:{}", name),
+            LimitKey::Label(name, value) => write!(f, "label:{}={}", name, value),
+        }
+    }
+}
+
+impl FromStr for LimitKey {
+    type Err = String;
+
+    /// Parses key in format "type:TYPE", "queue:NAME" or "label:NAME=VALUE"
+    fn from_str(value: &str) -> Result<LimitKey, String> {
+        match value.split_once(':') {
+            Some(("type", task_type)) => {
+                Ok(LimitKey::TaskType(TaskType::from_str(task_type, true)?))
+            }
+            Some(("queue", name)) => Ok(LimitKey::Queue(name.to_string())),
+            Some(("label", label)) => {
+                let (name, value) = label
+                    .split_once('=')
+                    .ok_or("label limit must be in format label:name=value")?;
+                Ok(LimitKey::Label(name.to_string(), value.to_string()))
+            }
+            _ => Err(format!(
+                "invalid limit key {}, expected type:TYPE, queue:NAME or label:NAME=VALUE",
+                value
+            )),
+        }
+    }
+}
+
+/// Maximum amount of running tasks that match key
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConcurrencyLimit {
+    pub key: LimitKey,
+    pub limit: usize,
+}
+
+impl FromStr for ConcurrencyLimit {
+    type Err = String;
+
+    /// Parses limit in format "KEY=LIMIT", e.g. "type:bin=2" or "label:tool=matlab=2"
+    fn from_str(value: &str) -> Result<ConcurrencyLimit, String> {
+        let (key, limit) = value
+            .rsplit_once('=')
+            .ok_or("concurrency limit must be in format key=limit")?;
+        let limit = limit
+            .parse::<usize>()
+            .ok()
+            .filter(|&limit| limit > 0)
+            .ok_or("limit must be positive integer")?;
+
+        Ok(ConcurrencyLimit {
+            key: LimitKey::from_str(key)?,
+            limit,
+        })
+    }
+}
+
+/// Concurrency limit with amount of running tasks that it limits
+struct LimitCounter {
+    limit: ConcurrencyLimit,
+    running: usize,
+}
+
+/// Counters of running tasks of every concurrency limit
+#[derive(Default)]
+pub(crate) struct LimitCounters {
+    counters: Vec<LimitCounter>,
+}
+
+impl LimitCounters {
+    /// Returns true if task from given queue doesn't exceed any limit
+    pub(crate) fn has_room(&self, request: &CreateTaskRequest, queue: &str) -> bool {
+        self.counters.iter().all(|counter| {
+            !counter.limit.key.matches(request, queue) || counter.running < counter.limit.limit
+        })
+    }
+
+    /// Counts task as running in every limit that matches it.
+    /// Returns indices of these limits to release them later.
+    pub(crate) fn acquire(&mut self, request: &CreateTaskRequest, queue: &str) -> Vec<usize> {
+        let mut acquired = Vec::new();
+        for (index, counter) in self.counters.iter_mut().enumerate() {
+            if counter.limit.key.matches(request, queue) {
+                counter.running += 1;
+                acquired.push(index);
+            }
+        }
+
+        acquired
+    }
+}
+
+/// Concurrency limits shared by all queues of worker pool
+#[derive(Default)]
+pub struct ConcurrencyLimits {
+    counters: Mutex<LimitCounters>,
+    /// Wakes workers of all queues when running task frees its limits
+    notify: Notify,
+}
+
+impl ConcurrencyLimits {
+    /// Creates empty set of limits
+    pub fn new() -> ConcurrencyLimits {
+        ConcurrencyLimits::default()
+    }
+
+    /// Adds limit, limit with same key is replaced
+    pub fn add(&self, limit: ConcurrencyLimit) {
+        let mut counters = self.counters.lock().unwrap();
+        match counters
+            .counters
+            .iter_mut()
+            .find(|counter| counter.limit.key == limit.key)
+        {
+            Some(counter) => counter.limit = limit,
+            None => counters.counters.push(LimitCounter { limit, running: 0 }),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, LimitCounters> {
+        self.counters.lock().unwrap()
+    }
+
+    pub(crate) fn notify(&self) -> &Notify {
+        &self.notify
+    }
+
+    /// Frees limits acquired by finished task and wakes workers that wait for them
+    pub(crate) fn release(&self, acquired: &[usize]) {
+        if acquired.is_empty() {
+            return;
+        }
+
+        {
+            let mut counters = self.counters.lock().unwrap();
+            for &index in acquired {
+                counters.counters[index].running -= 1;
+            }
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// Returns every limit with amount of tasks that it currently limits
+    pub fn get_limits(&self) -> Vec<(ConcurrencyLimit, usize)> {
+        self.counters
+            .lock()
+            .unwrap()
+            .counters
+            .iter()
+            .map(|counter| (counter.limit.clone(), counter.running))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_concurrency_limits {
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::worker_pool::concurrency_limits::{ConcurrencyLimit, ConcurrencyLimits, LimitKey};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_concurrency_limit() {
+        let limit = ConcurrencyLimit::from_str("type:bin=2").unwrap();
+        assert_eq!(limit.key, LimitKey::TaskType(TaskType::Bin));
+        assert_eq!(limit.limit, 2);
+
+        let limit = ConcurrencyLimit::from_str("label:tool=matlab=1").unwrap();
+        assert_eq!(
+            limit.key,
+            LimitKey::Label("tool".to_string(), "matlab".to_string())
+        );
+        assert_eq!(limit.key.to_string(), "label:tool=matlab");
+
+        assert!(ConcurrencyLimit::from_str("type:bin").is_err());
+        assert!(ConcurrencyLimit::from_str("type:bin=0").is_err());
+        assert!(ConcurrencyLimit::from_str("type:java=1").is_err());
+        assert!(ConcurrencyLimit::from_str("user:bob=1").is_err());
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let limits = ConcurrencyLimits::new();
+        limits.add(ConcurrencyLimit::from_str("type:bin=1").unwrap());
+        limits.add(ConcurrencyLimit::from_str("queue:heavy=2").unwrap());
+        let request = CreateTaskRequest::new(TaskType::Bin, "".to_string(), "".to_string());
+
+        let acquired = limits.lock().acquire(&request, "heavy");
+        assert_eq!(acquired, vec![0, 1]);
+        assert!(!limits.lock().has_room(&request, "default"));
+
+        let python_request =
+            CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string());
+        assert!(limits.lock().has_room(&python_request, "heavy"));
+
+        limits.release(&acquired);
+        assert!(limits.lock().has_room(&request, "heavy"));
+        assert_eq!(limits.get_limits()[1].1, 0);
+    }
+}
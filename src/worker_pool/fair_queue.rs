@@ -0,0 +1,542 @@
+use crate::worker_pool::concurrency_limits::{ConcurrencyLimits, LimitKey};
+use crate::worker_pool::worker_pool::TaskInfo;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Client that tasks without client id belong to
+pub const DEFAULT_CLIENT_ID: &str = "default";
+
+/// Weight of clients that don't have configured weight
+pub const DEFAULT_CLIENT_WEIGHT: u32 = 1;
+
+/// Default time in milliseconds that smaller tasks can overtake task that waits for free slots
+pub const DEFAULT_BACKFILL_WINDOW_MS: u64 = 30000;
+
+#[derive(Default)]
+struct FairQueueState {
+    /// Waiting tasks of every client
+    queues: HashMap<String, VecDeque<TaskInfo>>,
+    /// Clients that have waiting tasks, in order of their turn
+    active_clients: VecDeque<String>,
+    /// Amount of tasks that client can still take in its current turn
+    deficits: HashMap<String, u32>,
+    /// Amount of tasks that client takes per turn
+    weights: HashMap<String, u32>,
+    /// Set when queue is closed, after that new tasks are rejected
+    closed: bool,
+    /// Amount of slots that running tasks can use
+    total_slots: usize,
+    /// Indices of slots that are not used by running tasks
+    free_slots: BTreeSet<usize>,
+    /// CPU cores that tasks are pinned to, by index of slot
+    slot_cpus: Vec<usize>,
+    /// Task that is next in turn but waits for free slots, and when it started waiting
+    blocked_head: Option<(String, Instant)>,
+    /// Time that smaller tasks can overtake blocked task
+    backfill_window: Duration,
+}
+
+/// Amount of slots that task takes, tasks never take more than all slots
+fn required_slots(task_info: &TaskInfo, total_slots: usize) -> usize {
+    task_info.task_request.required_slots().min(total_slots)
+}
+
+impl FairQueueState {
+    fn weight(&self, client_id: &str) -> u32 {
+        self.weights
+            .get(client_id)
+            .copied()
+            .unwrap_or(DEFAULT_CLIENT_WEIGHT)
+    }
+
+    /// Takes next task by deficit round robin: every client in its turn
+    /// takes as many tasks as its weight, then turn goes to next client
+    fn pop(&mut self) -> Option<TaskInfo> {
+        let client_id = self.active_clients.front()?.clone();
+        Some(self.take(&client_id, 0))
+    }
+
+    /// Takes task at given position of queue of client. If it is turn of client,
+    /// task counts towards its weight.
+    fn take(&mut self, client_id: &str, position: usize) -> TaskInfo {
+        let is_turn = self
+            .active_clients
+            .front()
+            .is_some_and(|active_client| active_client == client_id);
+        let weight = self.weight(client_id);
+
+        let queue = self.queues.get_mut(client_id).unwrap();
+        let task_info = queue.remove(position).unwrap();
+
+        if queue.is_empty() {
+            self.queues.remove(client_id);
+            self.deficits.remove(client_id);
+            self.active_clients
+                .retain(|active_client| active_client != client_id);
+        } else if is_turn {
+            let deficit = self.deficits.entry(client_id.to_string()).or_insert(0);
+            if *deficit == 0 {
+                *deficit = weight;
+            }
+            *deficit -= 1;
+            if *deficit == 0 {
+                self.active_clients.rotate_left(1);
+            }
+        }
+
+        task_info
+    }
+
+    /// Returns client and position of first task in order of turns that satisfies predicate
+    fn find(&self, predicate: impl Fn(&TaskInfo) -> bool) -> Option<(String, usize)> {
+        self.active_clients.iter().find_map(|client_id| {
+            self.queues[client_id]
+                .iter()
+                .position(&predicate)
+                .map(|position| (client_id.clone(), position))
+        })
+    }
+
+    /// Takes next task that fits into free slots and is allowed by concurrency limits.
+    /// Tasks held back by concurrency limits are skipped. If task that is next in turn
+    /// doesn't fit into free slots, first task that fits is taken instead, but only
+    /// during backfill window, after that blocked task waits for running tasks to free their slots.
+    fn pop_admitted(
+        &mut self,
+        queue_name: &str,
+        concurrency_limits: &ConcurrencyLimits,
+    ) -> Option<(TaskInfo, Vec<usize>, Vec<usize>)> {
+        let mut limit_counters = concurrency_limits.lock();
+        let is_allowed =
+            |task_info: &TaskInfo| limit_counters.has_room(&task_info.task_request, queue_name);
+
+        let (mut client_id, mut position) = self.find(is_allowed)?;
+        let head = &self.queues[&client_id][position];
+        if required_slots(head, self.total_slots) <= self.free_slots.len() {
+            self.blocked_head = None;
+        } else {
+            let head_id = head.id.clone();
+            let blocked_since = match &self.blocked_head {
+                Some((id, blocked_since)) if *id == head_id => *blocked_since,
+                _ => {
+                    let now = Instant::now();
+                    self.blocked_head = Some((head_id, now));
+                    now
+                }
+            };
+            if blocked_since.elapsed() >= self.backfill_window {
+                return None;
+            }
+
+            let (total_slots, free_slots) = (self.total_slots, self.free_slots.len());
+            (client_id, position) = self.find(|task_info| {
+                is_allowed(task_info) && required_slots(task_info, total_slots) <= free_slots
+            })?;
+        }
+
+        let task_info = self.take(&client_id, position);
+        let slots = (0..required_slots(&task_info, self.total_slots))
+            .map(|_| self.free_slots.pop_first().unwrap())
+            .collect();
+        let limits = limit_counters.acquire(&task_info.task_request, queue_name);
+
+        Some((task_info, slots, limits))
+    }
+
+    /// Returns CPU cores of given slots, or nothing if tasks are not pinned
+    fn cpus(&self, slots: &[usize]) -> Vec<usize> {
+        if self.slot_cpus.is_empty() {
+            return Vec::new();
+        }
+
+        let cpus: BTreeSet<usize> = slots.iter().map(|&slot| self.slot_cpus[slot]).collect();
+        cpus.into_iter().collect()
+    }
+}
+
+/// Slots and concurrency limits taken by running task, they are freed when permit is dropped
+pub struct SlotsPermit<'a> {
+    fair_queue: &'a FairQueue,
+    /// Indices of taken slots
+    slots: Vec<usize>,
+    /// CPU cores of taken slots that task is pinned to
+    cpus: Vec<usize>,
+    /// Indices of concurrency limits that task counts towards
+    limits: Vec<usize>,
+}
+
+impl SlotsPermit<'_> {
+    /// Returns amount of taken slots
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns CPU cores that task must be pinned to, empty if tasks are not pinned
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+}
+
+impl Drop for SlotsPermit<'_> {
+    fn drop(&mut self) {
+        self.fair_queue.release(&self.slots);
+        self.fair_queue.concurrency_limits.release(&self.limits);
+    }
+}
+
+/// Queue of tasks that shares workers between clients
+/// in proportion to their weights and admits tasks
+/// only when there are enough free slots for them
+/// and concurrency limits allow them
+pub struct FairQueue {
+    /// Name of queue, used to match concurrency limits
+    name: String,
+    state: Mutex<FairQueueState>,
+    /// Limits of running tasks shared with other queues
+    concurrency_limits: Arc<ConcurrencyLimits>,
+    /// Wakes workers that wait for tasks or free slots
+    notify: Notify,
+}
+
+impl FairQueue {
+    /// Creates empty queue with given amount of slots where every client has default weight
+    pub fn new(slots: usize) -> FairQueue {
+        FairQueue::with_concurrency_limits("default", slots, Arc::new(ConcurrencyLimits::new()))
+    }
+
+    /// Creates empty named queue with given amount of slots that respects given concurrency limits
+    pub fn with_concurrency_limits(
+        name: &str,
+        slots: usize,
+        concurrency_limits: Arc<ConcurrencyLimits>,
+    ) -> FairQueue {
+        let state = FairQueueState {
+            total_slots: slots,
+            free_slots: (0..slots).collect(),
+            backfill_window: Duration::from_millis(DEFAULT_BACKFILL_WINDOW_MS),
+            ..FairQueueState::default()
+        };
+
+        FairQueue {
+            name: name.to_string(),
+            state: Mutex::new(state),
+            concurrency_limits,
+            notify: Notify::new(),
+        }
+    }
+
+    /// Returns name of queue
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Pins tasks to CPU cores: task that takes slot i runs on core cpus[i].
+    /// If there are fewer cores than slots, cores are reused. Empty list disables pinning.
+    pub fn set_cpus(&self, cpus: &[usize]) {
+        let mut state = self.state.lock().unwrap();
+        state.slot_cpus = match cpus.is_empty() {
+            true => Vec::new(),
+            false => cpus
+                .iter()
+                .copied()
+                .cycle()
+                .take(state.total_slots)
+                .collect(),
+        };
+    }
+
+    /// Sets time that smaller tasks can overtake task that waits for free slots
+    pub fn set_backfill_window(&self, backfill_window: Duration) {
+        self.state.lock().unwrap().backfill_window = backfill_window;
+    }
+
+    /// Sets weights of clients, clients that are not in map have default weight
+    pub fn set_weights(&self, weights: HashMap<String, u32>) {
+        self.state.lock().unwrap().weights = weights
+            .into_iter()
+            .map(|(client_id, weight)| (client_id, weight.max(1)))
+            .collect();
+    }
+
+    /// Puts task at the end of queue of its client.
+    /// Returns task back if queue is closed.
+    pub fn push(&self, task_info: TaskInfo) -> Result<(), Box<TaskInfo>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Err(Box::new(task_info));
+            }
+
+            let client_id = task_info
+                .task_request
+                .client_id
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string());
+            if !state.queues.contains_key(&client_id) {
+                state.active_clients.push_back(client_id.clone());
+            }
+            state
+                .queues
+                .entry(client_id)
+                .or_default()
+                .push_back(task_info);
+        }
+
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Takes next task without waiting and without taking slots
+    pub fn try_pop(&self) -> Option<TaskInfo> {
+        self.state.lock().unwrap().pop()
+    }
+
+    /// Waits for next task that fits into free slots and is allowed by concurrency limits,
+    /// and takes its slots and limits. Returns None when queue is closed and empty.
+    pub async fn pop(&self) -> Option<(TaskInfo, SlotsPermit<'_>)> {
+        loop {
+            let notified = self.notify.notified();
+            let limits_notified = self.concurrency_limits.notify().notified();
+            tokio::pin!(notified);
+            tokio::pin!(limits_notified);
+            notified.as_mut().enable();
+            limits_notified.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some((task_info, slots, limits)) =
+                    state.pop_admitted(&self.name, &self.concurrency_limits)
+                {
+                    let permit = SlotsPermit {
+                        fair_queue: self,
+                        cpus: state.cpus(&slots),
+                        slots,
+                        limits,
+                    };
+                    return Some((task_info, permit));
+                }
+                if state.closed && state.queues.is_empty() {
+                    return None;
+                }
+            }
+
+            tokio::select! {
+                _ = notified => {}
+                _ = limits_notified => {}
+            }
+        }
+    }
+
+    /// Frees slots of finished task and wakes workers that wait for them
+    fn release(&self, slots: &[usize]) {
+        self.state
+            .lock()
+            .unwrap()
+            .free_slots
+            .extend(slots.iter().copied());
+        self.notify.notify_waiters();
+    }
+
+    /// Closes queue: new tasks are rejected, waiting workers are woken up
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_waiters();
+    }
+
+    /// Returns amount of tasks in queue
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .values()
+            .map(VecDeque::len)
+            .sum()
+    }
+
+    /// Returns true if queue has no tasks
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns amount of slots that are not used by running tasks
+    pub fn free_slots(&self) -> usize {
+        self.state.lock().unwrap().free_slots.len()
+    }
+
+    /// Returns amount of tasks in queue that are limited by given concurrency limit key
+    pub fn len_limited_by(&self, key: &LimitKey) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .values()
+            .flatten()
+            .filter(|task_info| key.matches(&task_info.task_request, &self.name))
+            .count()
+    }
+
+    /// Returns amount of tasks in queue of every client that has them
+    pub fn len_by_client(&self) -> BTreeMap<String, usize> {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .iter()
+            .map(|(client_id, queue)| (client_id.clone(), queue.len()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_fair_queue {
+    use crate::server::models::requests::{CreateTaskRequest, TaskType};
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::fair_queue::FairQueue;
+    use crate::worker_pool::worker_pool::TaskInfo;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn task_info(id: &str, client_id: &str) -> TaskInfo {
+        let mut request = CreateTaskRequest::new(TaskType::Python, "".to_string(), "".to_string());
+        request.client_id = Some(client_id.to_string());
+
+        TaskInfo::new(id.to_string(), request, TaskStatus::new())
+    }
+
+    fn sized_task_info(id: &str, client_id: &str, slots: u32) -> TaskInfo {
+        let mut task_info = task_info(id, client_id);
+        task_info.task_request.slots = Some(slots);
+
+        task_info
+    }
+
+    /// Returns id of task that is admitted within short time
+    async fn pop_admitted(fair_queue: &FairQueue) -> Option<String> {
+        match tokio::time::timeout(Duration::from_millis(50), fair_queue.pop()).await {
+            Ok(Some((task_info, permit))) => {
+                std::mem::forget(permit);
+                Some(task_info.id)
+            }
+            _ => None,
+        }
+    }
+
+    fn pop_ids(fair_queue: &FairQueue) -> Vec<String> {
+        std::iter::from_fn(|| fair_queue.try_pop())
+            .map(|task_info| task_info.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let fair_queue = FairQueue::new(1);
+        for i in 0..3 {
+            let _ = fair_queue.push(task_info(&format!("a{}", i), "a"));
+        }
+        let _ = fair_queue.push(task_info("b0", "b"));
+        let _ = fair_queue.push(task_info("b1", "b"));
+
+        assert_eq!(fair_queue.len(), 5);
+        assert_eq!(fair_queue.len_by_client()["a"], 3);
+        assert_eq!(pop_ids(&fair_queue), vec!["a0", "b0", "a1", "b1", "a2"]);
+        assert!(fair_queue.is_empty());
+    }
+
+    #[test]
+    fn test_weights() {
+        let fair_queue = FairQueue::new(1);
+        fair_queue.set_weights(HashMap::from([("a".to_string(), 2)]));
+        for i in 0..4 {
+            let _ = fair_queue.push(task_info(&format!("a{}", i), "a"));
+            let _ = fair_queue.push(task_info(&format!("b{}", i), "b"));
+        }
+
+        assert_eq!(
+            pop_ids(&fair_queue),
+            vec!["a0", "a1", "b0", "a2", "a3", "b1", "b2", "b3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let fair_queue = FairQueue::new(1);
+        let _ = fair_queue.push(task_info("a0", "a"));
+        fair_queue.close();
+
+        assert!(fair_queue.push(task_info("a1", "a")).is_err());
+        assert!(fair_queue.pop().await.is_some());
+        assert!(fair_queue.pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_slots_backfill() {
+        let fair_queue = FairQueue::new(4);
+        let _ = fair_queue.push(sized_task_info("a0", "a", 2));
+        let _ = fair_queue.push(sized_task_info("b0", "b", 4));
+        let _ = fair_queue.push(sized_task_info("a1", "a", 1));
+        let _ = fair_queue.push(sized_task_info("a2", "a", 1));
+        let _ = fair_queue.push(sized_task_info("a3", "a", 1));
+
+        let (task_info, permit) = fair_queue.pop().await.unwrap();
+        assert_eq!(task_info.id, "a0");
+        assert_eq!(permit.slots(), 2);
+        assert_eq!(fair_queue.free_slots(), 2);
+
+        // b0 doesn't fit, so smaller tasks of a take free slots
+        let (task_info, a1_permit) = fair_queue.pop().await.unwrap();
+        assert_eq!(task_info.id, "a1");
+        assert_eq!(pop_admitted(&fair_queue).await, Some("a2".to_string()));
+        assert_eq!(pop_admitted(&fair_queue).await, None);
+        assert_eq!(fair_queue.free_slots(), 0);
+
+        drop(permit);
+        drop(a1_permit);
+        assert_eq!(fair_queue.free_slots(), 3);
+        assert_eq!(pop_admitted(&fair_queue).await, Some("a3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_slots_cpus() {
+        let fair_queue = FairQueue::new(3);
+        fair_queue.set_cpus(&[4, 5]);
+        let _ = fair_queue.push(sized_task_info("a0", "a", 2));
+        let _ = fair_queue.push(sized_task_info("a1", "a", 1));
+
+        let (_, first_permit) = fair_queue.pop().await.unwrap();
+        assert_eq!(first_permit.cpus(), &[4, 5]);
+        let (_, second_permit) = fair_queue.pop().await.unwrap();
+        // Cores are reused when there are fewer cores than slots
+        assert_eq!(second_permit.cpus(), &[4]);
+
+        drop(first_permit);
+        fair_queue.set_cpus(&[]);
+        let _ = fair_queue.push(sized_task_info("a2", "a", 1));
+        let (_, permit) = fair_queue.pop().await.unwrap();
+        assert!(permit.cpus().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocked_task_is_not_starved() {
+        let fair_queue = FairQueue::new(4);
+        fair_queue.set_backfill_window(Duration::ZERO);
+        let _ = fair_queue.push(sized_task_info("a0", "a", 2));
+        let _ = fair_queue.push(sized_task_info("a1", "a", 1));
+        let _ = fair_queue.push(sized_task_info("b0", "b", 4));
+
+        let (_, permit) = fair_queue.pop().await.unwrap();
+        // Backfill window is over, so a1 waits until b0 gets its slots
+        assert_eq!(pop_admitted(&fair_queue).await, None);
+
+        drop(permit);
+        let (task_info, permit) = fair_queue.pop().await.unwrap();
+        assert_eq!(task_info.id, "b0");
+        assert_eq!(pop_admitted(&fair_queue).await, None);
+
+        drop(permit);
+        assert_eq!(pop_admitted(&fair_queue).await, Some("a1".to_string()));
+    }
+}
@@ -1 +1,4 @@
+pub mod concurrency_limits;
+pub mod fair_queue;
+#[allow(clippy::module_inception)]
 pub mod worker_pool;
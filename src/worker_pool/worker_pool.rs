@@ -1,8 +1,25 @@
-use crate::file_executer::file_executer::execute_file;
-use crate::server::models::requests::CreateTaskRequest;
+use crate::file_executer::file_executer::{execute_task_on_cpus, ExecutionResult};
+use crate::server::models::requests::{CreateTaskRequest, TaskType};
+use crate::server::models::responses::{
+    GetStatusResponse, GetStatusResult, LimitInfo, QueueInfo, ScheduledTaskInfo, TaskAttempt,
+    TaskEventKind,
+};
 use crate::server::server::TaskStatus;
+use crate::task_store::task_store::{SharedTaskQueue, TaskStoreError};
+use crate::worker_pool::concurrency_limits::{ConcurrencyLimit, ConcurrencyLimits};
+use crate::worker_pool::fair_queue::FairQueue;
 
-use tokio::task;
+use chrono::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::{self, JoinHandle};
+
+/// Time between attempts to take task from empty shared queue
+const SHARED_QUEUE_POLL_INTERVAL_MS: u64 = 100;
 
 /// Struct of all needed task information
 pub struct TaskInfo {
@@ -22,58 +39,942 @@ impl TaskInfo {
     }
 }
 
-/// Struct of tokio threads that will be taking tasks from sender and execute them.
+/// Task that waits for its scheduled time before it is put into queue
+struct ScheduledTask {
+    run_at: DateTime<Utc>,
+    task_status: TaskStatus,
+    /// Tokio thread that sleeps until scheduled time
+    timer: JoinHandle<()>,
+}
+
+/// Name of queue that gets tasks without queue and route
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// Configuration of named queue with its own workers
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueConfig {
+    pub name: String,
+    /// Amount of workers that take tasks only from this queue,
+    /// it is also amount of slots that running tasks of queue can take
+    pub workers_count: usize,
+    /// Timeout of tasks that don't set their own
+    pub timeout_ms: Option<u64>,
+    /// Memory limit of tasks that don't set their own
+    pub memory_limit_mb: Option<u64>,
+}
+
+impl QueueConfig {
+    /// Creates queue config without task defaults
+    pub fn new(name: &str, workers_count: usize) -> QueueConfig {
+        QueueConfig {
+            name: name.to_string(),
+            workers_count,
+            timeout_ms: None,
+            memory_limit_mb: None,
+        }
+    }
+}
+
+impl FromStr for QueueConfig {
+    type Err = String;
+
+    /// Parses queue config in format "name=workers[,timeout_ms=N][,memory_limit_mb=N]"
+    fn from_str(value: &str) -> Result<QueueConfig, String> {
+        let mut parts = value.split(',');
+        let (name, workers_count) = parts
+            .next()
+            .and_then(|part| part.split_once('='))
+            .ok_or("queue must be in format name=workers[,timeout_ms=N][,memory_limit_mb=N]")?;
+        let workers_count = workers_count
+            .parse::<usize>()
+            .map_err(|_| format!("invalid amount of workers {}", workers_count))?;
+        let mut config = QueueConfig::new(name, workers_count);
+
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or(format!("invalid queue option {}", part))?;
+            let value = value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid value of queue option {}", key))?;
+            match key {
+                "timeout_ms" => config.timeout_ms = Some(value),
+                "memory_limit_mb" => config.memory_limit_mb = Some(value),
+                _ => return Err(format!("unknown queue option {}", key)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Named queue with counter of its busy workers
+struct WorkerQueue {
+    config: QueueConfig,
+    task_queue: Arc<FairQueue>,
+    busy_workers: Arc<AtomicUsize>,
+}
+
+/// Named queues of worker pool and rules that choose queue of task
+#[derive(Default)]
+struct Queues {
+    queues: HashMap<String, WorkerQueue>,
+    /// Queues of tasks that don't set queue, by type of task
+    routes: HashMap<TaskType, String>,
+    /// Weights of clients, shared by all queues
+    client_weights: HashMap<String, u32>,
+    /// Time that tasks without their own expiration can wait in queue
+    default_max_wait: Option<Duration>,
+    /// Time that smaller tasks can overtake task that waits for free slots, shared by all queues
+    backfill_window: Option<Duration>,
+    /// CPU cores that workers of all queues are pinned to
+    cpus: Vec<usize>,
+    /// Queue shared with other servers, new tasks are put into it
+    /// instead of local queues
+    shared_queue: Option<Arc<dyn SharedTaskQueue>>,
+}
+
+impl Queues {
+    /// Returns queue from request, queue routed by type of task or default queue.
+    /// Tasks with unknown queue (e.g. restored after queue was removed) go to default queue.
+    fn route(&self, request: &CreateTaskRequest) -> Arc<FairQueue> {
+        request
+            .queue
+            .as_ref()
+            .or(self.routes.get(&request.task_type))
+            .and_then(|name| self.queues.get(name))
+            .or(self.queues.get(DEFAULT_QUEUE))
+            .map(|worker_queue| worker_queue.task_queue.clone())
+            .unwrap()
+    }
+
+    /// Gives every worker slot its CPU core: slots of default queue take first cores,
+    /// then slots of other queues in order of their names. Cores are reused if there
+    /// are fewer cores than slots.
+    fn assign_cpus(&self) {
+        let mut names: Vec<&String> = self.queues.keys().collect();
+        names.sort_by_key(|name| (name.as_str() != DEFAULT_QUEUE, name.as_str()));
+
+        let mut cpus = self.cpus.iter().copied().cycle();
+        for name in names {
+            let worker_queue = &self.queues[name];
+            let queue_cpus: Vec<usize> = match self.cpus.is_empty() {
+                true => Vec::new(),
+                false => cpus
+                    .by_ref()
+                    .take(worker_queue.config.workers_count)
+                    .collect(),
+            };
+            worker_queue.task_queue.set_cpus(&queue_cpus);
+        }
+    }
+
+    /// Returns true if local queues have more free slots than tasks waiting for them
+    fn has_free_slots(&self) -> bool {
+        let (free_slots, tasks) =
+            self.queues
+                .values()
+                .fold((0, 0), |(free_slots, tasks), worker_queue| {
+                    (
+                        free_slots + worker_queue.task_queue.free_slots(),
+                        tasks + worker_queue.task_queue.len(),
+                    )
+                });
+
+        free_slots > tasks
+    }
+}
+
+/// Struct of tokio threads that will be taking tasks from named queues and execute them.
 pub struct WorkerPool {
-    /// Amount of threads in thread pool
-    workers_count: usize,
-    /// Tokio sender that sends task id, task request, task status hashmap and current worker pool
-    pub sender: async_channel::Sender<TaskInfo>,
-    /// Tokio receiver that recieve task id, task request, task status hashmap and current worker pool
-    pub receiver: async_channel::Receiver<TaskInfo>,
+    /// Named queues with their workers
+    queues: Arc<Mutex<Queues>>,
+    /// Set when shutdown begins, after that new tasks are not accepted
+    /// and tasks from queue are cancelled instead of executed
+    shutting_down: Arc<AtomicBool>,
+    /// Sender that tells workers to kill their running child processes
+    kill_sender: watch::Sender<bool>,
+    /// Handles of worker threads, taken by shutdown to wait for them
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    /// Tasks that will be put into queue later, by id
+    scheduled_tasks: Arc<Mutex<HashMap<String, ScheduledTask>>>,
+    /// Senders that kill child process of running task, by task id
+    running_tasks: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+    /// Limits of running tasks by type, label or queue, shared by all queues
+    concurrency_limits: Arc<ConcurrencyLimits>,
 }
 
 impl WorkerPool {
-    /// Creates WorkerPool struct with given amount of workes, sender and receiver.
-    pub fn new(
-        workers_count: usize,
-        sender: async_channel::Sender<TaskInfo>,
-        receiver: async_channel::Receiver<TaskInfo>,
-    ) -> WorkerPool {
-        for _ in 0..workers_count {
-            create_worker(receiver.clone());
+    /// Creates WorkerPool struct with default queue with given amount of workers.
+    pub fn new(workers_count: usize) -> WorkerPool {
+        let (kill_sender, _) = watch::channel(false);
+
+        let worker_pool = WorkerPool {
+            queues: Arc::new(Mutex::new(Queues::default())),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            kill_sender,
+            workers: Mutex::new(Vec::new()),
+            scheduled_tasks: Arc::new(Mutex::new(HashMap::new())),
+            running_tasks: Arc::new(Mutex::new(HashMap::new())),
+            concurrency_limits: Arc::new(ConcurrencyLimits::new()),
+        };
+        worker_pool.add_queue(QueueConfig::new(DEFAULT_QUEUE, workers_count));
+
+        worker_pool
+    }
+
+    /// Adds named queue and starts its workers. If queue with same name exists,
+    /// it is replaced, tasks that are already in it are still executed by its old workers.
+    pub fn add_queue(&self, config: QueueConfig) {
+        let task_queue = Arc::new(FairQueue::with_concurrency_limits(
+            &config.name,
+            config.workers_count,
+            self.concurrency_limits.clone(),
+        ));
+        let busy_workers = Arc::new(AtomicUsize::new(0));
+        let mut queues = self.queues.lock().unwrap();
+        task_queue.set_weights(queues.client_weights.clone());
+        if let Some(backfill_window) = queues.backfill_window {
+            task_queue.set_backfill_window(backfill_window);
         }
 
-        WorkerPool {
-            workers_count,
-            sender,
-            receiver,
+        let workers = (0..config.workers_count).map(|worker| {
+            create_worker(
+                worker,
+                task_queue.clone(),
+                config.clone(),
+                busy_workers.clone(),
+                self.shutting_down.clone(),
+                self.kill_sender.subscribe(),
+                self.running_tasks.clone(),
+                self.queues.clone(),
+                self.scheduled_tasks.clone(),
+            )
+        });
+        self.workers.lock().unwrap().extend(workers);
+
+        let worker_queue = WorkerQueue {
+            config: config.clone(),
+            task_queue,
+            busy_workers,
+        };
+        if let Some(old_queue) = queues.queues.insert(config.name, worker_queue) {
+            old_queue.task_queue.close();
+        }
+        queues.assign_cpus();
+    }
+
+    /// Pins every worker slot (and child processes of tasks that take it) to CPU core
+    /// from given list. Empty list disables pinning.
+    pub fn set_cpus(&self, cpus: Vec<usize>) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.cpus = cpus;
+        queues.assign_cpus();
+    }
+
+    /// Routes tasks of given type that don't set queue to given queue
+    pub fn set_route(&self, task_type: TaskType, queue: &str) {
+        self.queues
+            .lock()
+            .unwrap()
+            .routes
+            .insert(task_type, queue.to_string());
+    }
+
+    /// Returns error if task asks for queue that doesn't exist
+    /// or for more slots than its queue has
+    pub fn check_queue(&self, request: &CreateTaskRequest) -> Result<(), String> {
+        let queues = self.queues.lock().unwrap();
+        if let Some(name) = &request.queue {
+            if !queues.queues.contains_key(name) {
+                return Err(format!("queue {} doesn't exist", name));
+            }
+        }
+
+        let name = request
+            .queue
+            .as_ref()
+            .or(queues.routes.get(&request.task_type))
+            .filter(|name| queues.queues.contains_key(*name))
+            .map_or(DEFAULT_QUEUE, String::as_str);
+        let slots = queues.queues[name].config.workers_count;
+        match request.slots {
+            Some(required_slots) if required_slots as usize > slots => Err(format!(
+                "task needs {} slots, but queue {} has only {}",
+                required_slots, name, slots
+            )),
+            _ => Ok(()),
         }
     }
 
-    /// Increases amount of currently working threads and send task in receiver
-    /// for free thread to pick up it
+    /// Puts task into its queue for free worker of that queue to pick up it.
+    /// If queue is already closed by shutdown, task is marked as cancelled.
+    /// If worker pool reads shared queue, task is put into shared queue.
     pub async fn do_task(&self, task_info: TaskInfo) {
-        let _ = self.sender.send(task_info).await;
+        let shared_queue = self.queues.lock().unwrap().shared_queue.clone();
+        if let Some(shared_queue) = shared_queue {
+            push_shared_task(shared_queue, task_info).await;
+            return;
+        }
+
+        if let Err(task_info) = push_task(&self.queues, task_info).await {
+            let id = task_info.id;
+            task_info
+                .task_status
+                .blocking(move |task_status| task_status.clone().cancel_waiting_task(&id))
+                .await;
+        }
+    }
+
+    /// Puts task into queue at given time. Until then task stays scheduled
+    /// and can be cancelled.
+    pub fn schedule_task(&self, task_info: TaskInfo, run_at: DateTime<Utc>) {
+        schedule_task_at(&self.queues, &self.scheduled_tasks, task_info, run_at);
+    }
+
+    /// Stops timer of scheduled task and marks it as cancelled.
+    /// Returns false if task is not scheduled. Calls task store,
+    /// so async code calls it on thread for blocking calls.
+    pub fn cancel_scheduled_task(&self, id: &str) -> bool {
+        let Some(scheduled_task) = self.scheduled_tasks.lock().unwrap().remove(id) else {
+            return false;
+        };
+
+        scheduled_task.timer.abort();
+        let mut task_status = scheduled_task.task_status;
+        task_status.cancel_waiting_task(id)
+    }
+
+    /// Kills child process of running task and marks task as cancelled.
+    /// Returns false if task is not running.
+    pub fn kill_running_task(&self, id: &str) -> bool {
+        match self.running_tasks.lock().unwrap().remove(id) {
+            Some(kill_sender) => kill_sender.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Returns tasks that wait for their scheduled time, ordered by time of run
+    pub fn get_scheduled_tasks(&self) -> Vec<ScheduledTaskInfo> {
+        let scheduled_tasks = self.scheduled_tasks.lock().unwrap();
+        let mut tasks: Vec<(&String, &ScheduledTask)> = scheduled_tasks.iter().collect();
+        tasks.sort_by_key(|(_, scheduled_task)| scheduled_task.run_at);
+
+        tasks
+            .into_iter()
+            .map(|(id, scheduled_task)| ScheduledTaskInfo {
+                id: id.clone(),
+                run_at: scheduled_task.run_at.to_string(),
+            })
+            .collect()
     }
 
     pub fn get_task_amount(&self) -> usize {
-        self.sender.len()
+        self.queues
+            .lock()
+            .unwrap()
+            .queues
+            .values()
+            .map(|worker_queue| worker_queue.task_queue.len())
+            .sum()
+    }
+
+    /// Returns amount of tasks in queues of every client that has them
+    pub fn get_task_amount_by_client(&self) -> BTreeMap<String, usize> {
+        let mut task_amount = BTreeMap::new();
+        for worker_queue in self.queues.lock().unwrap().queues.values() {
+            for (client_id, amount) in worker_queue.task_queue.len_by_client() {
+                *task_amount.entry(client_id).or_insert(0) += amount;
+            }
+        }
+
+        task_amount
+    }
+
+    /// Returns amount of tasks, workers and idle workers of every queue
+    pub fn get_queues_info(&self) -> BTreeMap<String, QueueInfo> {
+        self.queues
+            .lock()
+            .unwrap()
+            .queues
+            .iter()
+            .map(|(name, worker_queue)| {
+                let workers = worker_queue.config.workers_count;
+                let busy_workers = worker_queue.busy_workers.load(Ordering::SeqCst);
+                let queue_info = QueueInfo {
+                    tasks: worker_queue.task_queue.len(),
+                    workers,
+                    idle_workers: workers.saturating_sub(busy_workers),
+                    free_slots: worker_queue.task_queue.free_slots(),
+                };
+
+                (name.clone(), queue_info)
+            })
+            .collect()
+    }
+
+    /// Sets weights of clients, clients that are not in map have weight 1
+    pub fn set_client_weights(&self, weights: HashMap<String, u32>) {
+        let mut queues = self.queues.lock().unwrap();
+        for worker_queue in queues.queues.values() {
+            worker_queue.task_queue.set_weights(weights.clone());
+        }
+        queues.client_weights = weights;
+    }
+
+    /// Adds limit of running tasks that match its key, on top of slots of queues.
+    /// Limit with same key is replaced.
+    pub fn add_concurrency_limit(&self, limit: ConcurrencyLimit) {
+        self.concurrency_limits.add(limit);
+    }
+
+    /// Returns limit, amount of running tasks and amount of tasks in queues
+    /// of every concurrency limit by its key
+    pub fn get_limits_info(&self) -> BTreeMap<String, LimitInfo> {
+        let queues = self.queues.lock().unwrap();
+        self.concurrency_limits
+            .get_limits()
+            .into_iter()
+            .map(|(limit, running)| {
+                let waiting = queues
+                    .queues
+                    .values()
+                    .map(|worker_queue| worker_queue.task_queue.len_limited_by(&limit.key))
+                    .sum();
+                let limit_info = LimitInfo {
+                    limit: limit.limit,
+                    running,
+                    waiting,
+                };
+
+                (limit.key.to_string(), limit_info)
+            })
+            .collect()
+    }
+
+    /// Sets time that smaller tasks can overtake task that waits for free slots
+    pub fn set_backfill_window(&self, backfill_window: Duration) {
+        let mut queues = self.queues.lock().unwrap();
+        for worker_queue in queues.queues.values() {
+            worker_queue.task_queue.set_backfill_window(backfill_window);
+        }
+        queues.backfill_window = Some(backfill_window);
+    }
+
+    /// Sets time that tasks without expires_at or max_wait_ms can wait in queue
+    pub fn set_default_max_wait(&self, max_wait: Duration) {
+        self.queues.lock().unwrap().default_max_wait = Some(max_wait);
     }
+
+    /// Makes worker pool share waiting tasks with other servers: new tasks are put
+    /// into shared queue, and tasks from it are taken into local queues while
+    /// their workers have free slots
+    pub fn read_shared_queue(
+        &self,
+        shared_queue: Arc<dyn SharedTaskQueue>,
+        task_status: TaskStatus,
+    ) {
+        self.queues.lock().unwrap().shared_queue = Some(shared_queue.clone());
+
+        let reader = create_shared_queue_reader(
+            shared_queue,
+            task_status,
+            self.queues.clone(),
+            self.shutting_down.clone(),
+        );
+        self.workers.lock().unwrap().push(reader);
+    }
+
+    /// Returns true if shutdown was started and new tasks must be rejected
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Gracefully shuts worker pool down: stops accepting tasks,
+    /// drops tasks that are still in queue or scheduled and waits for running tasks
+    /// to finish. Tasks that are still running after grace period
+    /// are killed and marked as cancelled.
+    pub async fn shutdown(&self, grace_period: Duration) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let task_queues: Vec<Arc<FairQueue>> = self
+            .queues
+            .lock()
+            .unwrap()
+            .queues
+            .values()
+            .map(|worker_queue| worker_queue.task_queue.clone())
+            .collect();
+        for task_queue in task_queues {
+            task_queue.close();
+            while let Some(task_info) = task_queue.try_pop() {
+                drop_unstarted_task(task_info, &self.queues).await;
+            }
+        }
+
+        let scheduled_tasks: Vec<(String, ScheduledTask)> =
+            self.scheduled_tasks.lock().unwrap().drain().collect();
+        for (id, scheduled_task) in scheduled_tasks {
+            scheduled_task.timer.abort();
+            let mut task_status = scheduled_task.task_status;
+            if !task_status.is_durable() {
+                task_status.cancel_waiting_task(&id);
+            }
+        }
+
+        let workers: Vec<JoinHandle<()>> = self.workers.lock().unwrap().drain(..).collect();
+        let mut waiting_workers = task::spawn(async move {
+            for worker in workers {
+                let _ = worker.await;
+            }
+        });
+
+        if tokio::time::timeout(grace_period, &mut waiting_workers)
+            .await
+            .is_err()
+        {
+            let _ = self.kill_sender.send(true);
+            let _ = waiting_workers.await;
+        }
+    }
+}
+
+/// Starts timer that puts scheduled task into its queue at given time
+fn schedule_task_at(
+    queues: &Arc<Mutex<Queues>>,
+    scheduled_tasks: &Arc<Mutex<HashMap<String, ScheduledTask>>>,
+    task_info: TaskInfo,
+    run_at: DateTime<Utc>,
+) {
+    let id = task_info.id.clone();
+    let task_status = task_info.task_status.clone();
+    let queues = queues.clone();
+    let timer_scheduled_tasks = scheduled_tasks.clone();
+    let delay = (run_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+
+    // Lock is held until timer is registered, so timer can't fire before that
+    let mut scheduled_tasks_guard = scheduled_tasks.lock().unwrap();
+    let timer = task::spawn(async move {
+        tokio::time::sleep(delay).await;
+        timer_scheduled_tasks.lock().unwrap().remove(&task_info.id);
+
+        let enqueued_id = task_info.id.clone();
+        let enqueued = task_info
+            .task_status
+            .blocking(move |task_status| task_status.clone().enqueue_scheduled_task(&enqueued_id))
+            .await;
+        if !enqueued {
+            return;
+        }
+        // Task that is due goes through shared queue like new task, so it is
+        // not lost if this server stops before starting it
+        let shared_queue = queues.lock().unwrap().shared_queue.clone();
+        if let Some(shared_queue) = shared_queue {
+            push_shared_task(shared_queue, task_info).await;
+            return;
+        }
+        if let Err(task_info) = push_task(&queues, task_info).await {
+            drop_unstarted_task(*task_info, &queues).await;
+        }
+    });
+
+    scheduled_tasks_guard.insert(
+        id,
+        ScheduledTask {
+            run_at,
+            task_status,
+            timer,
+        },
+    );
+}
+
+/// Puts task into its queue and records it in history of task. Event is recorded
+/// before push, so it can't follow events of worker that takes task. Store is called
+/// without lock of queues, so if queue was replaced meanwhile, task is routed again.
+/// Returns task back if queue is closed.
+async fn push_task(queues: &Mutex<Queues>, mut task_info: TaskInfo) -> Result<(), Box<TaskInfo>> {
+    loop {
+        let task_queue = queues.lock().unwrap().route(&task_info.task_request);
+        let id = task_info.id.clone();
+        let queue = task_queue.name().to_string();
+        task_info
+            .task_status
+            .blocking(move |task_status| {
+                task_status.record_event(&id, TaskEventKind::Queued { queue })
+            })
+            .await;
+
+        match task_queue.push(task_info) {
+            Ok(()) => return Ok(()),
+            Err(returned_task_info) => {
+                let route = queues
+                    .lock()
+                    .unwrap()
+                    .route(&returned_task_info.task_request);
+                if Arc::ptr_eq(&route, &task_queue) {
+                    return Err(returned_task_info);
+                }
+                task_info = *returned_task_info;
+            }
+        }
+    }
+}
+
+/// Handles task that was taken from queue during shutdown. If task status
+/// is persisted to journal, task is left waiting to be restored on next start,
+/// otherwise it is cancelled. Task taken from shared queue is released
+/// for other servers.
+async fn drop_unstarted_task(task_info: TaskInfo, queues: &Mutex<Queues>) {
+    let shared_queue = queues.lock().unwrap().shared_queue.clone();
+    if let Some(shared_queue) = shared_queue {
+        let id = task_info.id.clone();
+        // Claim of task that can't be released expires after lease
+        if let Err(err) = call_shared_queue(shared_queue, move |queue| queue.release(&id)).await {
+            eprintln!("failed to release task {}: {}", task_info.id, err);
+        }
+        return;
+    }
+
+    if !task_info.task_status.is_durable() {
+        let id = task_info.id;
+        task_info
+            .task_status
+            .blocking(move |task_status| task_status.clone().cancel_waiting_task(&id))
+            .await;
+    }
+}
+
+/// Removes task from shared queue, if worker pool reads it, once task was started
+/// or turned out to be expired or not waiting anymore
+async fn complete_shared_task(id: &str, queues: &Mutex<Queues>) {
+    let shared_queue = queues.lock().unwrap().shared_queue.clone();
+    if let Some(shared_queue) = shared_queue {
+        let task_id = id.to_string();
+        let result = call_shared_queue(shared_queue, move |queue| queue.complete(&task_id)).await;
+        if let Err(err) = result {
+            eprintln!("failed to remove task {} from shared queue: {}", id, err);
+        }
+    }
+}
+
+/// Puts task into shared queue. Task that can't be put there would never
+/// be started, so it is cancelled.
+async fn push_shared_task(shared_queue: Arc<dyn SharedTaskQueue>, task_info: TaskInfo) {
+    let id = task_info.id.clone();
+    if let Err(err) = call_shared_queue(shared_queue, move |queue| queue.push(&id)).await {
+        eprintln!(
+            "failed to put task {} into shared queue: {}",
+            task_info.id, err
+        );
+        task_info
+            .task_status
+            .blocking(move |task_status| task_status.clone().cancel_waiting_task(&task_info.id))
+            .await;
+    }
+}
+
+/// Calls shared queue on thread for blocking calls, since it waits for database
+async fn call_shared_queue<T: Send + 'static>(
+    shared_queue: Arc<dyn SharedTaskQueue>,
+    function: impl FnOnce(&dyn SharedTaskQueue) -> Result<T, TaskStoreError> + Send + 'static,
+) -> Result<T, TaskStoreError> {
+    task::spawn_blocking(move || function(shared_queue.as_ref()))
+        .await
+        .unwrap()
 }
 
-/// Creates tokio thread that will execute python scripts and binary files
-fn create_worker(receiver: async_channel::Receiver<TaskInfo>) {
+/// Creates tokio thread that takes tasks from shared queue into local queues
+/// while their workers have free slots. Thread stops on shutdown.
+fn create_shared_queue_reader(
+    shared_queue: Arc<dyn SharedTaskQueue>,
+    task_status: TaskStatus,
+    queues: Arc<Mutex<Queues>>,
+    shutting_down: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    let poll_interval = Duration::from_millis(SHARED_QUEUE_POLL_INTERVAL_MS);
     task::spawn(async move {
-        loop {
-            if let Ok(task_info) = receiver.recv().await {
-                let mut task_status = task_info.task_status;
-                task_status.start_running_task(&task_info.id);
+        while !shutting_down.load(Ordering::SeqCst) {
+            if !queues.lock().unwrap().has_free_slots() {
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            }
 
-                let (stdout, stderr, execution_result) =
-                    execute_file(task_info.task_request, task_info.id.clone()).await;
+            let record = match call_shared_queue(shared_queue.clone(), |queue| queue.pop()).await {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    tokio::time::sleep(poll_interval).await;
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!("failed to take task from shared queue: {}", err);
+                    tokio::time::sleep(poll_interval).await;
+                    continue;
+                }
+            };
 
-                task_status.finish_running_task(&task_info.id, stdout, stderr, execution_result);
+            let task_info = TaskInfo::new(record.id, record.request, task_status.clone());
+            if let Err(task_info) = push_task(&queues, task_info).await {
+                drop_unstarted_task(*task_info, &queues).await;
             }
         }
+    })
+}
+
+/// Returns time after which task is not started anymore: expires_at of request,
+/// or max_wait_ms (or server default) after task became ready to run, i.e. after
+/// its scheduled time or creation
+fn task_deadline(
+    request: &CreateTaskRequest,
+    status: &GetStatusResponse,
+    default_max_wait: Option<Duration>,
+) -> Option<DateTime<Utc>> {
+    if let Some(expires_at) = &request.expires_at {
+        return expires_at.parse::<DateTime<Utc>>().ok();
+    }
+
+    let max_wait = request
+        .max_wait_ms
+        .map(Duration::from_millis)
+        .or(default_max_wait)?;
+    let ready_at = status
+        .meta
+        .scheduled_for
+        .as_ref()
+        .unwrap_or(&status.meta.created_at)
+        .parse::<DateTime<Utc>>()
+        .ok()?;
+
+    chrono::Duration::from_std(max_wait)
+        .ok()
+        .and_then(|max_wait| ready_at.checked_add_signed(max_wait))
+}
+
+/// Marks task taken from queue as running on given worker, or as expired if its
+/// deadline has passed. Returns false if task was not started.
+fn start_task(
+    task_info: &TaskInfo,
+    default_max_wait: Option<Duration>,
+    queue: &str,
+    worker: usize,
+    cpus: Vec<usize>,
+) -> Result<bool, TaskStoreError> {
+    let mut task_status = task_info.task_status.clone();
+    let deadline = task_deadline(
+        &task_info.task_request,
+        &task_status.get_status_by_id(&task_info.id)?,
+        default_max_wait,
+    );
+    if deadline.is_some_and(|deadline| deadline < Utc::now()) {
+        task_status.expire_task(&task_info.id);
+        return Ok(false);
+    }
+
+    Ok(task_status.start_running_task(&task_info.id, queue, worker, cpus))
+}
+
+/// Finishes task with result of its execution. Failed task with retry policy
+/// that allows another attempt is scheduled again after backoff, unless
+/// shutdown has started.
+fn finish_execution(
+    task_info: TaskInfo,
+    execution_result: ExecutionResult,
+    queues: &Arc<Mutex<Queues>>,
+    scheduled_tasks: &Arc<Mutex<HashMap<String, ScheduledTask>>>,
+    shutting_down: &AtomicBool,
+) {
+    let mut task_status = task_info.task_status.clone();
+    let id = task_info.id.clone();
+
+    // Task whose attempts can't be counted is not retried
+    let status = task_info.task_request.retry.as_ref().and_then(|_| {
+        task_status
+            .get_status_by_id(&id)
+            .inspect_err(|err| eprintln!("failed to read attempts of task {}: {}", id, err))
+            .ok()
     });
+    let (Some(retry), Some(status)) = (task_info.task_request.retry.clone(), status) else {
+        task_status.finish_running_task(
+            &id,
+            execution_result.stdout,
+            execution_result.stderr,
+            execution_result.status,
+            execution_result.artifacts,
+            execution_result.failure,
+        );
+        return;
+    };
+
+    let attempt = TaskAttempt {
+        attempt: status.attempts.len() as u32 + 1,
+        started_at: status.meta.started_at.unwrap_or_default(),
+        finished_at: Utc::now().to_string(),
+        status: execution_result.status.clone(),
+        result: GetStatusResult {
+            stdout: execution_result.stdout.clone(),
+            stderr: execution_result.stderr.clone(),
+            artifacts: execution_result.artifacts.clone(),
+            stdout_ref: None,
+            stderr_ref: None,
+        },
+        failure: execution_result.failure,
+    };
+
+    let should_retry = execution_result
+        .failure
+        .is_some_and(|failure| retry.should_retry(attempt.attempt, failure));
+    if should_retry && !shutting_down.load(Ordering::SeqCst) {
+        let retry_at = Utc::now()
+            + chrono::Duration::from_std(retry.backoff(attempt.attempt))
+                .unwrap_or(chrono::Duration::MAX);
+        task_status.retry_task(&id, attempt, retry_at);
+        schedule_task_at(queues, scheduled_tasks, task_info, retry_at);
+        return;
+    }
+
+    task_status.record_attempt(&id, attempt);
+    task_status.finish_running_task(
+        &id,
+        execution_result.stdout,
+        execution_result.stderr,
+        execution_result.status,
+        execution_result.artifacts,
+        execution_result.failure,
+    );
+}
+
+/// Creates tokio thread that will execute python scripts and binary files.
+/// Worker takes task only when there are enough free slots for it and frees
+/// them after task is finished. Worker stops when task queue is closed and empty.
+#[allow(clippy::too_many_arguments)]
+fn create_worker(
+    worker: usize,
+    task_queue: Arc<FairQueue>,
+    config: QueueConfig,
+    busy_workers: Arc<AtomicUsize>,
+    shutting_down: Arc<AtomicBool>,
+    mut kill_receiver: watch::Receiver<bool>,
+    running_tasks: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+    queues: Arc<Mutex<Queues>>,
+    scheduled_tasks: Arc<Mutex<HashMap<String, ScheduledTask>>>,
+) -> JoinHandle<()> {
+    task::spawn(async move {
+        while let Some((task_info, slots)) = task_queue.pop().await {
+            if shutting_down.load(Ordering::SeqCst) {
+                drop_unstarted_task(task_info, &queues).await;
+                continue;
+            }
+
+            let task_status = task_info.task_status.clone();
+            let default_max_wait = queues.lock().unwrap().default_max_wait;
+            let queue = config.name.clone();
+            let cpus = slots.cpus().to_vec();
+            let (task_info, started) = task_status
+                .blocking(move |_| {
+                    let started = start_task(&task_info, default_max_wait, &queue, worker, cpus);
+                    (task_info, started)
+                })
+                .await;
+            let started = match started {
+                Ok(started) => started,
+                Err(err) => {
+                    eprintln!("failed to start task {}: {}", task_info.id, err);
+                    drop_unstarted_task(task_info, &queues).await;
+                    continue;
+                }
+            };
+            complete_shared_task(&task_info.id, &queues).await;
+            if !started {
+                continue;
+            }
+            busy_workers.fetch_add(1, Ordering::SeqCst);
+
+            let mut task_request = task_info.task_request.clone();
+            task_request.timeout_ms = task_request.timeout_ms.or(config.timeout_ms);
+            task_request.memory_limit_mb = task_request.memory_limit_mb.or(config.memory_limit_mb);
+
+            let (kill_task_sender, kill_task_receiver) = oneshot::channel();
+            running_tasks
+                .lock()
+                .unwrap()
+                .insert(task_info.id.clone(), kill_task_sender);
+
+            let id = task_info.id.clone();
+            // Event is recorded off async thread, task is finished only after
+            // recording is done, so that events stay in order
+            let started_events = Arc::new(Mutex::new(Vec::new()));
+            let on_spawn = {
+                let (task_status, id, started_events) =
+                    (task_status.clone(), id.clone(), started_events.clone());
+                move |pid| {
+                    let (task_status, id) = (task_status.clone(), id.clone());
+                    let started_event = task::spawn(async move {
+                        task_status
+                            .blocking(move |task_status| {
+                                task_status.record_event(&id, TaskEventKind::Started { pid })
+                            })
+                            .await
+                    });
+                    started_events.lock().unwrap().push(started_event);
+                }
+            };
+            let execution_result = tokio::select! {
+                execution_result = execute_task_on_cpus(task_request, id.clone(), slots.cpus(), on_spawn) => {
+                    Some(execution_result)
+                }
+                _ = kill_receiver.wait_for(|&kill| kill) => None,
+                _ = kill_task_receiver => None,
+            };
+
+            running_tasks.lock().unwrap().remove(&id);
+            let started_events: Vec<JoinHandle<()>> =
+                started_events.lock().unwrap().drain(..).collect();
+            for started_event in started_events {
+                let _ = started_event.await;
+            }
+            match execution_result {
+                Some(execution_result) => {
+                    let queues = queues.clone();
+                    let scheduled_tasks = scheduled_tasks.clone();
+                    let shutting_down = shutting_down.clone();
+                    task_status
+                        .blocking(move |_| {
+                            finish_execution(
+                                task_info,
+                                execution_result,
+                                &queues,
+                                &scheduled_tasks,
+                                &shutting_down,
+                            )
+                        })
+                        .await;
+                }
+                None => {
+                    let cancelled_id = id.clone();
+                    task_status
+                        .blocking(move |task_status| task_status.clone().cancel_task(&cancelled_id))
+                        .await;
+                }
+            }
+
+            busy_workers.fetch_sub(1, Ordering::SeqCst);
+        }
+    })
+}
+
+#[cfg(test)]
+mod test_queue_config {
+    use crate::worker_pool::worker_pool::QueueConfig;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_queue_config() {
+        let queue_config = QueueConfig::from_str("heavy=2,timeout_ms=60000").unwrap();
+        assert_eq!(queue_config.name, "heavy");
+        assert_eq!(queue_config.workers_count, 2);
+        assert_eq!(queue_config.timeout_ms, Some(60000));
+        assert_eq!(queue_config.memory_limit_mb, None);
+
+        assert!(QueueConfig::from_str("heavy").is_err());
+        assert!(QueueConfig::from_str("heavy=two").is_err());
+        assert!(QueueConfig::from_str("heavy=2,cpu=3").is_err());
+    }
 }
@@ -0,0 +1,1015 @@
+use crate::server::models::requests::{
+    validate_workspace_path, CreateTaskRequest, CreateWorkflowRequest, InputTarget, OutputSource,
+    TaskInput,
+};
+use crate::server::models::responses::{
+    CreateWorkflowResponse, GetStatusResponse, GetWorkflowResponse, OutputRef, TaskStatusEnum,
+    WorkflowStatusEnum, WorkflowTaskStatus,
+};
+use crate::server::server::TaskStatus;
+use crate::worker_pool::worker_pool::{TaskInfo, WorkerPool};
+
+use base64::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tokio::task;
+use uuid::Uuid;
+
+/// Error for workflow funcs
+#[derive(Debug, Clone)]
+pub enum WorkflowError {
+    /// Workflow with given id doesn't exist
+    NotExist,
+    /// Workflow has invalid tasks or dependencies
+    InvalidWorkflow(String),
+    /// Server is shutting down and doesn't accept new tasks
+    ShuttingDown,
+    /// Tasks of workflow can't be stored or read
+    Storage(String),
+}
+
+impl std::fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WorkflowError::NotExist => write!(f, "workflow with that id doesn't exist!"),
+            WorkflowError::InvalidWorkflow(message) => write!(f, "invalid workflow: {}", message),
+            WorkflowError::ShuttingDown => {
+                write!(f, "server is shutting down and doesn't accept new tasks!")
+            }
+            WorkflowError::Storage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Maximum amount of tasks in one workflow
+pub const MAX_WORKFLOW_TASKS: usize = 1000;
+
+/// Amount of last finished workflows whose status can be requested
+pub const FINISHED_WORKFLOWS_KEPT: usize = 10000;
+
+/// Task of workflow with links to its parents and children
+#[derive(Clone)]
+struct WorkflowTask {
+    workflow_id: String,
+    name: String,
+    request: CreateTaskRequest,
+    /// Ids of tasks that must succeed before this task is put into queue
+    parents: Vec<String>,
+    /// Ids of tasks that depend on this task
+    children: Vec<String>,
+    /// Outputs of parents passed to task, `from` is id of parent
+    inputs: Vec<TaskInput>,
+}
+
+/// Task of workflow as it is reported in status of workflow
+struct WorkflowTaskSummary {
+    name: String,
+    id: String,
+    depends_on: Vec<String>,
+}
+
+/// Graph of all workflows on server. Finished workflows are removed from graph,
+/// only their summaries are kept.
+#[derive(Default)]
+struct WorkflowGraph {
+    /// Ids of tasks of every workflow in order of submission
+    workflows: HashMap<String, Vec<String>>,
+    /// Tasks of all workflows by task id
+    tasks: HashMap<String, WorkflowTask>,
+    /// Ids of tasks of every workflow that are not finished yet
+    unfinished: HashMap<String, HashSet<String>>,
+    /// Tasks of finished workflows
+    finished: HashMap<String, Vec<WorkflowTaskSummary>>,
+    /// Ids of finished workflows in order of finishing
+    finished_order: VecDeque<String>,
+}
+
+impl WorkflowGraph {
+    /// Returns tasks of workflow in order of submission
+    fn summary(&self, id: &str) -> Option<Vec<WorkflowTaskSummary>> {
+        if let Some(finished) = self.finished.get(id) {
+            return Some(
+                finished
+                    .iter()
+                    .map(|task| WorkflowTaskSummary {
+                        name: task.name.clone(),
+                        id: task.id.clone(),
+                        depends_on: task.depends_on.clone(),
+                    })
+                    .collect(),
+            );
+        }
+
+        let workflow = self.workflows.get(id)?;
+        Some(
+            workflow
+                .iter()
+                .map(|task_id| {
+                    let workflow_task = &self.tasks[task_id];
+                    WorkflowTaskSummary {
+                        name: workflow_task.name.clone(),
+                        id: task_id.clone(),
+                        depends_on: workflow_task
+                            .parents
+                            .iter()
+                            .map(|parent| self.tasks[parent].name.clone())
+                            .collect(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Marks task of workflow as finished. Workflow whose tasks are all finished
+    /// is removed from graph and kept as summary.
+    fn finish_task(&mut self, workflow_id: &str, id: &str) {
+        let Some(unfinished) = self.unfinished.get_mut(workflow_id) else {
+            return;
+        };
+        unfinished.remove(id);
+        if !unfinished.is_empty() {
+            return;
+        }
+
+        let summary = self.summary(workflow_id).unwrap_or_default();
+        self.unfinished.remove(workflow_id);
+        for task_id in self.workflows.remove(workflow_id).unwrap_or_default() {
+            self.tasks.remove(&task_id);
+        }
+
+        self.finished.insert(workflow_id.to_string(), summary);
+        self.finished_order.push_back(workflow_id.to_string());
+        if self.finished_order.len() > FINISHED_WORKFLOWS_KEPT {
+            if let Some(oldest) = self.finished_order.pop_front() {
+                self.finished.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Struct that keeps workflows and puts their tasks into worker pool
+/// once all dependencies of task succeeded
+pub struct WorkflowScheduler {
+    graph: Arc<Mutex<WorkflowGraph>>,
+    worker_pool: Arc<WorkerPool>,
+    task_status: TaskStatus,
+}
+
+impl WorkflowScheduler {
+    /// Creates workflow scheduler and starts tokio thread that
+    /// handles finished tasks of workflows
+    pub fn new(worker_pool: Arc<WorkerPool>, task_status: TaskStatus) -> WorkflowScheduler {
+        let workflow_scheduler = WorkflowScheduler {
+            graph: Arc::new(Mutex::new(WorkflowGraph::default())),
+            worker_pool,
+            task_status,
+        };
+
+        let mut finished_tasks = workflow_scheduler.task_status.subscribe_finished_tasks();
+        let graph = workflow_scheduler.graph.clone();
+        let worker_pool = workflow_scheduler.worker_pool.clone();
+        let task_status = workflow_scheduler.task_status.clone();
+        task::spawn(async move {
+            while let Some(id) = finished_tasks.recv().await {
+                on_task_finished(&id, &graph, &worker_pool, &task_status).await;
+            }
+        });
+
+        workflow_scheduler
+    }
+
+    /// Validates workflow, creates all its tasks and puts tasks
+    /// without dependencies into queue. Other tasks are blocked until
+    /// their dependencies succeed.
+    pub async fn create_workflow(
+        &self,
+        request: CreateWorkflowRequest,
+    ) -> Result<CreateWorkflowResponse, WorkflowError> {
+        if self.worker_pool.is_shutting_down() {
+            return Err(WorkflowError::ShuttingDown);
+        }
+        validate_workflow(&request)?;
+        for workflow_task in &request.tasks {
+            self.worker_pool
+                .check_queue(&workflow_task.task)
+                .map_err(|message| {
+                    WorkflowError::InvalidWorkflow(format!(
+                        "task {}: {}",
+                        workflow_task.name, message
+                    ))
+                })?;
+        }
+
+        let workflow_id = Uuid::new_v4().to_string();
+        let mut task_ids = BTreeMap::new();
+        let mut ready_tasks = Vec::new();
+
+        {
+            let mut graph = self.graph.lock().unwrap();
+            let mut workflow: Vec<String> = Vec::new();
+
+            for workflow_task in &request.tasks {
+                let mut status = GetStatusResponse::new_utc_status();
+                status.meta.workflow_id = Some(workflow_id.clone());
+                if !workflow_task.depends_on.is_empty() {
+                    status.status = TaskStatusEnum::BLOCKED;
+                }
+
+                let task_id = match self.task_status.add_new_task(&workflow_task.task, status) {
+                    Ok(task_id) => task_id,
+                    Err(err) => {
+                        // Tasks that were already added would never be unblocked
+                        let mut task_status = self.task_status.clone();
+                        for task_id in &workflow {
+                            task_status.cancel_waiting_task(task_id);
+                        }
+                        return Err(WorkflowError::Storage(err.to_string()));
+                    }
+                };
+                task_ids.insert(workflow_task.name.clone(), task_id.clone());
+                workflow.push(task_id);
+            }
+
+            for (workflow_task, task_id) in request.tasks.into_iter().zip(&workflow) {
+                let parents: Vec<String> = workflow_task
+                    .depends_on
+                    .iter()
+                    .map(|name| task_ids[name].clone())
+                    .collect();
+
+                if parents.is_empty() {
+                    ready_tasks.push(TaskInfo::new(
+                        task_id.clone(),
+                        workflow_task.task.clone(),
+                        self.task_status.clone(),
+                    ));
+                }
+
+                let inputs = workflow_task
+                    .inputs
+                    .into_iter()
+                    .map(|input| TaskInput {
+                        from: task_ids[&input.from].clone(),
+                        ..input
+                    })
+                    .collect();
+
+                graph.tasks.insert(
+                    task_id.clone(),
+                    WorkflowTask {
+                        workflow_id: workflow_id.clone(),
+                        name: workflow_task.name,
+                        request: workflow_task.task,
+                        parents,
+                        children: Vec::new(),
+                        inputs,
+                    },
+                );
+            }
+
+            // Children are linked when all tasks are in graph,
+            // since task can be listed before tasks it depends on
+            for task_id in &workflow {
+                for parent in graph.tasks[task_id].parents.clone() {
+                    if let Some(parent) = graph.tasks.get_mut(&parent) {
+                        parent.children.push(task_id.clone());
+                    }
+                }
+            }
+
+            graph
+                .unfinished
+                .insert(workflow_id.clone(), workflow.iter().cloned().collect());
+            graph.workflows.insert(workflow_id.clone(), workflow);
+        }
+
+        for task_info in ready_tasks {
+            self.worker_pool.do_task(task_info).await;
+        }
+
+        Ok(CreateWorkflowResponse {
+            id: workflow_id,
+            tasks: task_ids,
+        })
+    }
+
+    /// Returns statuses of all tasks of workflow and aggregate status
+    pub fn get_workflow(&self, id: &str) -> Result<GetWorkflowResponse, WorkflowError> {
+        // Statuses are read after graph is unlocked, so slow stores don't block workflows
+        let summary = self
+            .graph
+            .lock()
+            .unwrap()
+            .summary(id)
+            .ok_or(WorkflowError::NotExist)?;
+
+        let tasks: Vec<WorkflowTaskStatus> = summary
+            .into_iter()
+            .map(|task| {
+                let status = self
+                    .task_status
+                    .get_status_by_id(&task.id)
+                    .map_err(|err| WorkflowError::Storage(err.to_string()))?;
+                Ok(WorkflowTaskStatus {
+                    name: task.name,
+                    id: task.id,
+                    status: status.status,
+                    depends_on: task.depends_on,
+                })
+            })
+            .collect::<Result<_, WorkflowError>>()?;
+
+        let status = if !tasks.iter().all(|task| task.status.is_finished()) {
+            WorkflowStatusEnum::RUNNING
+        } else if tasks
+            .iter()
+            .all(|task| task.status == TaskStatusEnum::SUCCESS)
+        {
+            WorkflowStatusEnum::SUCCESS
+        } else {
+            WorkflowStatusEnum::ERROR
+        };
+
+        Ok(GetWorkflowResponse {
+            id: id.to_string(),
+            status,
+            tasks,
+        })
+    }
+}
+
+/// Checks that workflow is not empty, names of tasks are unique,
+/// all dependencies exist and there are no cycles
+fn validate_workflow(request: &CreateWorkflowRequest) -> Result<(), WorkflowError> {
+    if request.tasks.is_empty() {
+        return Err(WorkflowError::InvalidWorkflow(
+            "workflow has no tasks".to_string(),
+        ));
+    }
+    if request.tasks.len() > MAX_WORKFLOW_TASKS {
+        return Err(WorkflowError::InvalidWorkflow(format!(
+            "workflow has more than {} tasks",
+            MAX_WORKFLOW_TASKS
+        )));
+    }
+
+    let mut names = HashSet::new();
+    for workflow_task in &request.tasks {
+        if !names.insert(workflow_task.name.as_str()) {
+            return Err(WorkflowError::InvalidWorkflow(format!(
+                "task name {} is not unique",
+                workflow_task.name
+            )));
+        }
+        if workflow_task.task.run_at.is_some() || workflow_task.task.delay_ms.is_some() {
+            return Err(WorkflowError::InvalidWorkflow(format!(
+                "task {} can't have run_at or delay_ms",
+                workflow_task.name
+            )));
+        }
+        workflow_task.task.validate().map_err(|message| {
+            WorkflowError::InvalidWorkflow(format!("task {}: {}", workflow_task.name, message))
+        })?;
+        for input in &workflow_task.inputs {
+            validate_input(input).map_err(|message| {
+                WorkflowError::InvalidWorkflow(format!("task {}: {}", workflow_task.name, message))
+            })?;
+            if !workflow_task.depends_on.contains(&input.from) {
+                return Err(WorkflowError::InvalidWorkflow(format!(
+                    "task {} takes input from task {} that is not in its depends_on",
+                    workflow_task.name, input.from
+                )));
+            }
+        }
+    }
+
+    for workflow_task in &request.tasks {
+        if let Some(name) = workflow_task
+            .depends_on
+            .iter()
+            .find(|name| !names.contains(name.as_str()))
+        {
+            return Err(WorkflowError::InvalidWorkflow(format!(
+                "task {} depends on unknown task {}",
+                workflow_task.name, name
+            )));
+        }
+    }
+
+    // Kahn's algorithm: if some tasks are never freed of dependencies, they form a cycle
+    let mut dependencies_left: HashMap<&str, usize> = request
+        .tasks
+        .iter()
+        .map(|workflow_task| {
+            let dependencies: HashSet<&String> = workflow_task.depends_on.iter().collect();
+            (workflow_task.name.as_str(), dependencies.len())
+        })
+        .collect();
+    let mut ready: Vec<&str> = dependencies_left
+        .iter()
+        .filter(|(_, &count)| count == 0)
+        .map(|(&name, _)| name)
+        .collect();
+    let mut visited = 0;
+
+    while let Some(name) = ready.pop() {
+        visited += 1;
+        for workflow_task in &request.tasks {
+            let dependencies: HashSet<&String> = workflow_task.depends_on.iter().collect();
+            if dependencies
+                .iter()
+                .any(|dependency| dependency.as_str() == name)
+            {
+                let count = dependencies_left
+                    .get_mut(workflow_task.name.as_str())
+                    .unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(workflow_task.name.as_str());
+                }
+            }
+        }
+    }
+
+    if visited != request.tasks.len() {
+        return Err(WorkflowError::InvalidWorkflow(
+            "dependencies of tasks form a cycle".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that input has artifact path if it takes artifact
+/// and file path if it is written to file
+fn validate_input(input: &TaskInput) -> Result<(), String> {
+    if input.source == OutputSource::Artifact {
+        let artifact = input
+            .artifact
+            .as_ref()
+            .ok_or("input from artifact must have artifact path")?;
+        validate_workspace_path(artifact)?;
+    }
+    if input.target == InputTarget::File {
+        let path = input
+            .path
+            .as_ref()
+            .ok_or("input to file must have file path")?;
+        validate_workspace_path(path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns request of task with outputs of its parents passed to it
+fn resolve_inputs(
+    request: &CreateTaskRequest,
+    inputs: &[TaskInput],
+    task_status: &TaskStatus,
+) -> Result<CreateTaskRequest, String> {
+    let mut request = request.clone();
+
+    for input in inputs {
+        let result = task_status
+            .get_status_by_id(&input.from)
+            .map_err(|err| err.to_string())?
+            .result;
+        let output = match input.source {
+            OutputSource::Stdout => full_output(
+                task_status,
+                &input.from,
+                result.stdout_ref.as_ref(),
+                result.stdout,
+            )?,
+            OutputSource::Stderr => full_output(
+                task_status,
+                &input.from,
+                result.stderr_ref.as_ref(),
+                result.stderr.unwrap_or_default(),
+            )?,
+            OutputSource::Artifact => {
+                let artifact = input.artifact.clone().unwrap_or_default();
+                let content = result.artifacts.get(&artifact).ok_or(format!(
+                    "artifact {} was not produced by task {}",
+                    artifact, input.from
+                ))?;
+                BASE64_STANDARD.decode(content).unwrap_or_default()
+            }
+        };
+
+        match input.target {
+            InputTarget::Stdin => request
+                .stdin
+                .get_or_insert_with(String::new)
+                .push_str(&String::from_utf8_lossy(&output)),
+            InputTarget::Args => {
+                request.args = String::from_utf8_lossy(&output)
+                    .trim_end_matches('\n')
+                    .to_string()
+            }
+            InputTarget::File => {
+                let path = input.path.clone().unwrap_or_default();
+                request.files.insert(path, BASE64_STANDARD.encode(output));
+            }
+        }
+    }
+
+    Ok(request)
+}
+
+/// Returns whole output of task, task keeps only preview of output that was moved to output store
+fn full_output(
+    task_status: &TaskStatus,
+    id: &str,
+    output_ref: Option<&OutputRef>,
+    output: String,
+) -> Result<Vec<u8>, String> {
+    match output_ref.and_then(|output_ref| task_status.output_path(output_ref)) {
+        Some(path) => {
+            fs::read(path).map_err(|err| format!("failed to read output of task {}: {}", id, err))
+        }
+        None => Ok(output.into_bytes()),
+    }
+}
+
+fn is_succeeded(task_status: &TaskStatus, id: &str) -> bool {
+    task_status
+        .get_status_by_id(id)
+        .is_ok_and(|status| status.status == TaskStatusEnum::SUCCESS)
+}
+
+/// Puts children of succeeded task into queue if all their parents succeeded,
+/// skips children of task that didn't succeed
+async fn on_task_finished(
+    id: &str,
+    graph: &Mutex<WorkflowGraph>,
+    worker_pool: &WorkerPool,
+    task_status: &TaskStatus,
+) {
+    // Children are copied under lock, task store and output files are read after it is released
+    let (workflow_id, children) = {
+        let graph = graph.lock().unwrap();
+        let Some(workflow_task) = graph.tasks.get(id) else {
+            return;
+        };
+        let children: Vec<(String, WorkflowTask)> = workflow_task
+            .children
+            .iter()
+            .map(|child_id| (child_id.clone(), graph.tasks[child_id].clone()))
+            .collect();
+
+        (workflow_task.workflow_id.clone(), children)
+    };
+
+    let finished_id = id.to_string();
+    let releasing_task_status = task_status.clone();
+    let ready_tasks = task::spawn_blocking(move || {
+        release_children(&finished_id, children, releasing_task_status)
+    })
+    .await
+    .expect("releasing children of workflow task panicked");
+
+    for task_info in ready_tasks {
+        worker_pool.do_task(task_info).await;
+    }
+
+    graph.lock().unwrap().finish_task(&workflow_id, id);
+}
+
+/// Unblocks children of finished task whose parents all succeeded and returns them
+/// with outputs of parents passed to them. Children of task that didn't succeed are skipped.
+fn release_children(
+    id: &str,
+    children: Vec<(String, WorkflowTask)>,
+    task_status: TaskStatus,
+) -> Vec<TaskInfo> {
+    let mut ready_tasks = Vec::new();
+    // Task whose status can't be read is treated as failed
+    let succeeded = is_succeeded(&task_status, id);
+
+    for (child_id, child) in children {
+        let mut task_status = task_status.clone();
+
+        if !succeeded {
+            // Skipped child notifies about itself, so failure is propagated further
+            task_status.skip_task(&child_id);
+            continue;
+        }
+
+        let parents_succeeded = child
+            .parents
+            .iter()
+            .all(|parent| is_succeeded(&task_status, parent));
+        if !parents_succeeded || !task_status.unblock_task(&child_id) {
+            continue;
+        }
+
+        match resolve_inputs(&child.request, &child.inputs, &task_status) {
+            Ok(request) => {
+                task_status.replace_task_request(&child_id, &request);
+                ready_tasks.push(TaskInfo::new(child_id, request, task_status));
+            }
+            // Failed child notifies about itself, so its children are skipped
+            Err(message) => task_status.finish_running_task(
+                &child_id,
+                "".to_string(),
+                Some(message),
+                TaskStatusEnum::ERROR,
+                BTreeMap::new(),
+                None,
+            ),
+        }
+    }
+
+    ready_tasks
+}
+
+#[cfg(test)]
+mod test_workflows {
+    use crate::outputs::outputs::OutputStore;
+    use crate::server::models::requests::{
+        CreateTaskRequest, CreateWorkflowRequest, InputTarget, OutputSource, TaskInput, TaskType,
+        WorkflowTaskRequest,
+    };
+    use crate::server::models::responses::{TaskStatusEnum, WorkflowStatusEnum};
+    use crate::server::server::TaskStatus;
+    use crate::worker_pool::worker_pool::WorkerPool;
+    use crate::workflows::workflows::{WorkflowScheduler, MAX_WORKFLOW_TASKS};
+    use chrono::prelude::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn create_workflow_scheduler(workers_count: usize) -> (WorkflowScheduler, TaskStatus) {
+        let worker_pool = Arc::new(WorkerPool::new(workers_count));
+        let task_status = TaskStatus::new();
+
+        (
+            WorkflowScheduler::new(worker_pool, task_status.clone()),
+            task_status,
+        )
+    }
+
+    fn workflow_task(name: &str, python_code: &str, depends_on: &[&str]) -> WorkflowTaskRequest {
+        WorkflowTaskRequest {
+            name: name.to_string(),
+            task: CreateTaskRequest::new(TaskType::Python, python_code.to_string(), "".to_string()),
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+            inputs: Vec::new(),
+        }
+    }
+
+    async fn wait_for_workflow(workflow_scheduler: &WorkflowScheduler, id: &str) {
+        while workflow_scheduler.get_workflow(id).unwrap().status == WorkflowStatusEnum::RUNNING {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_workflow_runs_in_order() {
+        let (workflow_scheduler, task_status) = create_workflow_scheduler(4);
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("build", "import time; time.sleep(0.2)", &[]),
+                workflow_task("test1", "print(1)", &["build"]),
+                workflow_task("test2", "print(2)", &["build"]),
+                workflow_task("aggregate", "print('done')", &["test1", "test2"]),
+            ],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        let aggregate_status = task_status
+            .get_status_by_id(&response.tasks["aggregate"])
+            .unwrap();
+        assert_eq!(aggregate_status.status, TaskStatusEnum::BLOCKED);
+        assert_eq!(aggregate_status.meta.workflow_id, Some(response.id.clone()));
+
+        wait_for_workflow(&workflow_scheduler, &response.id).await;
+        let workflow = workflow_scheduler.get_workflow(&response.id).unwrap();
+        assert_eq!(workflow.status, WorkflowStatusEnum::SUCCESS);
+        assert_eq!(workflow.tasks[3].depends_on, vec!["test1", "test2"]);
+
+        // Finished workflow is removed from graph, its summary is still reported
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !workflow_scheduler.graph.lock().unwrap().tasks.is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let workflow = workflow_scheduler.get_workflow(&response.id).unwrap();
+        assert_eq!(workflow.status, WorkflowStatusEnum::SUCCESS);
+        assert_eq!(workflow.tasks[3].depends_on, vec!["test1", "test2"]);
+
+        let build_status = task_status
+            .get_status_by_id(&response.tasks["build"])
+            .unwrap();
+        let aggregate_status = task_status
+            .get_status_by_id(&response.tasks["aggregate"])
+            .unwrap();
+        let build_finished_at: DateTime<Utc> =
+            build_status.meta.finished_at.unwrap().parse().unwrap();
+        let aggregate_started_at: DateTime<Utc> =
+            aggregate_status.meta.started_at.unwrap().parse().unwrap();
+        assert!(build_finished_at <= aggregate_started_at);
+    }
+
+    #[tokio::test]
+    async fn test_task_listed_before_its_dependency() {
+        let (workflow_scheduler, task_status) = create_workflow_scheduler(2);
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("aggregate", "print('done')", &["test"]),
+                workflow_task("test", "print(1)", &["build"]),
+                workflow_task("build", "print(0)", &[]),
+            ],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            wait_for_workflow(&workflow_scheduler, &response.id),
+        )
+        .await
+        .unwrap();
+        let workflow = workflow_scheduler.get_workflow(&response.id).unwrap();
+        assert_eq!(workflow.status, WorkflowStatusEnum::SUCCESS);
+
+        let aggregate_status = task_status
+            .get_status_by_id(&response.tasks["aggregate"])
+            .unwrap();
+        assert_eq!(aggregate_status.result.stdout, "done\n");
+    }
+
+    #[tokio::test]
+    async fn test_failure_skips_downstream_tasks() {
+        let (workflow_scheduler, task_status) = create_workflow_scheduler(2);
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("build", "print(1 / 0)", &[]),
+                workflow_task("lint", "print('ok')", &[]),
+                workflow_task("test", "print(1)", &["build"]),
+                workflow_task("aggregate", "print('done')", &["test", "lint"]),
+            ],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        wait_for_workflow(&workflow_scheduler, &response.id).await;
+        let workflow = workflow_scheduler.get_workflow(&response.id).unwrap();
+        assert_eq!(workflow.status, WorkflowStatusEnum::ERROR);
+
+        let status_of = |name: &str| {
+            task_status
+                .get_status_by_id(&response.tasks[name])
+                .unwrap()
+                .status
+        };
+        assert_eq!(status_of("build"), TaskStatusEnum::ERROR);
+        assert_eq!(status_of("lint"), TaskStatusEnum::SUCCESS);
+        assert_eq!(status_of("test"), TaskStatusEnum::SKIPPED);
+        assert_eq!(status_of("aggregate"), TaskStatusEnum::SKIPPED);
+    }
+
+    fn task_input(
+        from: &str,
+        source: OutputSource,
+        artifact: Option<&str>,
+        target: InputTarget,
+        path: Option<&str>,
+    ) -> TaskInput {
+        TaskInput {
+            from: from.to_string(),
+            source,
+            artifact: artifact.map(|artifact| artifact.to_string()),
+            target,
+            path: path.map(|path| path.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outputs_are_passed_to_dependent_tasks() {
+        let (workflow_scheduler, task_status) = create_workflow_scheduler(2);
+
+        let mut generate = workflow_task(
+            "generate",
+            "print(3); open('numbers.txt', 'w').write('1 2 3')",
+            &[],
+        );
+        generate.task.artifacts = vec!["numbers.txt".to_string()];
+
+        let mut solve = workflow_task(
+            "solve",
+            "import sys; print(int(sys.argv[1]) * sum(map(int, input().split())))",
+            &["generate"],
+        );
+        solve.inputs = vec![
+            task_input(
+                "generate",
+                OutputSource::Stdout,
+                None,
+                InputTarget::Args,
+                None,
+            ),
+            task_input(
+                "generate",
+                OutputSource::Artifact,
+                Some("numbers.txt"),
+                InputTarget::Stdin,
+                None,
+            ),
+        ];
+
+        let mut check = workflow_task(
+            "check",
+            "assert open('answer.txt').read() == '18\\n'; print('OK')",
+            &["solve"],
+        );
+        check.inputs = vec![task_input(
+            "solve",
+            OutputSource::Stdout,
+            None,
+            InputTarget::File,
+            Some("answer.txt"),
+        )];
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![generate, solve, check],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        wait_for_workflow(&workflow_scheduler, &response.id).await;
+        let workflow = workflow_scheduler.get_workflow(&response.id).unwrap();
+        assert_eq!(workflow.status, WorkflowStatusEnum::SUCCESS);
+
+        let check_status = task_status
+            .get_status_by_id(&response.tasks["check"])
+            .unwrap();
+        assert_eq!(check_status.result.stdout, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_spilled_output_is_passed_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut task_status = TaskStatus::new();
+        task_status.output_store = Some(Arc::new(OutputStore::open(dir.path(), 10, 4).unwrap()));
+        let workflow_scheduler =
+            WorkflowScheduler::new(Arc::new(WorkerPool::new(2)), task_status.clone());
+
+        let generate = workflow_task("generate", "print('x' * 100)", &[]);
+        let mut check = workflow_task("check", "print(len(input()))", &["generate"]);
+        check.inputs = vec![task_input(
+            "generate",
+            OutputSource::Stdout,
+            None,
+            InputTarget::Stdin,
+            None,
+        )];
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![generate, check],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        wait_for_workflow(&workflow_scheduler, &response.id).await;
+        let generate_status = task_status
+            .get_status_by_id(&response.tasks["generate"])
+            .unwrap();
+        assert!(generate_status.result.stdout_ref.is_some());
+        assert_eq!(generate_status.result.stdout.len(), 4);
+
+        let check_status = task_status
+            .get_status_by_id(&response.tasks["check"])
+            .unwrap();
+        assert_eq!(check_status.status, TaskStatusEnum::SUCCESS);
+        assert_eq!(check_status.result.stdout, "100\n");
+    }
+
+    #[tokio::test]
+    async fn test_missing_artifact_fails_task() {
+        let (workflow_scheduler, task_status) = create_workflow_scheduler(1);
+
+        let mut solve = workflow_task("solve", "print(1)", &["generate"]);
+        solve.inputs = vec![task_input(
+            "generate",
+            OutputSource::Artifact,
+            Some("numbers.txt"),
+            InputTarget::Stdin,
+            None,
+        )];
+
+        let request = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("generate", "print(1)", &[]),
+                solve,
+                workflow_task("check", "print(1)", &["solve"]),
+            ],
+        };
+        let response = workflow_scheduler.create_workflow(request).await.unwrap();
+
+        wait_for_workflow(&workflow_scheduler, &response.id).await;
+        let solve_status = task_status
+            .get_status_by_id(&response.tasks["solve"])
+            .unwrap();
+        assert_eq!(solve_status.status, TaskStatusEnum::ERROR);
+        assert_eq!(
+            solve_status.result.stderr,
+            Some(format!(
+                "artifact numbers.txt was not produced by task {}",
+                response.tasks["generate"]
+            ))
+        );
+
+        let check_status = task_status
+            .get_status_by_id(&response.tasks["check"])
+            .unwrap();
+        assert_eq!(check_status.status, TaskStatusEnum::SKIPPED);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_workflows() {
+        let (workflow_scheduler, _) = create_workflow_scheduler(1);
+
+        let too_large = CreateWorkflowRequest {
+            tasks: (0..=MAX_WORKFLOW_TASKS)
+                .map(|index| workflow_task(&index.to_string(), "print(1)", &[]))
+                .collect(),
+        };
+        assert!(workflow_scheduler.create_workflow(too_large).await.is_err());
+
+        let cycle = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("first", "print(1)", &["third"]),
+                workflow_task("second", "print(2)", &["first"]),
+                workflow_task("third", "print(3)", &["second"]),
+            ],
+        };
+        assert!(workflow_scheduler.create_workflow(cycle).await.is_err());
+
+        let self_dependency = CreateWorkflowRequest {
+            tasks: vec![workflow_task("first", "print(1)", &["first"])],
+        };
+        assert!(workflow_scheduler
+            .create_workflow(self_dependency)
+            .await
+            .is_err());
+
+        let unknown_dependency = CreateWorkflowRequest {
+            tasks: vec![workflow_task("first", "print(1)", &["zeroth"])],
+        };
+        assert!(workflow_scheduler
+            .create_workflow(unknown_dependency)
+            .await
+            .is_err());
+
+        let duplicate_names = CreateWorkflowRequest {
+            tasks: vec![
+                workflow_task("first", "print(1)", &[]),
+                workflow_task("first", "print(2)", &[]),
+            ],
+        };
+        assert!(workflow_scheduler
+            .create_workflow(duplicate_names)
+            .await
+            .is_err());
+
+        let mut second = workflow_task("second", "print(2)", &[]);
+        second.inputs = vec![task_input(
+            "first",
+            OutputSource::Stdout,
+            None,
+            InputTarget::Stdin,
+            None,
+        )];
+        let input_without_dependency = CreateWorkflowRequest {
+            tasks: vec![workflow_task("first", "print(1)", &[]), second],
+        };
+        assert!(workflow_scheduler
+            .create_workflow(input_without_dependency)
+            .await
+            .is_err());
+
+        let mut second = workflow_task("second", "print(2)", &["first"]);
+        second.inputs = vec![task_input(
+            "first",
+            OutputSource::Stdout,
+            None,
+            InputTarget::File,
+            Some("../answer.txt"),
+        )];
+        let input_outside_workspace = CreateWorkflowRequest {
+            tasks: vec![workflow_task("first", "print(1)", &[]), second],
+        };
+        assert!(workflow_scheduler
+            .create_workflow(input_outside_workspace)
+            .await
+            .is_err());
+
+        let empty = CreateWorkflowRequest { tasks: vec![] };
+        assert!(workflow_scheduler.create_workflow(empty).await.is_err());
+
+        assert!(workflow_scheduler.get_workflow("random-UUID").is_err());
+    }
+}
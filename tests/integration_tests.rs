@@ -1,44 +1,607 @@
 use base64::prelude::*;
-use reqwest::blocking::Client;
+use reqwest::Client;
+use std::sync::Arc;
+use std::time::Duration;
+use tasksolver::export::export::{import_tasks, store_tasks};
+use tasksolver::journal::journal::{InterruptedTaskPolicy, Journal};
+use tasksolver::outputs::outputs::OutputStore;
 use tasksolver::server::models::requests::*;
 use tasksolver::server::models::responses::*;
-use tasksolver::server::server::start_tasksolver_server;
+use tasksolver::server::server::TaskSolverServer;
+use tasksolver::task_store::postgres_task_store::PostgresTaskStore;
+use tasksolver::task_store::sqlite_task_store::SqliteTaskStore;
+use tasksolver::task_store::task_store::TaskStore;
 
 fn build_server_url(address: &str, port: u16, endpoint: &str) -> String {
     format!("http://{}:{}/{}", address, port, endpoint)
 }
 
-// #[tokio::test]
+/// Polls task status until task is finished
+async fn wait_for_finish(client: &Client, get_status_url: &str, id: &str) -> GetStatusResponse {
+    loop {
+        let get_status_request = GetStatusRequest {
+            id: id.to_string(),
+            include_events: false,
+        };
+        let response = client
+            .get(get_status_url)
+            .json(&get_status_request)
+            .send()
+            .await;
+        let response_data: GetStatusResponse = response.unwrap().json().await.unwrap();
+
+        if response_data.status != TaskStatusEnum::WAIT
+            && response_data.status != TaskStatusEnum::RUNNING
+        {
+            return response_data;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
 async fn it_works() {
     let address = "127.0.0.1";
-    let port = 8080;
-    start_tasksolver_server(4, address, port).await;
+    let port = 18080;
+    let tasksolver_server = TaskSolverServer::new(4, address.to_string(), port);
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
 
     let client = Client::new();
-    let create_task_url = build_server_url(address, port, "/create_task");
-    let get_status_url = build_server_url(address, port, "/get_status");
-    let get_task_count_url = build_server_url(address, port, "/get_task_count");
+    let create_task_url = build_server_url(address, port, "create_task");
+    let get_status_url = build_server_url(address, port, "get_status");
+    let get_task_count_url = build_server_url(address, port, "get_task_count");
 
-    let request = CreateTaskRequest {
-        task_type: TaskType::Bin,
-        file: BASE64_STANDARD.encode("echo Hello, world!").to_string(),
-        args: "".to_string(),
-    };
+    let request = CreateTaskRequest::new(
+        TaskType::Bin,
+        BASE64_STANDARD.encode("echo Hello, world!").to_string(),
+        "".to_string(),
+    );
 
-    let response = client.post(&create_task_url).json(&request).send();
-    assert_eq!(response.is_ok(), true);
+    let response = client.post(&create_task_url).json(&request).send().await;
+    assert!(response.is_ok());
 
-    let response_data: CreateTaskResponse = response.unwrap().json().unwrap();
+    let response_data: CreateTaskResponse = response.unwrap().json().await.unwrap();
     let id = response_data.id;
 
-    let get_status_request = GetStatusRequest { id };
-
-    let response = client.get(&get_status_url).json(&get_status_request).send();
-    assert_eq!(response.is_ok(), true);
-
-    let response_data: GetStatusResponse = response.unwrap().json().unwrap();
+    let response_data = wait_for_finish(&client, &get_status_url, &id).await;
     let status = response_data.status;
     let stdout = response_data.result.stdout;
     assert_eq!(status, TaskStatusEnum::SUCCESS);
-    assert_eq!(stdout, "Hello, world!".to_string());
+    assert_eq!(stdout, "Hello, world!\n".to_string());
+
+    let response = client.get(&get_task_count_url).send().await;
+    let response_data: GetTaskCountResponse = response.unwrap().json().await.unwrap();
+    assert_eq!(response_data.tasks, 0);
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() {
+    let address = "127.0.0.1";
+    let port = 18081;
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port)
+        .with_grace_period(Duration::from_millis(500));
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let client = Client::new();
+    let create_task_url = build_server_url(address, port, "create_task");
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "import time; time.sleep(30)".to_string(),
+        "".to_string(),
+    );
+    let response = client.post(&create_task_url).json(&request).send().await;
+    assert_eq!(response.unwrap().status(), reqwest::StatusCode::OK);
+
+    shutdown_handle.shutdown();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = client.post(&create_task_url).json(&request).send().await;
+    assert_eq!(
+        response.unwrap().status(),
+        reqwest::StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let result = tokio::time::timeout(Duration::from_secs(5), tasksolver_handle).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_journal_restores_tasks_after_restart() {
+    let directory = tempfile::tempdir().unwrap();
+    let journal_path = directory.path().join("journal.jsonl");
+    let address = "127.0.0.1";
+    let client = Client::new();
+
+    let port = 18082;
+    let tasksolver_server = TaskSolverServer::new(0, address.to_string(), port).with_journal(
+        Journal::open(&journal_path).unwrap(),
+        InterruptedTaskPolicy::Error,
+    );
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "print('restored')".to_string(),
+        "".to_string(),
+    );
+    let response = client
+        .post(build_server_url(address, port, "create_task"))
+        .json(&request)
+        .send()
+        .await;
+    let waiting_id = response
+        .unwrap()
+        .json::<CreateTaskResponse>()
+        .await
+        .unwrap()
+        .id;
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+
+    let mut interrupted_status = GetStatusResponse::new_utc_status();
+    interrupted_status.status = TaskStatusEnum::RUNNING;
+    Journal::open(&journal_path)
+        .unwrap()
+        .record_submission("interrupted", &request, &interrupted_status)
+        .unwrap();
+
+    let port = 18083;
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port).with_journal(
+        Journal::open(&journal_path).unwrap(),
+        InterruptedTaskPolicy::Error,
+    );
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+    let get_status_url = build_server_url(address, port, "get_status");
+
+    let response_data = wait_for_finish(&client, &get_status_url, &waiting_id).await;
+    assert_eq!(response_data.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(response_data.result.stdout, "restored\n");
+
+    let response_data = wait_for_finish(&client, &get_status_url, "interrupted").await;
+    assert_eq!(response_data.status, TaskStatusEnum::ERROR);
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+#[tokio::test]
+async fn test_start_fails_if_journal_is_unreadable() {
+    let directory = tempfile::tempdir().unwrap();
+    let journal_path = directory.path().join("journal.jsonl");
+    let journal = Journal::open(&journal_path).unwrap();
+
+    // Reading directory in place of journal file fails
+    std::fs::remove_file(&journal_path).unwrap();
+    std::fs::create_dir(&journal_path).unwrap();
+
+    let tasksolver_server = TaskSolverServer::new(1, "127.0.0.1".to_string(), 18092)
+        .with_journal(journal, InterruptedTaskPolicy::Error);
+    assert!(tasksolver_server.start_tasksolver_server().await.is_err());
+}
+
+#[tokio::test]
+async fn test_sqlite_store_restores_tasks_after_restart() {
+    let directory = tempfile::tempdir().unwrap();
+    let database_path = directory.path().join("tasks.sqlite");
+    let address = "127.0.0.1";
+    let client = Client::new();
+
+    let port = 18085;
+    let tasksolver_server = TaskSolverServer::new(0, address.to_string(), port).with_task_store(
+        Arc::new(SqliteTaskStore::open(&database_path).unwrap()),
+        InterruptedTaskPolicy::Error,
+    );
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "print('restored')".to_string(),
+        "".to_string(),
+    );
+    let response = client
+        .post(build_server_url(address, port, "create_task"))
+        .json(&request)
+        .send()
+        .await;
+    let waiting_id = response
+        .unwrap()
+        .json::<CreateTaskResponse>()
+        .await
+        .unwrap()
+        .id;
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+
+    let mut interrupted_status = GetStatusResponse::new_utc_status();
+    interrupted_status.status = TaskStatusEnum::RUNNING;
+    SqliteTaskStore::open(&database_path)
+        .unwrap()
+        .create("interrupted", &request, &interrupted_status)
+        .unwrap();
+
+    let port = 18086;
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port).with_task_store(
+        Arc::new(SqliteTaskStore::open(&database_path).unwrap()),
+        InterruptedTaskPolicy::Error,
+    );
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+    let get_status_url = build_server_url(address, port, "get_status");
+
+    let response_data = wait_for_finish(&client, &get_status_url, &waiting_id).await;
+    assert_eq!(response_data.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(response_data.result.stdout, "restored\n");
+
+    let response_data = wait_for_finish(&client, &get_status_url, "interrupted").await;
+    assert_eq!(response_data.status, TaskStatusEnum::ERROR);
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+/// Empty database on server from TASKSOLVER_TEST_POSTGRES_URL that is dropped
+/// with all its connections when test finishes
+struct TestDatabase {
+    server_url: String,
+    name: String,
+    url: String,
+}
+
+impl TestDatabase {
+    fn create() -> TestDatabase {
+        let server_url = std::env::var("TASKSOLVER_TEST_POSTGRES_URL")
+            .expect("TASKSOLVER_TEST_POSTGRES_URL must be set to run postgres tests");
+        let name = format!("tasksolver_{}", uuid::Uuid::new_v4().simple());
+        TestDatabase::execute(&server_url, format!("CREATE DATABASE {}", name));
+
+        let url = format!("{}/{}", server_url, name);
+        TestDatabase {
+            server_url,
+            name,
+            url,
+        }
+    }
+
+    /// Blocking client can't run inside tokio runtime, so it runs on its own thread
+    fn execute(server_url: &str, query: String) {
+        let server_url = server_url.to_string();
+        std::thread::spawn(move || {
+            let mut client =
+                postgres::Client::connect(&format!("{}/postgres", server_url), postgres::NoTls)
+                    .unwrap();
+            client.batch_execute(&query).unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        TestDatabase::execute(
+            &self.server_url,
+            format!("DROP DATABASE {} WITH (FORCE)", self.name),
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs PostgreSQL server from TASKSOLVER_TEST_POSTGRES_URL, e.g. postgres://postgres@localhost:5432"]
+async fn test_servers_share_postgres_store() {
+    let database = TestDatabase::create();
+    let database_url = database.url.clone();
+
+    let address = "127.0.0.1";
+    let client = Client::new();
+    let mut servers = Vec::new();
+    // First server has no workers, so its tasks are executed by second one
+    for (workers_count, port) in [(0, 18087), (1, 18088)] {
+        let task_store = Arc::new(PostgresTaskStore::connect(&database_url).unwrap());
+        let tasksolver_server = TaskSolverServer::new(workers_count, address.to_string(), port)
+            .with_task_store(task_store.clone(), InterruptedTaskPolicy::Error)
+            .with_shared_queue(task_store);
+        let shutdown_handle = tasksolver_server.shutdown_handle();
+        let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+        servers.push((shutdown_handle, tasksolver_handle));
+    }
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "print('shared')".to_string(),
+        "".to_string(),
+    );
+    let response = client
+        .post(build_server_url(address, 18087, "create_task"))
+        .json(&request)
+        .send()
+        .await;
+    let id = response
+        .unwrap()
+        .json::<CreateTaskResponse>()
+        .await
+        .unwrap()
+        .id;
+
+    let response_data = wait_for_finish(
+        &client,
+        &build_server_url(address, 18088, "get_status"),
+        &id,
+    )
+    .await;
+    assert_eq!(response_data.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(response_data.result.stdout, "shared\n");
+
+    let response_data = wait_for_finish(
+        &client,
+        &build_server_url(address, 18087, "get_status"),
+        &id,
+    )
+    .await;
+    assert_eq!(response_data.status, TaskStatusEnum::SUCCESS);
+
+    for (shutdown_handle, tasksolver_handle) in servers {
+        shutdown_handle.shutdown();
+        let _ = tasksolver_handle.await;
+    }
+}
+
+#[tokio::test]
+async fn test_idempotency_key() {
+    let address = "127.0.0.1";
+    let port = 18084;
+    let tasksolver_server = TaskSolverServer::new(0, address.to_string(), port);
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let client = Client::new();
+    let create_task_url = build_server_url(address, port, "create_task");
+
+    let request = CreateTaskRequest::new(TaskType::Python, "print(1)".to_string(), "".to_string());
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .post(&create_task_url)
+            .header("Idempotency-Key", "retried-request")
+            .json(&request)
+            .send()
+            .await;
+        let response_data: CreateTaskResponse = response.unwrap().json().await.unwrap();
+        ids.push(response_data.id);
+    }
+    assert_eq!(ids[0], ids[1]);
+
+    let other_request =
+        CreateTaskRequest::new(TaskType::Python, "print(2)".to_string(), "".to_string());
+    let response = client
+        .post(&create_task_url)
+        .header("Idempotency-Key", "retried-request")
+        .json(&other_request)
+        .send()
+        .await;
+    assert_eq!(response.unwrap().status(), reqwest::StatusCode::CONFLICT);
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+#[tokio::test]
+async fn test_wait_for_task() {
+    let address = "127.0.0.1";
+    let port = 18089;
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port)
+        .with_grace_period(Duration::from_millis(100));
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let client = Client::new();
+    let create_task_url = build_server_url(address, port, "create_task");
+    let wait_task_url = build_server_url(address, port, "wait_task");
+
+    let request = CreateTaskRequest::new(
+        TaskType::Bin,
+        BASE64_STANDARD.encode("echo Hello, world!").to_string(),
+        "".to_string(),
+    );
+    let response = client
+        .post(format!("{}?wait=true&timeout_ms=10000", create_task_url))
+        .json(&request)
+        .send()
+        .await;
+    let response_data: CreateTaskWaitResponse = response.unwrap().json().await.unwrap();
+    assert_eq!(response_data.status.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(response_data.status.result.stdout, "Hello, world!\n");
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "import time; time.sleep(30)".to_string(),
+        "".to_string(),
+    );
+    let response = client.post(&create_task_url).json(&request).send().await;
+    let id = response
+        .unwrap()
+        .json::<CreateTaskResponse>()
+        .await
+        .unwrap()
+        .id;
+
+    let wait_task_request = WaitTaskRequest {
+        id,
+        timeout_ms: Some(200),
+    };
+    let response = client
+        .get(&wait_task_url)
+        .json(&wait_task_request)
+        .send()
+        .await;
+    let response_data: GetStatusResponse = response.unwrap().json().await.unwrap();
+    assert!(!response_data.status.is_finished());
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+#[tokio::test]
+async fn test_large_output_is_streamed_from_disk() {
+    let address = "127.0.0.1";
+    let port = 18090;
+    let output_dir = tempfile::tempdir().unwrap();
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port)
+        .with_grace_period(Duration::from_millis(100))
+        .with_output_store(OutputStore::open(output_dir.path(), 1000, 10).unwrap());
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let client = Client::new();
+    let create_task_url = build_server_url(address, port, "create_task");
+    let get_status_url = build_server_url(address, port, "get_status");
+
+    let request = CreateTaskRequest::new(
+        TaskType::Python,
+        "print('0123456789' * 500, end='')".to_string(),
+        "".to_string(),
+    );
+    let response = client.post(&create_task_url).json(&request).send().await;
+    let id = response
+        .unwrap()
+        .json::<CreateTaskResponse>()
+        .await
+        .unwrap()
+        .id;
+    let status = wait_for_finish(&client, &get_status_url, &id).await;
+    assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(status.result.stdout, "0123456789");
+    let stdout_ref = status.result.stdout_ref.unwrap();
+    assert_eq!(stdout_ref.size, 5000);
+
+    let output_url = format!("http://{}:{}{}", address, port, stdout_ref.url);
+    let response = client.get(&output_url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "0123456789".repeat(500));
+
+    let response = client
+        .get(&output_url)
+        .header("range", "bytes=4995-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.headers()["content-range"], "bytes 4995-4999/5000");
+    assert_eq!(response.text().await.unwrap(), "56789");
+
+    let response = client
+        .get(format!(
+            "http://{}:{}/tasks/{}/output/stderr",
+            address, port, id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .get(format!(
+            "http://{}:{}/tasks/{}/output/stdout?attempt=1",
+            address, port, id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    // Export endpoint is not served unless it is enabled
+    let response = client
+        .get(build_server_url(address, port, "admin/export"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+}
+
+#[tokio::test]
+async fn test_export_and_import_into_fresh_store() {
+    let address = "127.0.0.1";
+    let port = 18091;
+    let output_dir = tempfile::tempdir().unwrap();
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port)
+        .with_grace_period(Duration::from_millis(100))
+        .with_output_store(OutputStore::open(output_dir.path(), 1000, 10).unwrap())
+        .with_admin_export(true);
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let client = Client::new();
+    let create_task_url = build_server_url(address, port, "create_task");
+    let get_status_url = build_server_url(address, port, "get_status");
+
+    let mut ids = Vec::new();
+    for code in ["print('a' * 5000, end='')", "print('small')"] {
+        let request = CreateTaskRequest::new(TaskType::Python, code.to_string(), "".to_string());
+        let response = client.post(&create_task_url).json(&request).send().await;
+        let id = response
+            .unwrap()
+            .json::<CreateTaskResponse>()
+            .await
+            .unwrap()
+            .id;
+        wait_for_finish(&client, &get_status_url, &id).await;
+        ids.push(id);
+    }
+
+    let response = client
+        .get(build_server_url(address, port, "admin/export?format=tar"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let export = response.bytes().await.unwrap();
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
+
+    let import_output_dir = tempfile::tempdir().unwrap();
+    let import_output_store = OutputStore::open(import_output_dir.path(), 1000, 10).unwrap();
+    let records = import_tasks(export.as_ref(), Some(&import_output_store)).unwrap();
+    assert_eq!(records.len(), 2);
+    let sqlite_dir = tempfile::tempdir().unwrap();
+    let sqlite_path = sqlite_dir.path().join("tasks.sqlite");
+    let task_store = SqliteTaskStore::open(&sqlite_path).unwrap();
+    store_tasks(&records, None, &task_store).unwrap();
+    assert!(store_tasks(&records, None, &task_store).is_err());
+
+    let tasksolver_server = TaskSolverServer::new(1, address.to_string(), port)
+        .with_grace_period(Duration::from_millis(100))
+        .with_task_store(Arc::new(task_store), InterruptedTaskPolicy::Error)
+        .with_output_store(import_output_store);
+    let shutdown_handle = tasksolver_server.shutdown_handle();
+    let tasksolver_handle = tasksolver_server.start_tasksolver_server().await.unwrap();
+
+    let status = wait_for_finish(&client, &get_status_url, &ids[1]).await;
+    assert_eq!(status.status, TaskStatusEnum::SUCCESS);
+    assert_eq!(status.result.stdout, "small\n");
+
+    let status = wait_for_finish(&client, &get_status_url, &ids[0]).await;
+    let output_url = format!(
+        "http://{}:{}{}",
+        address,
+        port,
+        status.result.stdout_ref.unwrap().url
+    );
+    let response = client.get(&output_url).send().await.unwrap();
+    assert_eq!(response.text().await.unwrap(), "a".repeat(5000));
+
+    shutdown_handle.shutdown();
+    let _ = tasksolver_handle.await;
 }